@@ -0,0 +1,574 @@
+use crate::{ColorGrid, Field3D, PressureGrid, SimulationConfig, SpatialProfile, Step, TimeProfile, VelocityGrid, get_dimension};
+
+//One of the six walls of the domain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Face{
+    pub dimension: usize,//The dimension orthogonal to the face, 0 for x, 1 for y, 2 for z
+    pub upper: bool,//false for the face with the lowest coordinate, true for the face with the highest coordinate
+}
+
+impl Face{
+    pub const ALL: [Face; 6] = [
+        Face{dimension: 0, upper: false}, Face{dimension: 0, upper: true},
+        Face{dimension: 1, upper: false}, Face{dimension: 1, upper: true},
+        Face{dimension: 2, upper: false}, Face{dimension: 2, upper: true},
+    ];
+    pub fn name(&self) -> &'static str{
+        return ["x_min", "x_max", "y_min", "y_max", "z_min", "z_max"][self.index()];
+    }
+    //The position of the face in Face::ALL
+    pub fn index(&self) -> usize{
+        return 2*self.dimension+self.upper as usize;
+    }
+    //The two dimensions along the face, in increasing order
+    pub fn tangential_dimensions(&self) -> [usize; 2]{
+        return match self.dimension{
+            0 => [1, 2],
+            1 => [0, 2],
+            _ => [0, 1],
+        };
+    }
+}
+
+//What happens to the velocities along a wall, the velocity through the wall is always zero
+#[derive(Clone, Debug, PartialEq)]
+pub enum WallCondition{
+    NoSlip,//The fluid sticks to the wall
+    FreeSlip,//The fluid slides along the wall without friction
+    Moving{velocity: [f32; 3]},//The wall slides along itself with this velocity in m/s and drags the fluid with it, the component orthogonal to the wall is ignored
+}
+
+impl WallCondition{
+    //The parallel velocity just outside of the wall is sign times the velocity just inside of it plus offset
+    fn mirror(&self, parallel_dimension: usize) -> (f32, f32){
+        return match self{
+            WallCondition::NoSlip => (-1.0, 0.0),
+            WallCondition::FreeSlip => (1.0, 0.0),
+            //The average of both sides is the velocity of the wall
+            WallCondition::Moving{velocity} => (-1.0, 2.0*velocity[parallel_dimension]),
+        };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PatchKind{
+    Inflow,//Fluid flows into the domain with the given speed
+    Outflow,//Fluid leaves the domain with the given speed
+    //The kinds below are open boundaries, the speed of the patch is not used and the fluid leaves the domain by itself
+    Pressure{pressure: f32},//The static pressure on the patch is fixed in Pa, the velocity through it follows from the pressure like the velocities inside
+    ZeroGradient,//The velocity through the patch is the same as the velocity just inside of it
+    Convective{speed: Option<f32>},//The flow is carried out of the domain with the given speed in m/s, or with the average speed through the patch when it is None
+}
+
+//A rectangular part of one of the walls where fluid flows in or out of the domain
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundaryPatch{
+    pub name: String,
+    pub face: Face,
+    //The first and the last (inclusive) cell of the patch in the two dimensions along the face, see Face::tangential_dimensions
+    pub min_cells: [usize; 2],
+    pub max_cells: [usize; 2],
+    pub kind: PatchKind,
+    pub speed: f32,//in m/s
+    pub profile: TimeProfile,
+    pub spatial_profile: SpatialProfile,
+}
+
+impl BoundaryPatch{
+    //The number of cells of the patch in both dimensions along the face
+    pub fn cells(&self) -> [usize; 2]{
+        return [self.max_cells[0]-self.min_cells[0]+1, self.max_cells[1]-self.min_cells[1]+1];
+    }
+    //The velocity orthogonal to the face in the given cell of the patch (counted from min_cells) at the given time, positive in the direction of the coordinate axis.
+    //Open boundaries do not have a fixed velocity, this gives zero for them.
+    pub fn orthogonal_velocity(&self, cell: [usize; 2], time: f32) -> f32{
+        let into_domain=if self.face.upper {-1.0} else {1.0};
+        let direction=match self.kind{
+            PatchKind::Inflow => into_domain,
+            PatchKind::Outflow => -into_domain,
+            _ => 0.0,
+        };
+        return direction*self.speed*self.profile.factor(time)*self.spatial_profile.factor(cell, self.cells(), time);
+    }
+    //Whether the fluid decides itself how fast it flows through the patch
+    pub fn is_open(&self) -> bool{
+        return !matches!(self.kind, PatchKind::Inflow | PatchKind::Outflow);
+    }
+    //The velocities through zero gradient and convective patches are scaled so that as much fluid leaves the domain as enters it
+    fn is_mass_balanced(&self) -> bool{
+        return matches!(self.kind, PatchKind::ZeroGradient | PatchKind::Convective{..});
+    }
+}
+
+//Set the wall boundary conditions. last_time_step are the velocities of the previous time step in the order x, y, z, convective patches need them.
+pub(crate) fn set_wall_boundary_conditions(velocity_grid_x: &mut VelocityGrid, velocity_grid_y: &mut VelocityGrid, velocity_grid_z: &mut VelocityGrid, last_time_step: [&VelocityGrid; 3], step: Step, color_grid: &mut ColorGrid, config: &SimulationConfig){
+    //The velocities through patches with a fixed pressure are calculated by the solver, the walls should not overwrite them
+    let kept_x=get_solved_wall_velocities(velocity_grid_x, config);
+    let kept_y=get_solved_wall_velocities(velocity_grid_y, config);
+    let kept_z=get_solved_wall_velocities(velocity_grid_z, config);
+    //A periodic dimension has no walls
+    if !config.periodic[0]{
+        set_boundary_conditions_of_two_parallel_walls(velocity_grid_x, velocity_grid_y, velocity_grid_z, 0.0, config);
+    }
+    if !config.periodic[1]{
+        set_boundary_conditions_of_two_parallel_walls(velocity_grid_y, velocity_grid_x, velocity_grid_z, 0.0, config);
+    }
+    if !config.periodic[2]{
+        set_boundary_conditions_of_two_parallel_walls(velocity_grid_z, velocity_grid_x, velocity_grid_y, 0.0, config);
+    }
+    restore_solved_wall_velocities(velocity_grid_x, kept_x);
+    restore_solved_wall_velocities(velocity_grid_y, kept_y);
+    restore_solved_wall_velocities(velocity_grid_z, kept_z);
+    for patch in config.boundary_patches.iter(){
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        let (orthogonal_velocity_grid, parallel_velocity_grid_a, parallel_velocity_grid_b)=order_by_dimension(velocity_grid_x, velocity_grid_y, velocity_grid_z, patch.face.dimension);
+        if patch.is_open(){
+            set_open_boundary_velocity(orthogonal_velocity_grid, last_time_step[patch.face.dimension], patch, min_coords, max_coords, step.dt, config);
+            color_patch(orthogonal_velocity_grid.dimension, min_coords, max_coords, patch.face.upper, color_grid);
+        }else{
+            create_inflow_or_outflow(orthogonal_velocity_grid, parallel_velocity_grid_a, parallel_velocity_grid_b, min_coords, max_coords, |cell| patch.orthogonal_velocity(cell, step.time), color_grid);
+        }
+    }
+    balance_outflow(velocity_grid_x, velocity_grid_y, velocity_grid_z, config);
+    //The values outside of the open boundaries are set after balancing, so they use the final velocities on the patches
+    for patch in config.boundary_patches.iter().filter(|patch| patch.is_open()){
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        let (orthogonal_velocity_grid, parallel_velocity_grid_a, parallel_velocity_grid_b)=order_by_dimension(velocity_grid_x, velocity_grid_y, velocity_grid_z, patch.face.dimension);
+        set_open_boundary_ghost_values(orthogonal_velocity_grid, parallel_velocity_grid_a, parallel_velocity_grid_b, min_coords, max_coords, patch.face.upper);
+    }
+    wrap_periodic_velocities(velocity_grid_x, velocity_grid_y, velocity_grid_z, config);
+}
+
+//The faces on the walls of this velocity grid whose velocity is calculated by the solver, like the velocities inside the domain. These are the faces of patches with a fixed pressure.
+pub(crate) fn solved_wall_faces(velocity_grid: &VelocityGrid, config: &SimulationConfig) -> Vec<[usize; 3]>{
+    let mut faces=Vec::new();
+    for patch in config.boundary_patches.iter().filter(|patch| patch.face.dimension==velocity_grid.dimension && matches!(patch.kind, PatchKind::Pressure{..})){
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        for x in min_coords[0]..=max_coords[0]{
+            for y in min_coords[1]..=max_coords[1]{
+                for z in min_coords[2]..=max_coords[2]{
+                    faces.push([x, y, z]);
+                }
+            }
+        }
+    }
+    return faces;
+}
+
+//Whether the given face of the domain cell with storage coordinates `cell` lies on a patch with a fixed pressure
+pub(crate) fn is_on_pressure_patch(face: Face, cell: [usize; 3], config: &SimulationConfig) -> bool{
+    let ghost=crate::GHOST_LAYERS;
+    return config.boundary_patches.iter().any(|patch| patch.face==face && matches!(patch.kind, PatchKind::Pressure{..})
+        && face.tangential_dimensions().into_iter().enumerate().all(|(i, dimension)| cell[dimension]>=ghost+patch.min_cells[i] && cell[dimension]<=ghost+patch.max_cells[i]));
+}
+
+fn get_solved_wall_velocities(velocity_grid: &VelocityGrid, config: &SimulationConfig) -> Vec<([usize; 3], f32)>{
+    return solved_wall_faces(velocity_grid, config).into_iter().map(|face| (face, velocity_grid.grid[face])).collect();
+}
+
+fn restore_solved_wall_velocities(velocity_grid: &mut VelocityGrid, velocities: Vec<([usize; 3], f32)>){
+    for (face, velocity) in velocities{
+        velocity_grid.grid[face]=velocity;
+    }
+}
+
+//The patches with a fixed pressure set the pressure in the ghost cells behind them, so that the average of the pressure on both sides of the wall is the fixed pressure
+pub(crate) fn set_pressure_boundary_conditions(pressure_grid: &mut PressureGrid, config: &SimulationConfig){
+    set_value_on_pressure_patches(pressure_grid, |pressure| pressure, config);
+}
+
+//The pressure on the patches with a fixed pressure does not change, so the pressure correction on them is zero
+pub(crate) fn set_pressure_correction_boundary_conditions(pressure_correction: &mut PressureGrid, config: &SimulationConfig){
+    set_value_on_pressure_patches(pressure_correction, |_| 0.0, config);
+}
+
+fn set_value_on_pressure_patches<F: Fn(f32) -> f32>(field: &mut PressureGrid, value: F, config: &SimulationConfig){
+    for patch in config.boundary_patches.iter(){
+        let pressure=match patch.kind{
+            PatchKind::Pressure{pressure} => pressure,
+            _ => {continue}
+        };
+        let dim=get_dimension(patch.face.dimension);
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        //On the lower wall the face has the same coordinates as the cell inside the domain, on the upper wall it has the same coordinates as the ghost cell
+        let (inside, outside)=if patch.face.upper {(dim, [0, 0, 0])} else {([0, 0, 0], dim)};
+        for x in min_coords[0]..=max_coords[0]{
+            for y in min_coords[1]..=max_coords[1]{
+                for z in min_coords[2]..=max_coords[2]{
+                    let inside_value=field[[x-inside[0],y-inside[1],z-inside[2]]];
+                    field[[x-outside[0],y-outside[1],z-outside[2]]]=2.0*value(pressure)-inside_value;
+                }
+            }
+        }
+    }
+}
+
+//Set the velocity through a zero gradient or convective patch, the velocity through a patch with a fixed pressure is calculated by the solver
+fn set_open_boundary_velocity(orthogonal_velocity_grid: &mut VelocityGrid, last_time_step: &VelocityGrid, patch: &BoundaryPatch, min_coords: [usize; 3], max_coords: [usize; 3], dt: f32, config: &SimulationConfig){
+    let dim=get_dimension(orthogonal_velocity_grid.dimension);
+    let outward=if patch.face.upper {1.0} else {-1.0};
+    //The face one step into the domain
+    let inside=|[x, y, z]: [usize; 3]| if patch.face.upper {[x-dim[0], y-dim[1], z-dim[2]]} else {[x+dim[0], y+dim[1], z+dim[2]]};
+    let faces=(min_coords[0]..=max_coords[0]).flat_map(|x| (min_coords[1]..=max_coords[1]).flat_map(move |y| (min_coords[2]..=max_coords[2]).map(move |z| [x, y, z])));
+    match patch.kind{
+        PatchKind::ZeroGradient => {
+            for face in faces{
+                orthogonal_velocity_grid.grid[face]=orthogonal_velocity_grid.grid[inside(face)];
+            }
+        }
+        PatchKind::Convective{speed} => {
+            //du/dt + c du/dn = 0 with an upwind difference in the direction out of the domain, using the velocities of the previous time step
+            let speed=match speed{
+                Some(speed) => speed,
+                None => {
+                    let faces: Vec<[usize; 3]>=faces.clone().collect();
+                    (faces.iter().map(|&face| outward*last_time_step.grid[face]).sum::<f32>()/faces.len() as f32).max(0.0)
+                }
+            };
+            let courant_number=(speed*dt/config.grid_element_scale).min(1.0);
+            for face in faces{
+                let old_velocity=last_time_step.grid[face];
+                orthogonal_velocity_grid.grid[face]=old_velocity-courant_number*(old_velocity-last_time_step.grid[inside(face)]);
+            }
+        }
+        _ => {}
+    }
+}
+
+//Outside of an open boundary all velocities are the same as just inside of it, so the derivatives through the boundary are zero
+fn set_open_boundary_ghost_values(orthogonal_velocity_grid: &mut VelocityGrid, parallel_velocity_grid_a: &mut VelocityGrid, parallel_velocity_grid_b: &mut VelocityGrid, min_orthogonal_coords: [usize; 3], max_orthogonal_coords: [usize; 3], upper: bool){
+    let dimension=orthogonal_velocity_grid.dimension;
+    let dim=get_dimension(dimension);
+    for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
+        for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
+            for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
+                let outside=if upper {[x+dim[0], y+dim[1], z+dim[2]]} else {[x-dim[0], y-dim[1], z-dim[2]]};
+                orthogonal_velocity_grid.grid[outside]=orthogonal_velocity_grid.grid[[x,y,z]];
+            }
+        }
+    }
+    //The parallel velocities just outside of the lower wall are in the ghost layer below the face, on the upper wall they have the same coordinate as the face
+    let (inside, outside)=if upper {(-1, 0)} else {(0, -1)};
+    for parallel_velocity_grid in [parallel_velocity_grid_a, parallel_velocity_grid_b]{
+        let parallel_dim=get_dimension(parallel_velocity_grid.dimension);
+        for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]+parallel_dim[0]{
+            for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]+parallel_dim[1]{
+                for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]+parallel_dim[2]{
+                    let shifted=|offset: isize| [0, 1, 2].map(|i| ([x, y, z][i] as isize+offset*dim[i] as isize) as usize);
+                    parallel_velocity_grid.grid[shifted(outside)]=parallel_velocity_grid.grid[shifted(inside)];
+                }
+            }
+        }
+    }
+}
+
+//Scale the velocities through the zero gradient and convective patches so that the same amount of fluid leaves the domain as enters it through the other patches
+fn balance_outflow(velocity_grid_x: &mut VelocityGrid, velocity_grid_y: &mut VelocityGrid, velocity_grid_z: &mut VelocityGrid, config: &SimulationConfig){
+    if !config.boundary_patches.iter().any(|patch| patch.is_mass_balanced()){
+        return;
+    }
+    let mut inflow=0.0;//The volume flowing in through the other patches per second, divided by the area of a cell face
+    let mut outflow=0.0;//The same for the outflow through the balanced patches
+    let mut balanced_faces=0;
+    for patch in config.boundary_patches.iter(){
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        let velocity_grid=match patch.face.dimension{0 => &*velocity_grid_x, 1 => &*velocity_grid_y, _ => &*velocity_grid_z};
+        let into_domain=if patch.face.upper {-1.0} else {1.0};
+        for x in min_coords[0]..=max_coords[0]{
+            for y in min_coords[1]..=max_coords[1]{
+                for z in min_coords[2]..=max_coords[2]{
+                    if patch.is_mass_balanced(){
+                        outflow-=into_domain*velocity_grid.grid[[x,y,z]];
+                        balanced_faces+=1;
+                    }else{
+                        inflow+=into_domain*velocity_grid.grid[[x,y,z]];
+                    }
+                }
+            }
+        }
+    }
+    //Scaling keeps the shape of the outflow, when nothing flows out yet the outflow is spread evenly over the patches
+    let scale=if outflow>0.0 && inflow>0.0 {Some(inflow/outflow)} else {None};
+    log::trace!(target: "boundary", "Balancing an outflow of {} m/s over {} faces against an inflow of {} m/s, scale {:?}", outflow, balanced_faces, inflow, scale);
+    for patch in config.boundary_patches.iter().filter(|patch| patch.is_mass_balanced()){
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        let (velocity_grid, _, _)=order_by_dimension(velocity_grid_x, velocity_grid_y, velocity_grid_z, patch.face.dimension);
+        let outward=if patch.face.upper {1.0} else {-1.0};
+        for x in min_coords[0]..=max_coords[0]{
+            for y in min_coords[1]..=max_coords[1]{
+                for z in min_coords[2]..=max_coords[2]{
+                    velocity_grid.grid[[x,y,z]]=match scale{
+                        Some(scale) => scale*velocity_grid.grid[[x,y,z]],
+                        None => outward*inflow/balanced_faces as f32,
+                    };
+                }
+            }
+        }
+    }
+}
+
+//Fill the ghost layers of the periodic dimensions with the values from the opposite side of the domain.
+//This is done after the walls, so the ghost values along the walls are wrapped as well.
+pub(crate) fn wrap_periodic_velocities(velocity_grid_x: &mut VelocityGrid, velocity_grid_y: &mut VelocityGrid, velocity_grid_z: &mut VelocityGrid, config: &SimulationConfig){
+    wrap_periodic_field(&mut velocity_grid_x.grid, config);
+    wrap_periodic_field(&mut velocity_grid_y.grid, config);
+    wrap_periodic_field(&mut velocity_grid_z.grid, config);
+}
+
+pub(crate) fn wrap_periodic_field<T: Copy>(field: &mut Field3D<T>, config: &SimulationConfig){
+    for dimension in 0..3{
+        if config.periodic[dimension]{
+            field.wrap_periodic(dimension);
+        }
+    }
+}
+
+//Returns the velocity grid of the given dimension first, followed by the other two
+fn order_by_dimension<'a>(velocity_grid_x: &'a mut VelocityGrid, velocity_grid_y: &'a mut VelocityGrid, velocity_grid_z: &'a mut VelocityGrid, dimension: usize) -> (&'a mut VelocityGrid, &'a mut VelocityGrid, &'a mut VelocityGrid){
+    return match dimension{
+        0 => (velocity_grid_x, velocity_grid_y, velocity_grid_z),
+        1 => (velocity_grid_y, velocity_grid_x, velocity_grid_z),
+        _ => (velocity_grid_z, velocity_grid_x, velocity_grid_y),
+    };
+}
+
+//The coordinates of the faces of a patch in the velocity grid orthogonal to the patch
+fn get_patch_coords(patch: &BoundaryPatch, config: &SimulationConfig) -> ([usize; 3], [usize; 3]){
+    let ghost=crate::GHOST_LAYERS;
+    let mut min_coords=[0, 0, 0];
+    let mut max_coords=[0, 0, 0];
+    let wall_coordinate=if patch.face.upper {ghost+config.pressure_grid_size[patch.face.dimension]} else {ghost};
+    min_coords[patch.face.dimension]=wall_coordinate;
+    max_coords[patch.face.dimension]=wall_coordinate;
+    for (i, dimension) in patch.face.tangential_dimensions().into_iter().enumerate(){
+        min_coords[dimension]=ghost+patch.min_cells[i];
+        max_coords[dimension]=ghost+patch.max_cells[i];
+    }
+    return (min_coords, max_coords);
+}
+
+fn set_boundary_conditions_of_two_parallel_walls(orthogonal_velocity_grid: &mut VelocityGrid, parallel_velocity_grid_a: &mut VelocityGrid, parallel_velocity_grid_b: &mut VelocityGrid, orthogonal_velocity_grid_value: f32, config: &SimulationConfig){
+    let wall_dimension=orthogonal_velocity_grid.dimension;
+    let ghost=orthogonal_velocity_grid.grid.ghost();
+    //The lower wall lies on the first face, the parallel velocities just outside of it are in the ghost layer
+    let (min_orthogonal_coords, max_orthogonal_coords)=get_wall_coords(&orthogonal_velocity_grid.grid, wall_dimension, ghost);
+    let (min_parallel_coords_a, max_parallel_coords_a)=get_wall_coords(&parallel_velocity_grid_a.grid, wall_dimension, ghost-1);
+    let (min_parallel_coords_b, max_parallel_coords_b)=get_wall_coords(&parallel_velocity_grid_b.grid, wall_dimension, ghost-1);
+    //Set boundary conditions for the zero wall
+    let condition=&config.walls[Face{dimension: wall_dimension, upper: false}.index()];
+    set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid, min_orthogonal_coords, max_orthogonal_coords, orthogonal_velocity_grid_value);
+    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_a, min_parallel_coords_a, max_parallel_coords_a, false, wall_dimension, condition);
+    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_b, min_parallel_coords_b, max_parallel_coords_b, false, wall_dimension, condition);
+    //The other wall lies on the last face, the parallel velocities just outside of it are the first ones in the ghost layer on that side
+    let (min_orthogonal_coords, max_orthogonal_coords)=get_wall_coords(&orthogonal_velocity_grid.grid, wall_dimension, ghost+config.pressure_grid_size[wall_dimension]);
+    let (min_parallel_coords_a, max_parallel_coords_a)=get_wall_coords(&parallel_velocity_grid_a.grid, wall_dimension, ghost+config.pressure_grid_size[wall_dimension]);
+    let (min_parallel_coords_b, max_parallel_coords_b)=get_wall_coords(&parallel_velocity_grid_b.grid, wall_dimension, ghost+config.pressure_grid_size[wall_dimension]);
+    //Set boundary conditions for the maximum wall
+    let condition=&config.walls[Face{dimension: wall_dimension, upper: true}.index()];
+    set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid, min_orthogonal_coords, max_orthogonal_coords, orthogonal_velocity_grid_value);
+    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_a, min_parallel_coords_a, max_parallel_coords_a, true, wall_dimension, condition);
+    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_b, min_parallel_coords_b, max_parallel_coords_b, true, wall_dimension, condition);
+}
+
+//The coordinates of a whole layer of a grid with the given coordinate in the wall dimension, ghost layers in the other dimensions included
+fn get_wall_coords(grid: &Field3D, wall_dimension: usize, coordinate: usize) -> ([usize; 3], [usize; 3]){
+    let mut min_coords=[0, 0, 0];
+    let mut max_coords=grid.shape().map(|size| size-1);
+    min_coords[wall_dimension]=coordinate;
+    max_coords[wall_dimension]=coordinate;
+    return (min_coords, max_coords);
+}
+
+
+//Set the orthogonal velocity to a certain value on a wall
+fn set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid: &mut VelocityGrid, min_coords: [usize; 3], max_coords: [usize; 3], value: f32){
+    for x in min_coords[0]..=max_coords[0]{
+        for y in min_coords[1]..=max_coords[1]{
+            for z in min_coords[2]..=max_coords[2]{
+                orthogonal_velocity_grid.grid[[x,y,z]]=value;
+            }
+        }
+    }
+}
+
+//min_orthogonal_coords and max_orthogonal_coords are the coordinates of the faces in the orthogonal velocity grid, they should lie on one of the walls.
+//flow gives the velocity for a cell of the patch, counted from min_orthogonal_coords in the two dimensions along the wall.
+fn create_inflow_or_outflow<F: Fn([usize; 2]) -> f32>(orthogonal_velocity_grid: &mut VelocityGrid, parallel_velocity_grid_a: &mut VelocityGrid, parallel_velocity_grid_b: &mut VelocityGrid, min_orthogonal_coords: [usize;3], max_orthogonal_coords: [usize; 3], flow: F, color_grid: &mut ColorGrid){
+    let dim =get_dimension(orthogonal_velocity_grid.dimension);
+    let wall_is_on_lower_side=min_orthogonal_coords[orthogonal_velocity_grid.dimension]==orthogonal_velocity_grid.grid.ghost();
+    let tangential_dimensions=Face{dimension: orthogonal_velocity_grid.dimension, upper: !wall_is_on_lower_side}.tangential_dimensions();
+    
+    for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
+        for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
+            for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
+                let cell=tangential_dimensions.map(|dimension| [x, y, z][dimension]-min_orthogonal_coords[dimension]);
+                orthogonal_velocity_grid.grid[[x,y,z]]=flow(cell);
+            }
+        }
+    }
+    //For the parallel velocities the size should be one larger in all dimensions, in the orthogonal dimension these are the velocities on both sides of the wall
+    for x in min_orthogonal_coords[0]-dim[0]..=max_orthogonal_coords[0]+1-dim[0]{
+        for y in min_orthogonal_coords[1]-dim[1]..=max_orthogonal_coords[1]+1-dim[1]{
+            for z in min_orthogonal_coords[2]-dim[2]..=max_orthogonal_coords[2]+1-dim[2]{
+                parallel_velocity_grid_a.grid[[x,y,z]]=0.0;
+                parallel_velocity_grid_b.grid[[x,y,z]]=0.0;
+            }
+        }
+    }
+    color_patch(orthogonal_velocity_grid.dimension, min_orthogonal_coords, max_orthogonal_coords, !wall_is_on_lower_side, color_grid);
+}
+
+//Give the inflow and outflow a color. The cell inside the domain next to the face on the lower wall has the same coordinates, on the upper wall it is one lower.
+fn color_patch(dimension: usize, min_orthogonal_coords: [usize;3], max_orthogonal_coords: [usize; 3], upper: bool, color_grid: &mut ColorGrid){
+    let offset=if upper {get_dimension(dimension)} else {[0, 0, 0]};
+    for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
+        for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
+            for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
+                color_grid[[x-offset[0],y-offset[1],z-offset[2]]]=[1.0,0.0,0.0];
+            }
+        }
+    }
+}
+
+//wall_is_on_lower_side=0 means the wall is on the side with lower coordinates seen from the dry side and wall_is_on_lower_side=1 means the wall is on the side with higher coordinates. 
+//orthogonal_dimension is the dimension number(0 for x, 1 for y, 2 for z) of the dimension orthogonal to the wall
+fn set_parallel_boundary_condition_at_wall(parallel_velocity_grid: &mut VelocityGrid, min_coords: [usize; 3], max_coords: [usize; 3], wall_is_on_lower_side: bool, orthogonal_dimension: usize, condition: &WallCondition){
+    let (sign, offset)=condition.mirror(parallel_velocity_grid.dimension);
+    let dim=get_dimension(orthogonal_dimension);
+    let transformation_in_one_dimension=1 - 2 * (wall_is_on_lower_side as isize);// -1 when a lower element is needed, +1 when a higher element is needed
+    let transformation_to_neighbor:[isize; 3]=[(dim[0] as isize) * transformation_in_one_dimension, (dim[1] as isize) * transformation_in_one_dimension, (dim[2] as isize) * transformation_in_one_dimension];// This is the transformation to the neighbor opposite of the wall
+    for x in min_coords[0]..=max_coords[0]{
+        for y in min_coords[1]..=max_coords[1]{
+            for z in min_coords[2]..=max_coords[2]{
+                //For a wall that does not move, the parallel velocity should be the opposite of the parallel velocity on the other side of the wall, so that the average is zero.
+                parallel_velocity_grid.grid[[x,y,z]]=offset+sign*parallel_velocity_grid.grid[[(x as isize + transformation_to_neighbor[0])as usize, (y as isize + transformation_to_neighbor[1]) as usize, (z as isize+transformation_to_neighbor[2]) as usize]];          
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{GHOST_LAYERS, SimulationState, simulation_time_step};
+
+    //A channel along x with a uniform inflow over the whole lower x face and the given patch over the whole upper x face
+    fn channel(outlet: PatchKind) -> SimulationConfig{
+        let config=SimulationConfig{pressure_grid_size: [10, 6, 6], ..SimulationConfig::default()};
+        let patch=|name: &str, upper: bool, kind: PatchKind| BoundaryPatch{name: String::from(name), face: Face{dimension: 0, upper}, min_cells: [0, 0], max_cells: [5, 5], kind, speed: 0.1, profile: TimeProfile::Constant, spatial_profile: SpatialProfile::Uniform};
+        return SimulationConfig{boundary_patches: vec![patch("inflow", false, PatchKind::Inflow), patch("outlet", true, outlet)], ..config};
+    }
+
+    //The shipped scenario with a lid sliding in x over the upper z wall and free slip walls in y
+    fn lid_driven_cavity() -> SimulationConfig{
+        let path=std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenarios/lid_driven_cavity.toml");
+        return crate::scenario::load_scenario(&path).unwrap();
+    }
+
+    //The velocities in x direction through the faces of a patch
+    fn patch_velocities(state: &SimulationState, patch: &BoundaryPatch, config: &SimulationConfig) -> Vec<f32>{
+        let (min_coords, max_coords)=get_patch_coords(patch, config);
+        let mut velocities=Vec::new();
+        for y in min_coords[1]..=max_coords[1]{
+            for z in min_coords[2]..=max_coords[2]{
+                velocities.push(state.velocity_x.grid[[min_coords[0],y,z]]);
+            }
+        }
+        return velocities;
+    }
+
+    #[test]
+    fn zero_gradient_and_convective_outlets_let_out_what_flows_in(){
+        for outlet in [PatchKind::ZeroGradient, PatchKind::Convective{speed: None}, PatchKind::Convective{speed: Some(0.05)}]{
+            let config=channel(outlet);
+            let mut state=SimulationState::new(&config).unwrap();
+            for _ in 0..3{
+                simulation_time_step(&mut state, &config).unwrap();
+                let inflow: f32=patch_velocities(&state, &config.boundary_patches[0], &config).iter().sum();
+                let outflow: f32=patch_velocities(&state, &config.boundary_patches[1], &config).iter().sum();
+                assert!((inflow-36.0*0.1).abs()<1e-5, "inflow {}", inflow);
+                assert!((outflow-inflow).abs()<1e-4*inflow, "{:?}: outflow {} against inflow {}", outlet, outflow, inflow);
+            }
+        }
+    }
+
+    #[test]
+    fn pressure_outlet_holds_its_pressure(){
+        let config=channel(PatchKind::Pressure{pressure: 2.0});
+        let mut state=SimulationState::new(&config).unwrap();
+        for _ in 0..3{
+            simulation_time_step(&mut state, &config).unwrap();
+            //The pressure on the patch is the average of the last cell of the domain and the ghost cell behind it
+            let outside=GHOST_LAYERS+config.pressure_grid_size[0];
+            for y in GHOST_LAYERS..GHOST_LAYERS+6{
+                for z in GHOST_LAYERS..GHOST_LAYERS+6{
+                    let pressure=(state.pressure_grid[[outside-1,y,z]]+state.pressure_grid[[outside,y,z]])/2.0;
+                    assert!((pressure-2.0).abs()<1e-5, "pressure {} at y {} z {}", pressure, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn moving_lid_drags_the_fluid_with_the_wall_velocity(){
+        let config=lid_driven_cavity();
+        let mut state=SimulationState::new(&config).unwrap();
+        simulation_time_step(&mut state, &config).unwrap();
+        let [cells_x, cells_y, cells_z]=config.pressure_grid_size;
+        let lid=GHOST_LAYERS+cells_z;
+        //The faces on the walls in x are excluded, their velocity is zero
+        for x in GHOST_LAYERS+1..GHOST_LAYERS+cells_x{
+            for y in GHOST_LAYERS..GHOST_LAYERS+cells_y{
+                let velocity=(state.velocity_x.grid[[x,y,lid-1]]+state.velocity_x.grid[[x,y,lid]])/2.0;
+                assert!((velocity-0.01).abs()<1e-7, "velocity {} on the lid at x {} y {}", velocity, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn free_slip_ghost_values_are_the_interior_values(){
+        let config=lid_driven_cavity();
+        let mut state=SimulationState::new(&config).unwrap();
+        for _ in 0..2{
+            simulation_time_step(&mut state, &config).unwrap();
+        }
+        let [cells_x, cells_y, cells_z]=config.pressure_grid_size;
+        //The walls in y slide freely, so the velocities in x and z just outside of them are the same as just inside
+        for velocity_grid in [&state.velocity_x, &state.velocity_z]{
+            for x in GHOST_LAYERS..GHOST_LAYERS+cells_x{
+                for z in GHOST_LAYERS..GHOST_LAYERS+cells_z{
+                    assert_eq!(velocity_grid.grid[[x,GHOST_LAYERS-1,z]], velocity_grid.grid[[x,GHOST_LAYERS,z]]);
+                    assert_eq!(velocity_grid.grid[[x,GHOST_LAYERS+cells_y,z]], velocity_grid.grid[[x,GHOST_LAYERS+cells_y-1,z]]);
+                }
+            }
+        }
+        //The first step has dragged the fluid under the lid along, so the flow next to the free slip walls is not zero
+        assert!(state.velocity_x.grid[[GHOST_LAYERS+cells_x/2,GHOST_LAYERS,GHOST_LAYERS+cells_z-1]]>0.0);
+    }
+
+    #[test]
+    fn periodic_channel_is_the_same_along_the_periodic_dimensions(){
+        let path=std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenarios/periodic_channel.toml");
+        let config=crate::scenario::load_scenario(&path).unwrap();
+        let mut state=SimulationState::new(&config).unwrap();
+        for _ in 0..3{
+            simulation_time_step(&mut state, &config).unwrap();
+        }
+        let [cells_x, cells_y, _]=config.pressure_grid_size;
+        for field in [&state.velocity_x.grid, &state.velocity_y.grid, &state.velocity_z.grid, &state.pressure_grid]{
+            let mut wrapped=field.clone();
+            wrap_periodic_field(&mut wrapped, &config);
+            assert_eq!(&wrapped, field, "the ghost layers are not wrapped");
+        }
+        //The force pushes the fluid in x and the plates in z slow it down, nothing changes along x and y
+        for z in state.velocity_x.grid.domain_range(2){
+            let velocity=state.velocity_x.grid[[GHOST_LAYERS,GHOST_LAYERS,z]];
+            assert!(velocity>0.0, "velocity {} at z {}", velocity, z);
+            for x in GHOST_LAYERS..GHOST_LAYERS+cells_x{
+                for y in GHOST_LAYERS..GHOST_LAYERS+cells_y{
+                    assert!((state.velocity_x.grid[[x,y,z]]-velocity).abs()<1e-7, "velocity {} at {:?} against {}", state.velocity_x.grid[[x,y,z]], [x, y, z], velocity);
+                }
+            }
+        }
+    }
+}
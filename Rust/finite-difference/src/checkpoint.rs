@@ -0,0 +1,509 @@
+//Checkpoints of a simulation, so a long run can be continued after a crash. A checkpoint holds everything that changes from one time step to the next:
+//the three staggered velocity grids and the pressure with their ghost layers, the time, the number of the next time step and the tendency of Adams-Bashforth.
+//Everything else is built again from the config, so a restarted simulation calculates the same numbers bit for bit as the one that wrote the checkpoint.
+//
+//The file is little endian: the magic bytes, the format version (u32), the config hash (u64), the time step (i32), the time, the size of the last time step (f32),
+//what limited it (u8), then the velocity grids x, y, z and the pressure grid, each as its number of values (u64) and the values (f32),
+//and last a flag (u8) whether there is an earlier tendency, followed by the size of its time step (f32) and the three tendency grids.
+use std::{fmt, fs, io::{self, Read, Write}, path::{Path, PathBuf}};
+
+use crate::{AdaptiveTimeStep, AdvectionCorrection, BoundaryPatch, ConvectionScheme, DiffusionScheme, Field3D, FluxLimiter, MultigridCycle, Obstacle, PatchKind, Preconditioner, PressureSolver,
+    SimulationConfig, SimulationState, SolverError, SpatialProfile, TimeIntegration, TimeProfile, TimeStepLimit, WallCondition};
+
+const MAGIC: &[u8; 8] = b"PWSCHKPT";
+//The version of the file format, increase it when the layout or the config hash changes
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum CheckpointError{
+    Io{path: PathBuf, error: io::Error},//The file could not be written or read, also when it ends too early
+    NotACheckpoint,//The file does not start with the magic bytes
+    UnsupportedVersion(u32),//The file was written in another version of the format
+    ConfigMismatch{checkpoint: u64, config: u64},//The checkpoint was written by a simulation with another config, the values are the two config hashes
+    Layout(String),//The grids in the file do not fit the grid size of the config, or the file has more data than expected
+    Solver(SolverError),//The state for the config could not be created
+    Unhashable(String),//The config has a closure, so there is no way to tell whether a checkpoint was written with the same config
+}
+
+impl fmt::Display for CheckpointError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return match self{
+            CheckpointError::Io{path, error} => write!(f, "Failed to access {}: {}", path.display(), error),
+            CheckpointError::NotACheckpoint => write!(f, "The file is not a checkpoint"),
+            CheckpointError::UnsupportedVersion(version) => write!(f, "The checkpoint has format version {}, only version {} can be read", version, FORMAT_VERSION),
+            CheckpointError::ConfigMismatch{checkpoint, config} => write!(f, "The checkpoint was written with another configuration (hash {:016x}, the current one is {:016x})", checkpoint, config),
+            CheckpointError::Layout(message) => write!(f, "{}", message),
+            CheckpointError::Solver(error) => write!(f, "{}", error),
+            CheckpointError::Unhashable(what) => write!(f, "The configuration can not be checkpointed, {}", what),
+        };
+    }
+}
+
+impl std::error::Error for CheckpointError{}
+
+//A hash of everything in the config that changes the results, a checkpoint is only read with a config that has the same hash.
+//Every parameter is encoded in a fixed little endian layout and hashed with FNV-1a, so the hash is the same on every machine and in every version of Rust.
+//The number of threads, the stencil kernels and the names of the patches are left out, the results do not depend on them.
+//A config with a spatial profile function or a custom solver can not be hashed: two closures can not be compared, so they could calculate anything.
+pub fn config_hash(config: &SimulationConfig) -> Result<u64, CheckpointError>{
+    //No .. here, so a new parameter does not compile until it is part of the hash
+    let SimulationConfig{grid_element_scale, time_step_size, adaptive_time_step, density, external_force, viscosity, atmospheric_pressure, max_iterations_per_time_frame, relaxation, allowed_error,
+        pressure_solver, pressure_grid_size, boundary_patches, walls, periodic, initial_velocity, obstacles, threads: _, stencil_kernels: _, convection_scheme, diffusion_scheme,
+        diffusion_solver, diffusion_tolerance, time_integration}=config;
+    let mut hash=ConfigHasher::new();
+    hash.f32s(&[*grid_element_scale, *time_step_size]);
+    match adaptive_time_step{
+        Some(AdaptiveTimeStep{cfl_number, diffusion_number, min_step_size, max_step_size}) => {
+            hash.u8(1);
+            hash.f32s(&[*cfl_number, *diffusion_number, *min_step_size, *max_step_size]);
+        }
+        None => hash.u8(0),
+    }
+    hash.f32(*density);
+    hash.f32s(external_force);
+    hash.f32s(&[*viscosity, *atmospheric_pressure]);
+    hash.bytes(&max_iterations_per_time_frame.to_le_bytes());
+    hash.f32s(&[*relaxation, *allowed_error]);
+    hash_solver(&mut hash, pressure_solver, "pressure_solver")?;
+    hash.usizes(pressure_grid_size);
+    hash.usize(boundary_patches.len());
+    for patch in boundary_patches.iter(){
+        hash_patch(&mut hash, patch)?;
+    }
+    for wall in walls.iter(){
+        match wall{
+            WallCondition::NoSlip => hash.u8(0),
+            WallCondition::FreeSlip => hash.u8(1),
+            WallCondition::Moving{velocity} => {
+                hash.u8(2);
+                hash.f32s(velocity);
+            }
+        }
+    }
+    for periodic in periodic.iter(){
+        hash.u8(*periodic as u8);
+    }
+    hash.f32s(initial_velocity);
+    hash.usize(obstacles.len());
+    for obstacle in obstacles.iter(){
+        hash_obstacle(&mut hash, obstacle);
+    }
+    match convection_scheme{
+        ConvectionScheme::Central => hash.u8(0),
+        ConvectionScheme::Upwind => hash.u8(1),
+        ConvectionScheme::Hybrid => hash.u8(2),
+        ConvectionScheme::Quick => hash.u8(3),
+        ConvectionScheme::Tvd{limiter} => {
+            hash.u8(4);
+            hash.u8(match limiter{FluxLimiter::Minmod => 0, FluxLimiter::VanLeer => 1, FluxLimiter::Superbee => 2});
+        }
+        ConvectionScheme::SemiLagrangian{correction} => {
+            hash.u8(5);
+            hash.u8(match correction{AdvectionCorrection::None => 0, AdvectionCorrection::MacCormack => 1, AdvectionCorrection::Bfecc => 2});
+        }
+    }
+    hash.u8(match diffusion_scheme{DiffusionScheme::Explicit => 0, DiffusionScheme::Implicit => 1, DiffusionScheme::CrankNicolson => 2});
+    hash_solver(&mut hash, diffusion_solver, "diffusion_solver")?;
+    hash.f32(*diffusion_tolerance);
+    hash.u8(match time_integration{TimeIntegration::ForwardEuler => 0, TimeIntegration::Heun => 1, TimeIntegration::SspRk3 => 2, TimeIntegration::AdamsBashforth => 3});
+    return Ok(hash.finish());
+}
+
+fn hash_solver(hash: &mut ConfigHasher, solver: &PressureSolver, field: &str) -> Result<(), CheckpointError>{
+    match solver{
+        PressureSolver::Legacy => hash.u8(0),
+        PressureSolver::Jacobi => hash.u8(1),
+        PressureSolver::RedBlackGaussSeidel => hash.u8(2),
+        PressureSolver::Sor{omega} => {
+            hash.u8(3);
+            hash.f32(*omega);
+        }
+        PressureSolver::ConjugateGradient{preconditioner} => {
+            hash.u8(4);
+            hash.u8(match preconditioner{Preconditioner::Jacobi => 0, Preconditioner::IncompleteCholesky => 1});
+        }
+        PressureSolver::Multigrid{cycle, smoothing_steps} => {
+            hash.u8(5);
+            hash.u8(match cycle{MultigridCycle::V => 0, MultigridCycle::W => 1});
+            hash.usize(*smoothing_steps);
+        }
+        PressureSolver::Custom(_) => return Err(CheckpointError::Unhashable(format!("the {} is a custom solver", field))),
+    }
+    return Ok(());
+}
+
+fn hash_patch(hash: &mut ConfigHasher, patch: &BoundaryPatch) -> Result<(), CheckpointError>{
+    let BoundaryPatch{name, face, min_cells, max_cells, kind, speed, profile, spatial_profile}=patch;
+    hash.usize(face.index());
+    hash.usizes(min_cells);
+    hash.usizes(max_cells);
+    match kind{
+        PatchKind::Inflow => hash.u8(0),
+        PatchKind::Outflow => hash.u8(1),
+        PatchKind::Pressure{pressure} => {
+            hash.u8(2);
+            hash.f32(*pressure);
+        }
+        PatchKind::ZeroGradient => hash.u8(3),
+        PatchKind::Convective{speed} => {
+            hash.u8(4);
+            hash_option(hash, *speed);
+        }
+    }
+    hash.f32(*speed);
+    match profile{
+        TimeProfile::Constant => hash.u8(0),
+        TimeProfile::Sigmoid{midpoint, width} => {
+            hash.u8(1);
+            hash.f32s(&[*midpoint, *width]);
+        }
+        TimeProfile::Ramp{start, duration} => {
+            hash.u8(2);
+            hash.f32s(&[*start, *duration]);
+        }
+        TimeProfile::Sinusoid{mean, amplitude, frequency, phase} => {
+            hash.u8(3);
+            hash.f32s(&[*mean, *amplitude, *frequency, *phase]);
+        }
+        TimeProfile::Table{times, factors} => {
+            hash.u8(4);
+            hash.f32s(times);
+            hash.f32s(factors);
+        }
+    }
+    match spatial_profile{
+        SpatialProfile::Uniform => hash.u8(0),
+        SpatialProfile::Parabolic{across} => {
+            hash.u8(1);
+            hash_option(hash, across.map(|across| across as f32));
+        }
+        SpatialProfile::PowerLaw{exponent, across} => {
+            hash.u8(2);
+            hash.f32(*exponent);
+            hash_option(hash, across.map(|across| across as f32));
+        }
+        SpatialProfile::Table{values} => {
+            hash.u8(3);
+            hash.usize(values.len());
+            for row in values.iter(){
+                hash.f32s(row);
+            }
+        }
+        SpatialProfile::Function(_) => return Err(CheckpointError::Unhashable(format!("patch {} has a spatial profile function", name))),
+    }
+    return Ok(());
+}
+
+fn hash_obstacle(hash: &mut ConfigHasher, obstacle: &Obstacle){
+    match obstacle{
+        Obstacle::Box{min, max} => {
+            hash.u8(0);
+            hash.f32s(min);
+            hash.f32s(max);
+        }
+        Obstacle::Cylinder{center, radius, axis, length} => {
+            hash.u8(1);
+            hash.f32s(center);
+            hash.f32(*radius);
+            hash.usize(*axis);
+            hash_option(hash, *length);
+        }
+        Obstacle::Sphere{center, radius} => {
+            hash.u8(2);
+            hash.f32s(center);
+            hash.f32(*radius);
+        }
+        Obstacle::Mesh(mesh) => {
+            hash.u8(3);
+            hash.usize(mesh.triangles.len());
+            for triangle in mesh.triangles.iter(){
+                for corner in triangle.iter(){
+                    hash.f32s(corner);
+                }
+            }
+        }
+    }
+}
+
+fn hash_option(hash: &mut ConfigHasher, value: Option<f32>){
+    match value{
+        Some(value) => {
+            hash.u8(1);
+            hash.f32(value);
+        }
+        None => hash.u8(0),
+    }
+}
+
+//FNV-1a over the bytes of the encoded config. Lists are preceded by their length, so two different configs never give the same bytes.
+struct ConfigHasher(u64);
+
+impl ConfigHasher{
+    fn new() -> Self{
+        return Self(0xcbf29ce484222325);
+    }
+    fn bytes(&mut self, bytes: &[u8]){
+        for &byte in bytes{
+            self.0^=byte as u64;
+            self.0=self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn u8(&mut self, value: u8){
+        self.bytes(&[value]);
+    }
+    fn usize(&mut self, value: usize){
+        self.bytes(&(value as u64).to_le_bytes());
+    }
+    fn usizes(&mut self, values: &[usize]){
+        self.usize(values.len());
+        for &value in values{
+            self.usize(value);
+        }
+    }
+    fn f32(&mut self, value: f32){
+        self.bytes(&value.to_le_bytes());
+    }
+    fn f32s(&mut self, values: &[f32]){
+        self.usize(values.len());
+        for &value in values{
+            self.f32(value);
+        }
+    }
+    fn finish(&self) -> u64{
+        return self.0;
+    }
+}
+
+//Write the state to path. The data is first written to a file next to it which then replaces path, so a crash while writing keeps the last checkpoint.
+pub fn write_checkpoint(state: &SimulationState, config: &SimulationConfig, path: &Path) -> Result<(), CheckpointError>{
+    let hash=config_hash(config)?;
+    let mut data=Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    data.extend_from_slice(&hash.to_le_bytes());
+    data.extend_from_slice(&state.time_step.to_le_bytes());
+    data.extend_from_slice(&state.time.to_le_bytes());
+    data.extend_from_slice(&state.time_step_size.to_le_bytes());
+    data.push(limit_code(state.time_step_limit));
+    for field in [&state.velocity_x.grid, &state.velocity_y.grid, &state.velocity_z.grid, &state.pressure_grid]{
+        write_field(&mut data, field);
+    }
+    match &state.previous_tendency{
+        Some((tendency, time_step_size)) => {
+            data.push(1);
+            data.extend_from_slice(&time_step_size.to_le_bytes());
+            for field in tendency.iter(){
+                write_field(&mut data, field);
+            }
+        }
+        None => {data.push(0);}
+    }
+    let mut temporary=path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary=PathBuf::from(temporary);
+    let result=fs::File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(&data)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+    if let Err(error)=result{
+        //The last complete checkpoint at path stays, only the half written file is removed
+        let _=fs::remove_file(&temporary);
+        return Err(CheckpointError::Io{path: path.to_path_buf(), error});
+    }
+    return Ok(());
+}
+
+//A new state for config with the velocities, pressure and time of the checkpoint at path. The config has to be the one the checkpoint was written with.
+pub fn read_checkpoint(path: &Path, config: &SimulationConfig) -> Result<SimulationState, CheckpointError>{
+    let mut data=Vec::new();
+    let io_error=|error| CheckpointError::Io{path: path.to_path_buf(), error};
+    fs::File::open(path).and_then(|mut file| file.read_to_end(&mut data)).map_err(io_error)?;
+    let mut reader=Reader{data: &data, path};
+    if reader.bytes(MAGIC.len())? != MAGIC{
+        return Err(CheckpointError::NotACheckpoint);
+    }
+    let version=u32::from_le_bytes(reader.array()?);
+    if version!=FORMAT_VERSION{
+        return Err(CheckpointError::UnsupportedVersion(version));
+    }
+    let checkpoint_hash=u64::from_le_bytes(reader.array()?);
+    let hash=config_hash(config)?;
+    if checkpoint_hash!=hash{
+        return Err(CheckpointError::ConfigMismatch{checkpoint: checkpoint_hash, config: hash});
+    }
+    let mut state=SimulationState::new(config).map_err(CheckpointError::Solver)?;
+    state.time_step=i32::from_le_bytes(reader.array()?);
+    state.time=f32::from_le_bytes(reader.array()?);
+    state.time_step_size=f32::from_le_bytes(reader.array()?);
+    state.time_step_limit=limit_from_code(reader.array::<1>()?[0])?;
+    reader.field(&mut state.velocity_x.grid, "x velocity")?;
+    reader.field(&mut state.velocity_y.grid, "y velocity")?;
+    reader.field(&mut state.velocity_z.grid, "z velocity")?;
+    reader.field(&mut state.pressure_grid, "pressure")?;
+    if reader.array::<1>()?[0]==1{
+        let time_step_size=f32::from_le_bytes(reader.array()?);
+        let mut tendency=[state.velocity_x.grid.clone(), state.velocity_y.grid.clone(), state.velocity_z.grid.clone()];
+        for (field, name) in tendency.iter_mut().zip(["x tendency", "y tendency", "z tendency"]){
+            reader.field(field, name)?;
+        }
+        state.previous_tendency=Some((tendency, time_step_size));
+    }
+    if !reader.data.is_empty(){
+        return Err(CheckpointError::Layout(format!("The checkpoint has {} bytes after the end of the state", reader.data.len())));
+    }
+    return Ok(state);
+}
+
+fn write_field(data: &mut Vec<u8>, field: &Field3D){
+    data.extend_from_slice(&(field.as_slice().len() as u64).to_le_bytes());
+    for value in field.as_slice(){
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn limit_code(limit: TimeStepLimit) -> u8{
+    return match limit{
+        TimeStepLimit::Fixed => 0,
+        TimeStepLimit::Convection => 1,
+        TimeStepLimit::Diffusion => 2,
+        TimeStepLimit::Minimum => 3,
+        TimeStepLimit::Maximum => 4,
+    };
+}
+
+fn limit_from_code(code: u8) -> Result<TimeStepLimit, CheckpointError>{
+    return match code{
+        0 => Ok(TimeStepLimit::Fixed),
+        1 => Ok(TimeStepLimit::Convection),
+        2 => Ok(TimeStepLimit::Diffusion),
+        3 => Ok(TimeStepLimit::Minimum),
+        4 => Ok(TimeStepLimit::Maximum),
+        _ => Err(CheckpointError::Layout(format!("Unknown time step limit {}", code))),
+    };
+}
+
+//The part of the file that has not been read yet
+struct Reader<'a>{
+    data: &'a [u8],
+    path: &'a Path,
+}
+
+impl<'a> Reader<'a>{
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], CheckpointError>{
+        if self.data.len()<count{
+            return Err(CheckpointError::Io{path: self.path.to_path_buf(), error: io::Error::from(io::ErrorKind::UnexpectedEof)});
+        }
+        let (bytes, rest)=self.data.split_at(count);
+        self.data=rest;
+        return Ok(bytes);
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CheckpointError>{
+        return Ok(self.bytes(N)?.try_into().unwrap());
+    }
+    //Read the values of a field into a field of the layout of the config
+    fn field(&mut self, field: &mut Field3D, name: &str) -> Result<(), CheckpointError>{
+        let length=u64::from_le_bytes(self.array()?);
+        if length!=field.as_slice().len() as u64{
+            return Err(CheckpointError::Layout(format!("The {} grid of the checkpoint has {} values, the grid of the config has {}", name, length, field.as_slice().len())));
+        }
+        let bytes=self.bytes(4*field.as_slice().len())?;
+        for (value, bytes) in field.as_mut_slice().iter_mut().zip(bytes.chunks_exact(4)){
+            *value=f32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::sync::Arc;
+    use crate::{CustomSolver, SorSolver, resume_headless, run_headless, tests::{bits, small_config}};
+
+    //A file in the temporary directory that is removed at the end of the test
+    struct TemporaryFile(PathBuf);
+
+    impl TemporaryFile{
+        fn new(name: &str) -> Self{
+            return Self(std::env::temp_dir().join(format!("pws_{}_{}.chk", name, std::process::id())));
+        }
+    }
+
+    impl Drop for TemporaryFile{
+        fn drop(&mut self){
+            let _=fs::remove_file(&self.0);
+        }
+    }
+
+    //Adams-Bashforth with an adaptive time step, so the tendency and the size of the last step have to be restored as well
+    fn config() -> SimulationConfig{
+        return SimulationConfig{
+            time_integration: TimeIntegration::AdamsBashforth,
+            adaptive_time_step: Some(AdaptiveTimeStep{cfl_number: 0.5, diffusion_number: 0.15, min_step_size: 0.001, max_step_size: 0.05}),
+            ..small_config()
+        };
+    }
+
+    fn written_checkpoint(name: &str, config: &SimulationConfig) -> TemporaryFile{
+        let file=TemporaryFile::new(name);
+        let state=run_headless(config, 2, |_, _| false).unwrap();
+        write_checkpoint(&state, config, &file.0).unwrap();
+        return file;
+    }
+
+    fn change_bytes(path: &Path, start: usize, bytes: &[u8]){
+        let mut data=fs::read(path).unwrap();
+        data[start..start+bytes.len()].copy_from_slice(bytes);
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn restart_continues_bit_for_bit(){
+        let config=config();
+        let uninterrupted=run_headless(&config, 4, |_, _| false).unwrap();
+        let file=written_checkpoint("restart", &config);
+        let restarted=resume_headless(read_checkpoint(&file.0, &config).unwrap(), &config, 2, |_, _| false).unwrap();
+        assert_eq!(restarted.time_step, uninterrupted.time_step);
+        assert_eq!(restarted.time.to_bits(), uninterrupted.time.to_bits());
+        assert!(bits(&restarted)==bits(&uninterrupted));
+    }
+
+    #[test]
+    fn rejects_other_files_versions_and_configs(){
+        let config=config();
+        let file=written_checkpoint("rejects", &config);
+        //The number of threads does not change the results
+        assert!(read_checkpoint(&file.0, &SimulationConfig{threads: 2, ..config.clone()}).is_ok());
+        let other=SimulationConfig{density: 1000.0, ..config.clone()};
+        assert!(matches!(read_checkpoint(&file.0, &other), Err(CheckpointError::ConfigMismatch{..})));
+        change_bytes(&file.0, MAGIC.len(), &99u32.to_le_bytes());
+        assert!(matches!(read_checkpoint(&file.0, &config), Err(CheckpointError::UnsupportedVersion(99))));
+        change_bytes(&file.0, 0, b"NOTACHKP");
+        assert!(matches!(read_checkpoint(&file.0, &config), Err(CheckpointError::NotACheckpoint)));
+    }
+
+    #[test]
+    fn refuses_config_with_closure(){
+        let config=SimulationConfig{pressure_solver: PressureSolver::Custom(CustomSolver(Arc::new(|| Box::new(SorSolver::new(1.5))))), ..small_config()};
+        let state=SimulationState::new(&config).unwrap();
+        let file=TemporaryFile::new("closure");
+        assert!(matches!(write_checkpoint(&state, &config, &file.0), Err(CheckpointError::Unhashable(_))));
+        assert!(!file.0.exists());
+    }
+
+    #[test]
+    fn failed_write_removes_temporary_file(){
+        let config=small_config();
+        let state=SimulationState::new(&config).unwrap();
+        //A directory can not be replaced by a file
+        let directory=TemporaryFile::new("directory");
+        fs::create_dir_all(&directory.0).unwrap();
+        assert!(matches!(write_checkpoint(&state, &config, &directory.0), Err(CheckpointError::Io{..})));
+        let mut temporary=directory.0.as_os_str().to_owned();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+        fs::remove_dir(&directory.0).unwrap();
+    }
+}
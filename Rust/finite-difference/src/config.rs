@@ -0,0 +1,263 @@
+use crate::boundary::{BoundaryPatch, Face, PatchKind, WallCondition};
+use crate::convection::ConvectionScheme;
+use crate::diffusion::DiffusionScheme;
+use crate::error::SolverError;
+use crate::profile::{SpatialProfile, TimeProfile};
+use crate::solver::{Preconditioner, PressureSolver};
+use crate::stencil::StencilKernels;
+use crate::time_integration::TimeIntegration;
+use crate::time_step::AdaptiveTimeStep;
+use crate::obstacle::Obstacle;
+
+//Pressure is measured in Pascal, because it is the standard SI unit for pressure.
+
+//All physical and numerical parameters of a simulation. Use SimulationConfig::default() for the values we used to have as constants.
+#[derive(Clone, Debug)]
+pub struct SimulationConfig{
+    pub grid_element_scale: f32,//The size of a grid element in meters(denoted in equations as delta x)
+    pub time_step_size: f32,//The size of a time step size in seconds
+    pub adaptive_time_step: Option<AdaptiveTimeStep>,//Choose the size of every time step from the flow instead of always using time_step_size
+    pub density: f32,//Density of the liquid in kg/m^{3}.
+    pub external_force: [f32; 3],//Gravity in N
+    pub viscosity: f32,//Viscosity in Pa*s.
+    pub atmospheric_pressure: f32,//Atmospheric pressure in Pa
+    pub max_iterations_per_time_frame: i32,//This sets a maximum so the computer can not get in an infinite loop.
+    pub relaxation: f32,//Pressure correction is often underestimated, this factor should be between 1.4 and 1.8. Only used by the legacy pressure solver.
+    pub allowed_error: f32,//The largest divergence in 1/s that is accepted at the end of a time step
+    pub pressure_solver: PressureSolver,
+    pub pressure_grid_size: [usize; 3],//Grid size(e.g. number of elements in each dimension) x,y,z
+    pub boundary_patches: Vec<BoundaryPatch>,//The places where fluid flows in or out, all other parts of the walls are closed
+    pub walls: [WallCondition; 6],//The condition on each wall, in the order of Face::ALL
+    pub periodic: [bool; 3],//Whether the domain wraps around in x, y and z. The walls of a periodic dimension are ignored, what leaves the domain on one side comes back on the other.
+    pub initial_velocity: [f32; 3],//The velocity of the fluid at the start of the simulation in m/s
+    pub obstacles: Vec<Obstacle>,//Solid objects inside the domain, the fluid flows around them
+    pub threads: usize,//The number of threads that calculate a time step, 0 for one thread per core
+    pub stencil_kernels: StencilKernels,
+    pub convection_scheme: ConvectionScheme,//How the derivatives of the convection term are calculated
+    pub diffusion_scheme: DiffusionScheme,
+    pub diffusion_solver: PressureSolver,//The method of the equations of implicit diffusion, any method but the legacy one
+    pub diffusion_tolerance: f32,//The largest residual of the equations of implicit diffusion in m/s
+    pub time_integration: TimeIntegration,//How the explicit terms of the prediction are integrated over a time step
+}
+
+impl Default for SimulationConfig{
+    fn default() -> Self{
+        return Self{
+            grid_element_scale: 0.05,
+            time_step_size: 0.05,
+            adaptive_time_step: None,
+            density: 997.0,//We simulate water.
+            external_force: [0.0, 0.0, 0.0],
+            viscosity: 0.001,
+            atmospheric_pressure: 101325.0,//101 325
+            max_iterations_per_time_frame: 30000,
+            relaxation: 1.0,
+            allowed_error: 0.005,
+            pressure_solver: PressureSolver::Sor{omega: 1.7},
+            pressure_grid_size: [50, 50, 50],
+            boundary_patches: vec![
+                BoundaryPatch{name: String::from("outflow"), face: Face{dimension: 0, upper: false}, min_cells: [21, 21], max_cells: [27, 27], kind: PatchKind::Outflow, speed: 0.1, profile: TimeProfile::Constant, spatial_profile: SpatialProfile::Uniform},
+                BoundaryPatch{name: String::from("inflow"), face: Face{dimension: 2, upper: false}, min_cells: [21, 21], max_cells: [27, 27], kind: PatchKind::Inflow, speed: 0.1, profile: TimeProfile::Constant, spatial_profile: SpatialProfile::Uniform},
+            ],
+            walls: [WallCondition::NoSlip, WallCondition::NoSlip, WallCondition::NoSlip, WallCondition::NoSlip, WallCondition::NoSlip, WallCondition::NoSlip],
+            periodic: [false, false, false],
+            initial_velocity: [0.0, 0.0, 0.0],
+            obstacles: Vec::new(),
+            threads: 0,
+            stencil_kernels: StencilKernels::Vectorized,
+            convection_scheme: ConvectionScheme::Central,
+            diffusion_scheme: DiffusionScheme::Explicit,
+            diffusion_solver: PressureSolver::ConjugateGradient{preconditioner: Preconditioner::Jacobi},
+            diffusion_tolerance: 0.000001,
+            time_integration: TimeIntegration::ForwardEuler,
+        }
+    }
+}
+
+impl SimulationConfig{
+    //Check the values that would make the simulation panic or give NaN. Scenario files are checked with this too, together with what only a file can get wrong.
+    pub fn validate(&self) -> Result<(), SolverError>{
+        let invalid=|field: &'static str, message: String| Err(SolverError::InvalidConfig{field, message});
+        if self.pressure_grid_size.iter().any(|&cells| cells<3){
+            return invalid("pressure_grid_size", format!("the domain needs at least 3 cells in every dimension, got {:?}", self.pressure_grid_size));
+        }
+        for (field, value) in [("grid_element_scale", self.grid_element_scale), ("time_step_size", self.time_step_size), ("density", self.density), ("allowed_error", self.allowed_error), ("relaxation", self.relaxation), ("diffusion_tolerance", self.diffusion_tolerance)]{
+            if !(value.is_finite() && value>0.0){
+                return invalid(field, format!("must be larger than zero, got {}", value));
+            }
+        }
+        if !(self.viscosity.is_finite() && self.viscosity>=0.0){
+            return invalid("viscosity", format!("can not be negative, got {}", self.viscosity));
+        }
+        if self.max_iterations_per_time_frame<1{
+            return invalid("max_iterations_per_time_frame", format!("must be at least 1, got {}", self.max_iterations_per_time_frame));
+        }
+        if let Some(adaptive)=self.adaptive_time_step{
+            if !(adaptive.min_step_size>0.0 && adaptive.min_step_size<=adaptive.max_step_size){
+                return invalid("adaptive_time_step", format!("the step sizes have to satisfy 0 < {} <= {}", adaptive.min_step_size, adaptive.max_step_size));
+            }
+            if !(adaptive.cfl_number>0.0 && adaptive.cfl_number<1.0){
+                return invalid("adaptive_time_step", format!("the CFL number has to be between 0 and 1, got {}", adaptive.cfl_number));
+            }
+            //The explicit diffusion of three dimensions is unstable above 1/6
+            if !(adaptive.diffusion_number>0.0 && adaptive.diffusion_number<=1.0/6.0){
+                return invalid("adaptive_time_step", format!("the diffusion number has to be larger than 0 and at most 1/6, got {}", adaptive.diffusion_number));
+            }
+        }
+        for patch in self.boundary_patches.iter(){
+            if let Err(message)=self.validate_patch(patch){
+                return invalid("boundary_patches", format!("patch {}: {}", patch.name, message));
+            }
+        }
+        validate_solver(&self.pressure_solver).or_else(|message| invalid("pressure_solver", message))?;
+        validate_solver(&self.diffusion_solver).or_else(|message| invalid("diffusion_solver", message))?;
+        if self.diffusion_scheme!=DiffusionScheme::Explicit && self.diffusion_solver==PressureSolver::Legacy{
+            return invalid("diffusion_solver", String::from("can not be the legacy pressure solver"));
+        }
+        return Ok(());
+    }
+
+    fn validate_patch(&self, patch: &BoundaryPatch) -> Result<(), String>{
+        if self.periodic[patch.face.dimension]{
+            return Err(format!("lies on the periodic face {}", patch.face.name()));
+        }
+        let tangential_dimensions=patch.face.tangential_dimensions();
+        let face_cells=tangential_dimensions.map(|dimension| self.pressure_grid_size[dimension]);
+        for j in 0..2{
+            if patch.min_cells[j]>patch.max_cells[j]{
+                return Err(format!("min_cells {:?} is larger than max_cells {:?}", patch.min_cells, patch.max_cells));
+            }
+            if patch.max_cells[j]>=face_cells[j]{
+                return Err(format!("max_cells {:?} lies outside of the {} face, which has {} x {} cells", patch.max_cells, patch.face.name(), face_cells[0], face_cells[1]));
+            }
+        }
+        match &patch.profile{
+            TimeProfile::Sigmoid{width, ..} if width.is_nan() || *width<=0.0 => {return Err(format!("the width of the sigmoid profile has to be larger than zero, got {}", width));}
+            TimeProfile::Ramp{duration, ..} if duration.is_nan() || *duration<=0.0 => {return Err(format!("the duration of the ramp profile has to be larger than zero, got {}", duration));}
+            TimeProfile::Table{times, factors} => {
+                if times.is_empty() || times.len()!=factors.len(){
+                    return Err(format!("the table profile needs the same number of times and factors and at least one of them, got {} and {}", times.len(), factors.len()));
+                }
+                if times.windows(2).any(|pair| pair[0].partial_cmp(&pair[1])!=Some(std::cmp::Ordering::Less)){
+                    return Err(String::from("the times of the table profile have to be increasing"));
+                }
+            }
+            _ => {}
+        }
+        let cells=[patch.max_cells[0]-patch.min_cells[0]+1, patch.max_cells[1]-patch.min_cells[1]+1];
+        match &patch.spatial_profile{
+            SpatialProfile::PowerLaw{exponent, ..} if exponent.is_nan() || *exponent<=0.0 => {return Err(format!("the exponent of the power law profile has to be larger than zero, got {}", exponent));}
+            SpatialProfile::Parabolic{across: Some(across)} | SpatialProfile::PowerLaw{across: Some(across), ..} if *across>1 => {return Err(format!("across has to be 0 or 1, got {}", across));}
+            SpatialProfile::Table{values} if values.len()!=cells[0] || values.iter().any(|row| row.len()!=cells[1]) => {
+                return Err(format!("the patch has {} x {} cells, so the table profile needs {} rows with {} values each", cells[0], cells[1], cells[0], cells[1]));
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+}
+
+fn validate_solver(solver: &PressureSolver) -> Result<(), String>{
+    return match solver{
+        //SOR only converges for omega strictly between 0 and 2
+        PressureSolver::Sor{omega} if !(*omega>0.0 && *omega<2.0) => Err(format!("omega has to be strictly between 0 and 2, got {}", omega)),
+        PressureSolver::Multigrid{smoothing_steps: 0, ..} => Err(String::from("multigrid needs at least 1 smoothing step")),
+        _ => Ok(()),
+    };
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{AdaptiveTimeStep, MultigridCycle};
+
+    //The field of the error of an invalid config
+    fn rejected_field(config: &SimulationConfig) -> &'static str{
+        return match config.validate(){
+            Err(SolverError::InvalidConfig{field, ..}) => field,
+            result => panic!("expected an invalid config, got {:?}", result),
+        };
+    }
+
+    fn with_patch(change: impl FnOnce(&mut BoundaryPatch)) -> SimulationConfig{
+        let mut config=SimulationConfig::default();
+        change(&mut config.boundary_patches[0]);
+        return config;
+    }
+
+    fn with_adaptive(cfl_number: f32, diffusion_number: f32) -> SimulationConfig{
+        return SimulationConfig{adaptive_time_step: Some(AdaptiveTimeStep{cfl_number, diffusion_number, min_step_size: 0.001, max_step_size: 0.05}), ..SimulationConfig::default()};
+    }
+
+    #[test]
+    fn default_config_is_valid(){
+        assert_eq!(SimulationConfig::default().validate(), Ok(()));
+        assert_eq!(with_adaptive(0.5, 0.15).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_patch_outside_of_face(){
+        assert_eq!(rejected_field(&with_patch(|patch| patch.max_cells=[27, 50])), "boundary_patches");
+        assert_eq!(rejected_field(&with_patch(|patch| patch.min_cells=[28, 21])), "boundary_patches");
+    }
+
+    #[test]
+    fn rejects_patch_on_periodic_face(){
+        let config=SimulationConfig{periodic: [true, false, false], ..SimulationConfig::default()};
+        assert_eq!(rejected_field(&config), "boundary_patches");
+    }
+
+    #[test]
+    fn rejects_invalid_time_table(){
+        assert_eq!(rejected_field(&with_patch(|patch| patch.profile=TimeProfile::Table{times: vec![], factors: vec![]})), "boundary_patches");
+        assert_eq!(rejected_field(&with_patch(|patch| patch.profile=TimeProfile::Table{times: vec![0.0, 1.0], factors: vec![1.0]})), "boundary_patches");
+        assert_eq!(rejected_field(&with_patch(|patch| patch.profile=TimeProfile::Table{times: vec![0.0, 1.0, 1.0], factors: vec![0.0, 1.0, 0.5]})), "boundary_patches");
+        assert_eq!(with_patch(|patch| patch.profile=TimeProfile::Table{times: vec![0.0, 1.0], factors: vec![0.0, 1.0]}).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_spatial_table_of_wrong_size(){
+        //The patch has 7 x 7 cells
+        assert_eq!(rejected_field(&with_patch(|patch| patch.spatial_profile=SpatialProfile::Table{values: vec![vec![1.0; 7]; 6]})), "boundary_patches");
+        assert_eq!(rejected_field(&with_patch(|patch| patch.spatial_profile=SpatialProfile::Table{values: vec![vec![1.0; 8]; 7]})), "boundary_patches");
+        assert_eq!(with_patch(|patch| patch.spatial_profile=SpatialProfile::Table{values: vec![vec![1.0; 7]; 7]}).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_sigmoid_and_ramp_without_width(){
+        assert_eq!(rejected_field(&with_patch(|patch| patch.profile=TimeProfile::Sigmoid{midpoint: 1.0, width: 0.0})), "boundary_patches");
+        assert_eq!(rejected_field(&with_patch(|patch| patch.profile=TimeProfile::Ramp{start: 0.0, duration: -1.0})), "boundary_patches");
+    }
+
+    #[test]
+    fn rejects_multigrid_without_smoothing(){
+        let config=SimulationConfig{pressure_solver: PressureSolver::Multigrid{cycle: MultigridCycle::V, smoothing_steps: 0}, ..SimulationConfig::default()};
+        assert_eq!(rejected_field(&config), "pressure_solver");
+        let config=SimulationConfig{diffusion_scheme: DiffusionScheme::Implicit, diffusion_solver: PressureSolver::Multigrid{cycle: MultigridCycle::W, smoothing_steps: 0}, ..SimulationConfig::default()};
+        assert_eq!(rejected_field(&config), "diffusion_solver");
+    }
+
+    #[test]
+    fn rejects_omega_outside_of_zero_and_two(){
+        for omega in [0.0, -0.5, 2.0, 2.5, f32::NAN]{
+            let config=SimulationConfig{pressure_solver: PressureSolver::Sor{omega}, ..SimulationConfig::default()};
+            assert_eq!(rejected_field(&config), "pressure_solver");
+        }
+        let config=SimulationConfig{pressure_solver: PressureSolver::Sor{omega: 0.5}, ..SimulationConfig::default()};
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_adaptive_numbers_out_of_range(){
+        assert_eq!(rejected_field(&with_adaptive(0.0, 0.15)), "adaptive_time_step");
+        assert_eq!(rejected_field(&with_adaptive(1.0, 0.15)), "adaptive_time_step");
+        assert_eq!(rejected_field(&with_adaptive(0.5, 0.0)), "adaptive_time_step");
+        assert_eq!(rejected_field(&with_adaptive(0.5, 0.2)), "adaptive_time_step");
+    }
+
+    #[test]
+    fn scenario_is_validated(){
+        let error=crate::scenario::parse_scenario("[domain]\ncells = [10, 10, 10]\nspacing = 0.1\n[solver]\nmethod = \"sor\"\nomega = 2.5\n").unwrap_err();
+        assert!(error.to_string().starts_with("pressure_solver: omega"), "{}", error);
+    }
+}
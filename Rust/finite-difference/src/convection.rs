@@ -0,0 +1,133 @@
+//The discretisation of the convection term: the derivatives of a velocity in the direction it is carried by the velocity at the same point.
+//Central differences are accurate but create oscillations when the cell Reynolds number (density*|u|*dx/viscosity) is above 2. The upwind schemes take
+//more of their values from the side the flow comes from. QUICK and TVD need two values upstream, next to a wall there is only one and first order upwind is used there.
+use crate::{AdvectionCorrection, SimulationConfig, VelocityGrid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConvectionScheme{
+    Central,//Second order central differences
+    Upwind,//First order, the difference with the value upstream. It never oscillates but smears out the flow.
+    Hybrid,//Central where the cell Reynolds number is below 2, upwind where it is higher
+    Quick,//Quadratic upstream interpolation of the values on the faces, third order but it can still overshoot a little
+    Tvd{limiter: FluxLimiter},//Second order where the flow is smooth and first order near steep changes, so no new extremes appear
+    //The velocities are carried by a semi-Lagrangian step before the prediction instead of by a convection term. It is stable for any time step.
+    SemiLagrangian{correction: AdvectionCorrection},
+}
+
+//How much of the second order correction a TVD scheme keeps, from the ratio r of the upstream and the downstream difference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FluxLimiter{
+    Minmod,//The most diffusive limiter
+    VanLeer,//A smooth limiter in between
+    Superbee,//The least diffusive limiter, it sharpens steep changes
+}
+
+impl FluxLimiter{
+    fn limit(&self, r: f32) -> f32{
+        return match self{
+            FluxLimiter::Minmod => r.clamp(0.0, 1.0),
+            FluxLimiter::VanLeer => (r+r.abs())/(1.0+r.abs()),
+            FluxLimiter::Superbee => (2.0*r).min(1.0).max(r.min(2.0)).max(0.0),
+        };
+    }
+}
+
+//The derivative in the given dimension of the velocities of a grid at the point (x,y,z), when they are carried by the velocity `advecting` at that point
+pub(crate) fn convective_derivative(velocity_grid: &VelocityGrid, x: usize, y: usize, z: usize, dimension: usize, advecting: f32, config: &SimulationConfig) -> f32{
+    let grid=&velocity_grid.grid;
+    let h=config.grid_element_scale;
+    let coordinate=[x, y, z][dimension];
+    //The value `steps` points further along the axis
+    let value=|steps: isize| {
+        let mut index=[x, y, z];
+        index[dimension]=coordinate.wrapping_add_signed(steps);
+        grid[index]
+    };
+    let (lower, center, upper)=(value(-1), value(0), value(1));
+    let central=(upper-lower)/(2.0*h);
+    let upwind=if advecting>=0.0 {(center-lower)/h} else {(upper-center)/h};
+    return match config.convection_scheme{
+        ConvectionScheme::Central => central,
+        ConvectionScheme::Upwind => upwind,
+        ConvectionScheme::Hybrid => {
+            let cell_reynolds_number=config.density*advecting.abs()*h/config.viscosity;
+            if cell_reynolds_number<2.0 {central} else {upwind}
+        }
+        ConvectionScheme::Quick => upstream_weighted(value, coordinate, grid.shape()[dimension], advecting, h, |far, upwind, downwind| 0.75*upwind+0.375*downwind-0.125*far).unwrap_or(upwind),
+        ConvectionScheme::Tvd{limiter} => upstream_weighted(value, coordinate, grid.shape()[dimension], advecting, h, |far, upwind, downwind| limited_face(limiter, far, upwind, downwind)).unwrap_or(upwind),
+        //The velocities have already been carried
+        ConvectionScheme::SemiLagrangian{..} => 0.0,
+    };
+}
+
+//The derivative from the values on the faces downstream and upstream of the point, face gives the value on a face from the two values upstream of it and the value downstream.
+//None when the second value upstream of the point is outside of the grid.
+fn upstream_weighted<V: Fn(isize) -> f32, F: Fn(f32, f32, f32) -> f32>(value: V, coordinate: usize, length: usize, advecting: f32, h: f32, face: F) -> Option<f32>{
+    let direction: isize=if advecting>=0.0 {1} else {-1};
+    let far_upstream=coordinate as isize-2*direction;
+    if far_upstream<0 || far_upstream>=length as isize{
+        return None;
+    }
+    //The value k points downstream of the point
+    let along_flow=|k: isize| value(k*direction);
+    let downstream_face=face(along_flow(-1), along_flow(0), along_flow(1));
+    let upstream_face=face(along_flow(-2), along_flow(-1), along_flow(0));
+    //The faces are ordered along the flow, so the difference has the sign of the flow direction
+    return Some(direction as f32*(downstream_face-upstream_face)/h);
+}
+
+//The value on the face between the cells `upwind` and `downwind`, with `far` the value one further upstream
+fn limited_face(limiter: FluxLimiter, far: f32, upwind: f32, downwind: f32) -> f32{
+    let difference=downwind-upwind;
+    if difference==0.0{
+        return upwind;
+    }
+    let r=(upwind-far)/difference;
+    return upwind+0.5*limiter.limit(r)*difference;
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::GHOST_LAYERS;
+
+    //Carry a step from 1 to 0 along x with a speed of 1 m/s and forward Euler for `steps` time steps at a CFL number of 0.05, returns the lowest and the highest value along the line
+    fn advect_step(scheme: ConvectionScheme, steps: usize) -> (f32, f32){
+        let config=SimulationConfig{convection_scheme: scheme, ..SimulationConfig::default()};
+        let cells=40;
+        let mut velocity_grid=VelocityGrid::new([cells, 3, 3], 0);
+        let (y, z)=(GHOST_LAYERS+1, GHOST_LAYERS+1);
+        for x in velocity_grid.grid.storage_range(0){
+            velocity_grid.grid[[x,y,z]]=if x<GHOST_LAYERS+10 {1.0} else {0.0};
+        }
+        let dt=0.05*config.grid_element_scale;
+        //The values in the ghost layers and on the walls stay what they are
+        let solved=GHOST_LAYERS+1..GHOST_LAYERS+cells;
+        for _ in 0..steps{
+            let derivatives: Vec<f32>=solved.clone().map(|x| convective_derivative(&velocity_grid, x, y, z, 0, 1.0, &config)).collect();
+            for (x, derivative) in solved.clone().zip(derivatives){
+                velocity_grid.grid[[x,y,z]]-=dt*derivative;
+            }
+        }
+        let values=solved.map(|x| velocity_grid.grid[[x,y,z]]);
+        return values.fold((f32::INFINITY, f32::NEG_INFINITY), |(lowest, highest), value| (lowest.min(value), highest.max(value)));
+    }
+
+    #[test]
+    fn upwind_and_tvd_create_no_new_extremes(){
+        let schemes=[ConvectionScheme::Upwind, ConvectionScheme::Tvd{limiter: FluxLimiter::Minmod}, ConvectionScheme::Tvd{limiter: FluxLimiter::VanLeer}, ConvectionScheme::Tvd{limiter: FluxLimiter::Superbee}];
+        for scheme in schemes{
+            let (lowest, highest)=advect_step(scheme, 160);
+            assert!(lowest>=-1e-6 && highest<=1.0+1e-6, "{:?} creates values between {} and {}", scheme, lowest, highest);
+        }
+    }
+
+    #[test]
+    fn central_overshoots_more_than_quick(){
+        let (_, central)=advect_step(ConvectionScheme::Central, 160);
+        let (quick_lowest, quick_highest)=advect_step(ConvectionScheme::Quick, 160);
+        assert!(central>1.1, "central differences overshoot to {}", central);
+        //QUICK is not bounded, but its overshoot is smaller than the one of central differences
+        assert!(quick_highest<central && quick_lowest>-0.1, "QUICK creates values between {} and {}, central differences up to {}", quick_lowest, quick_highest, central);
+    }
+}
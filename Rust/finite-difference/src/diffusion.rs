@@ -0,0 +1,187 @@
+//Implicit treatment of the viscous diffusion. Explicit diffusion is only stable when viscosity/density*dt/dx^2 is at most 1/6, which is a severe limit on fine grids.
+//With a fraction theta of the diffusion taken at the new time, the provisional velocities u* of one grid follow from
+//    u* - theta*c*(sum of the neighbours of u* - 6 u*) = (the explicit prediction with the fraction 1-theta of the diffusion),    c = viscosity/density*dt/dx^2
+//which is a symmetric positive definite system with one unknown for every velocity that the solver calculates. The velocities on the walls, in the ghost layers
+//and on the faces of obstacles are taken from the start of the time step, they are set by the boundary conditions after the prediction.
+use crate::{Field3D, LinearSolver, SimulationConfig, SolidMask, SolveStatistics, SolverError, StencilMatrix, Step, VelocityGrid, boundary, get_dimension, is_larger, obstacle, solved_range};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffusionScheme{
+    Explicit,//Forward Euler, only stable up to a diffusion number of 1/6
+    Implicit,//Backward Euler, stable for any time step but first order in time
+    CrankNicolson,//Half at the old and half at the new time, stable for any time step and second order in time
+}
+
+impl DiffusionScheme{
+    //The fraction of the diffusion that is taken at the new time
+    pub fn implicit_fraction(&self) -> f32{
+        return match self{
+            DiffusionScheme::Explicit => 0.0,
+            DiffusionScheme::Implicit => 1.0,
+            DiffusionScheme::CrankNicolson => 0.5,
+        };
+    }
+}
+
+//The diffusion equations of one velocity grid. The couplings only depend on the grid, the obstacles and the boundaries, the weights on the time step size.
+pub(crate) struct DiffusionProblem{
+    unknown_points: Vec<[usize; 3]>,//The storage coordinates of the velocity of every unknown
+    neighbors: Vec<[Option<usize>; 6]>,//The unknown of every neighbour, None when the neighbour is a known velocity
+    matrix: StencilMatrix,
+    coefficient: f32,//theta*c of the matrix
+    solver: Box<dyn LinearSolver>,
+}
+
+impl DiffusionProblem{
+    pub(crate) fn new(velocity_grid: &VelocityGrid, solid_mask: &SolidMask, config: &SimulationConfig) -> Result<Self, SolverError>{
+        let grid=&velocity_grid.grid;
+        let ghost=grid.ghost();
+        let mut unknown_points=Vec::new();
+        for x in solved_range(velocity_grid, 0, config){
+            for y in solved_range(velocity_grid, 1, config){
+                for z in solved_range(velocity_grid, 2, config){
+                    if !obstacle::is_solid_face(solid_mask, velocity_grid.dimension, x, y, z){
+                        unknown_points.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        //The velocities through patches with a fixed pressure are predicted like the velocities inside
+        unknown_points.extend(boundary::solved_wall_faces(velocity_grid, config));
+        let mut unknown_index: Field3D<Option<usize>>=Field3D::new(grid.cells(), ghost, grid.staggering(), None);
+        for (index, &point) in unknown_points.iter().enumerate(){
+            unknown_index[point]=Some(index);
+        }
+        let neighbors=unknown_points.iter().map(|&point| {
+            [0, 1, 2, 3, 4, 5].map(|neighbor| {
+                let dimension=neighbor/2;
+                let mut index=neighbor_point(point, neighbor);
+                //The neighbour on the other side of a periodic seam is the point one period further in
+                if config.periodic[dimension]{
+                    let period=grid.cells()[dimension];
+                    if index[dimension]<ghost {index[dimension]+=period} else if index[dimension]>=ghost+period {index[dimension]-=period}
+                }
+                unknown_index[index]
+            })
+        }).collect();
+        let cells=[0, 1, 2].map(|dimension| grid.cells()[dimension]+grid.staggering()[dimension]);
+        let matrix=StencilMatrix::new(cells, unknown_points.iter().map(|point| point.map(|coordinate| coordinate-ghost)).collect());
+        let solver=config.diffusion_solver.create_solver().ok_or_else(|| SolverError::InvalidConfig{field: "diffusion_solver", message: String::from("can not be the legacy pressure solver")})?;
+        return Ok(Self{unknown_points, neighbors, matrix, coefficient: 0.0, solver});
+    }
+    //Make the provisional velocities implicit in the diffusion, provisional holds the explicit prediction and last the velocities the prediction started from
+    pub(crate) fn solve(&mut self, provisional: &mut VelocityGrid, last: &VelocityGrid, step: Step, config: &SimulationConfig) -> Result<SolveStatistics, SolverError>{
+        let coefficient=config.diffusion_scheme.implicit_fraction()*config.viscosity/config.density*step.dt/(config.grid_element_scale*config.grid_element_scale);
+        if coefficient!=self.coefficient{//The time step size changed
+            self.build_matrix(coefficient);
+        }
+        let b: Vec<f32>=self.unknown_points.iter().zip(self.neighbors.iter()).map(|(&point, neighbors)| {
+            let mut value=provisional.grid[point];
+            for (neighbor, &unknown) in neighbors.iter().enumerate(){
+                if unknown.is_none(){
+                    value+=coefficient*last.grid[neighbor_point(point, neighbor)];
+                }
+            }
+            value
+        }).collect();
+        //The explicit prediction is a good first guess
+        let mut x: Vec<f32>=self.unknown_points.iter().map(|&point| provisional.grid[point]).collect();
+        let max_iterations=config.max_iterations_per_time_frame.max(1) as usize;
+        let statistics=self.solver.solve(&self.matrix, &mut x, &b, config.diffusion_tolerance, max_iterations);
+        if !statistics.converged{
+            let mut residual=vec![0.0; x.len()];
+            self.matrix.residual(&x, &b, &mut residual);
+            //The first of the largest residuals, NaN counts as the largest
+            let row=residual.iter().enumerate().fold(0, |largest, (row, value)| if is_larger(value.abs(), residual[largest].abs()) {row} else {largest});
+            return Err(SolverError::DiffusionNotConverged{time_step: step.index, dimension: provisional.dimension, iterations: statistics.iterations, residual: statistics.residual, point: self.matrix.coordinates(row)});
+        }
+        for (&point, &value) in self.unknown_points.iter().zip(x.iter()){
+            provisional.grid[point]=value;
+        }
+        return Ok(statistics);
+    }
+    fn build_matrix(&mut self, coefficient: f32){
+        let mut matrix=StencilMatrix::new(self.matrix.cells(), (0..self.matrix.size()).map(|row| self.matrix.coordinates(row)).collect());
+        for (row, neighbors) in self.neighbors.iter().enumerate(){
+            matrix.add_to_diagonal(row, 1.0+6.0*coefficient);
+            for &neighbor in neighbors.iter().flatten(){
+                matrix.add_coupling(row, neighbor, coefficient);
+            }
+        }
+        self.solver.prepare(&matrix);
+        self.matrix=matrix;
+        self.coefficient=coefficient;
+    }
+}
+
+//Neighbour 2d of a point is the neighbour below it in dimension d, neighbour 2d+1 the neighbour above it
+fn neighbor_point(point: [usize; 3], neighbor: usize) -> [usize; 3]{
+    let dim=get_dimension(neighbor/2);
+    if neighbor%2==1{
+        return [point[0]+dim[0], point[1]+dim[1], point[2]+dim[2]];
+    }
+    return [point[0]-dim[0], point[1]-dim[1], point[2]-dim[2]];
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{GHOST_LAYERS, PressureSolver, SimulationState, simulation_time_step};
+
+    #[test]
+    fn legacy_solver_is_an_invalid_config(){
+        let config=SimulationConfig{diffusion_scheme: DiffusionScheme::Implicit, diffusion_solver: PressureSolver::Legacy, ..SimulationConfig::default()};
+        let velocity_grid=VelocityGrid::new(config.pressure_grid_size, 0);
+        let solid_mask=obstacle::build_solid_mask(&config);
+        match DiffusionProblem::new(&velocity_grid, &solid_mask, &config){
+            Err(SolverError::InvalidConfig{field, ..}) => assert_eq!(field, "diffusion_solver"),
+            Err(error) => panic!("expected an invalid config, got {:?}", error),
+            Ok(_) => panic!("expected an invalid config"),
+        }
+    }
+
+    //The relative error of the amplitude of a sine wave of the y velocity along x after `steps` time steps, against the exact decay exp(-viscosity/density*k^2*t).
+    //The flow along the waves does not change them, so only the diffusion acts. The wave moves a fifth of a cell per time step, which is twelve times larger than explicit diffusion allows.
+    fn decay_error(scheme: DiffusionScheme, steps: usize) -> f32{
+        let cells=32;
+        let config=SimulationConfig{
+            pressure_grid_size: [cells, 4, 4],
+            grid_element_scale: 0.01,
+            time_step_size: 0.02,
+            density: 1.0,
+            viscosity: 0.01,
+            periodic: [true, true, true],
+            boundary_patches: Vec::new(),
+            diffusion_scheme: scheme,
+            ..SimulationConfig::default()
+        };
+        let wave_number=2.0*std::f32::consts::PI/(cells as f32*config.grid_element_scale);
+        let phase=|x: usize| wave_number*(x as f32-GHOST_LAYERS as f32+0.5)*config.grid_element_scale;
+        let mut state=SimulationState::new(&config).unwrap();
+        let grid=&mut state.velocity_y.grid;
+        for x in grid.storage_range(0){
+            for y in grid.storage_range(1){
+                for z in grid.storage_range(2){
+                    grid[[x,y,z]]=0.1*phase(x).sin();
+                }
+            }
+        }
+        for _ in 0..steps{
+            simulation_time_step(&mut state, &config).unwrap();
+        }
+        //The amplitude relative to the initial 0.1 m/s is the projection on the sine, so a small change of the shape does not count
+        let amplitude=20.0/cells as f32*state.velocity_y.grid.domain_range(0).map(|x| state.velocity_y.grid[[x,GHOST_LAYERS,GHOST_LAYERS]]*phase(x).sin()).sum::<f32>();
+        let exact=(-config.viscosity/config.density*wave_number*wave_number*steps as f32*config.time_step_size).exp();
+        return (amplitude-exact).abs()/exact;
+    }
+
+    #[test]
+    fn implicit_and_crank_nicolson_diffusion_decay_a_sine_like_the_exact_solution(){
+        let implicit=decay_error(DiffusionScheme::Implicit, 10);
+        let crank_nicolson=decay_error(DiffusionScheme::CrankNicolson, 10);
+        //Backward Euler is first order in time, Crank-Nicolson second order, what remains is mostly the error of the second order differences in space
+        assert!(implicit<0.05, "relative error {} with implicit diffusion", implicit);
+        assert!(crank_nicolson<0.005, "relative error {} with Crank-Nicolson diffusion", crank_nicolson);
+        assert!(crank_nicolson<implicit);
+    }
+}
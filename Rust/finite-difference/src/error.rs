@@ -0,0 +1,29 @@
+//The ways a simulation can fail. The library returns them instead of stopping the process, the application decides what to do with them.
+//time_step is the number of the time step that failed. Cells and points are given in domain coordinates: cell (0, 0, 0) is the lower corner of the domain
+//and point (i, j, k) of a velocity grid is the face at the lower side of cell (i, j, k) in the dimension of the grid.
+use std::fmt;
+
+const DIMENSIONS: [&str; 3] = ["x", "y", "z"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SolverError{
+    InvalidConfig{field: &'static str, message: String},//A value of the SimulationConfig can not be simulated, field is the name of the value
+    PressureNotConverged{time_step: i32, iterations: usize, divergence: f32, cell: [usize; 3]},//The velocities are not free of divergence after the last iteration, cell has the largest remaining divergence in 1/s
+    DiffusionNotConverged{time_step: i32, dimension: usize, iterations: usize, residual: f32, point: [usize; 3]},//The implicit diffusion of one velocity grid did not converge, point has the largest residual in m/s
+    NotFinite{time_step: i32, field: &'static str, point: [usize; 3]},//A velocity or pressure became NaN or infinite, the simulation has blown up. It is found by a check after a phase of the time step, not inside a solver, so there is no iteration.
+    CflViolation{time_step: i32, cfl_number: f32, cell: [usize; 3]},//The fluid in a cell moves more than a cell in a time step, the explicit convection would blow up. It is checked before the time step starts, so there is no iteration.
+}
+
+impl fmt::Display for SolverError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return match self{
+            SolverError::InvalidConfig{field, message} => write!(f, "Invalid configuration, {}: {}", field, message),
+            SolverError::PressureNotConverged{time_step, iterations, divergence, cell} => write!(f, "The pressure did not converge in {} iterations in time step {}, the largest remaining divergence is {} 1/s in cell {:?}", iterations, time_step, divergence, cell),
+            SolverError::DiffusionNotConverged{time_step, dimension, iterations, residual, point} => write!(f, "The diffusion of the {} velocities did not converge in {} iterations in time step {}, the largest remaining residual is {} m/s at point {:?}", DIMENSIONS[*dimension], iterations, time_step, residual, point),
+            SolverError::NotFinite{time_step, field, point} => write!(f, "The {} became NaN or infinite in time step {} at point {:?}", field, time_step, point),
+            SolverError::CflViolation{time_step, cfl_number, cell} => write!(f, "The CFL number is {} in cell {:?} at the start of time step {}, it has to be at most 1. Use a smaller or an adaptive time step, or semi-Lagrangian convection", cfl_number, cell, time_step),
+        };
+    }
+}
+
+impl std::error::Error for SolverError{}
@@ -0,0 +1,177 @@
+use std::ops::{Index, IndexMut, Range};
+
+use rayon::prelude::*;
+
+//A three dimensional grid of values stored in one contiguous Vec. The x index changes slowest and the z index fastest, so neighbours in z are next to each other in memory.
+//The domain consists of `cells` pressure cells in each dimension. Around the domain there are `ghost` layers of extra elements on every side, used for boundary conditions.
+//Values that live on the faces of the cells (staggered values, like the velocities) have one extra element in the dimension they are staggered in.
+//All indices are storage indices: the first cell of the domain (or its lower face for staggered fields) has index `ghost` in every dimension.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field3D<T = f32>{
+    data: Vec<T>,
+    cells: [usize; 3],
+    ghost: usize,
+    staggering: [usize; 3],
+    shape: [usize; 3],
+}
+
+impl<T: Copy> Field3D<T>{
+    pub fn new(cells: [usize; 3], ghost: usize, staggering: [usize; 3], value: T) -> Self{
+        let shape=[cells[0]+staggering[0]+2*ghost, cells[1]+staggering[1]+2*ghost, cells[2]+staggering[2]+2*ghost];
+        return Self{
+            data: vec![value; shape[0]*shape[1]*shape[2]],
+            cells,
+            ghost,
+            staggering,
+            shape,
+        }
+    }
+    //A field with one value in the center of every cell, like the pressure
+    pub fn cell_centered(cells: [usize; 3], ghost: usize, value: T) -> Self{
+        return Self::new(cells, ghost, [0, 0, 0], value);
+    }
+    //A field with one value on every face orthogonal to the given dimension, like the velocity in that dimension
+    pub fn staggered(cells: [usize; 3], ghost: usize, dimension: usize, value: T) -> Self{
+        let mut staggering=[0, 0, 0];
+        staggering[dimension]=1;
+        return Self::new(cells, ghost, staggering, value);
+    }
+    pub fn fill(&mut self, value: T){
+        for element in self.data.iter_mut(){
+            *element=value;
+        }
+    }
+    pub fn copy_from(&mut self, other: &Self){
+        assert!(self.has_same_layout(other), "Can not copy between fields with a different layout");
+        self.data.copy_from_slice(&other.data);
+    }
+    //Treat the field as periodic in the given dimension: every element outside of the first `cells` elements of the domain gets the value of the element one period further in.
+    //For a field that is staggered in this dimension the face on the upper wall is the same face as the one on the lower wall, so it is overwritten as well.
+    pub fn wrap_periodic(&mut self, dimension: usize){
+        let ghost=self.ghost;
+        let period=self.cells[dimension];
+        for layer in self.storage_range(dimension){
+            let source=if layer<ghost {layer+period} else if layer>=ghost+period {layer-period} else {continue};
+            for a in self.storage_range((dimension+1)%3){
+                for b in self.storage_range((dimension+2)%3){
+                    let mut target_index=[0, 0, 0];
+                    target_index[dimension]=layer;
+                    target_index[(dimension+1)%3]=a;
+                    target_index[(dimension+2)%3]=b;
+                    let mut source_index=target_index;
+                    source_index[dimension]=source;
+                    self[target_index]=self[source_index];
+                }
+            }
+        }
+    }
+}
+
+impl<T> Field3D<T>{
+    //The number of stored elements in each dimension, ghost layers included
+    pub fn shape(&self) -> [usize; 3]{
+        return self.shape;
+    }
+    //The number of pressure cells of the domain in each dimension
+    pub fn cells(&self) -> [usize; 3]{
+        return self.cells;
+    }
+    pub fn ghost(&self) -> usize{
+        return self.ghost;
+    }
+    pub fn staggering(&self) -> [usize; 3]{
+        return self.staggering;
+    }
+    //The distance in the data between two neighbours in each dimension
+    pub fn strides(&self) -> [usize; 3]{
+        return [self.shape[1]*self.shape[2], self.shape[2], 1];
+    }
+    pub fn linear_index(&self, x: usize, y: usize, z: usize) -> usize{
+        debug_assert!(x<self.shape[0] && y<self.shape[1] && z<self.shape[2], "Index ({}, {}, {}) is outside of a field with shape {:?}", x, y, z, self.shape);
+        return (x*self.shape[1]+y)*self.shape[2]+z;
+    }
+    //All elements in a dimension, ghost layers included
+    pub fn storage_range(&self, dimension: usize) -> Range<usize>{
+        return 0..self.shape[dimension];
+    }
+    //The elements inside the domain or on its boundary
+    pub fn domain_range(&self, dimension: usize) -> Range<usize>{
+        return self.ghost..self.ghost+self.cells[dimension]+self.staggering[dimension];
+    }
+    //The elements strictly inside the domain, for staggered fields this leaves out the faces on the boundary
+    pub fn inner_range(&self, dimension: usize) -> Range<usize>{
+        return self.ghost+self.staggering[dimension]..self.ghost+self.cells[dimension];
+    }
+    pub fn has_same_layout<U>(&self, other: &Field3D<U>) -> bool{
+        return self.cells==other.cells && self.ghost==other.ghost && self.staggering==other.staggering;
+    }
+    pub fn as_slice(&self) -> &[T]{
+        return &self.data;
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [T]{
+        return &mut self.data;
+    }
+}
+
+impl<T: Send> Field3D<T>{
+    //The layers with an x index in the given range as separate slices together with their x index, so they can be worked on in parallel.
+    //Element (y, z) of a layer has index y*shape[2]+z.
+    pub fn par_layers_mut(&mut self, range: Range<usize>) -> impl IndexedParallelIterator<Item = (usize, &mut [T])>{
+        let layer_size=self.shape[1]*self.shape[2];
+        return self.data.par_chunks_mut(layer_size).enumerate().skip(range.start).take(range.len());
+    }
+}
+
+impl<T> Index<[usize; 3]> for Field3D<T>{
+    type Output = T;
+    fn index(&self, index: [usize; 3]) -> &T{
+        return &self.data[self.linear_index(index[0], index[1], index[2])];
+    }
+}
+
+impl<T> IndexMut<[usize; 3]> for Field3D<T>{
+    fn index_mut(&mut self, index: [usize; 3]) -> &mut T{
+        let i=self.linear_index(index[0], index[1], index[2]);
+        return &mut self.data[i];
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    //A field where every element has a different value, made from its index
+    fn numbered(cells: [usize; 3], staggering: [usize; 3]) -> Field3D<usize>{
+        let mut field=Field3D::new(cells, 2, staggering, 0);
+        for (index, value) in field.as_mut_slice().iter_mut().enumerate(){
+            *value=index;
+        }
+        return field;
+    }
+
+    #[test]
+    fn ghost_layers_are_the_opposite_interior_layers(){
+        for staggering in [[0, 0, 0], [1, 0, 0], [0, 1, 0], [0, 0, 1]]{
+            for dimension in 0..3{
+                let original=numbered([4, 5, 3], staggering);
+                let mut field=original.clone();
+                field.wrap_periodic(dimension);
+                let period=field.cells()[dimension];
+                for x in field.storage_range(0){
+                    for y in field.storage_range(1){
+                        for z in field.storage_range(2){
+                            let layer=[x, y, z][dimension];
+                            let mut source=[x, y, z];
+                            if layer<2{
+                                source[dimension]+=period;
+                            }else if layer>=2+period{
+                                source[dimension]-=period;
+                            }
+                            assert_eq!(field[[x,y,z]], original[source], "staggering {:?}, dimension {}, at {:?}", staggering, dimension, [x, y, z]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,119 +1,393 @@
+mod boundary;
+pub mod checkpoint;
+mod config;
+mod convection;
+mod diffusion;
+mod error;
+mod field;
+mod mesh;
+mod obstacle;
+mod profile;
+mod report;
+pub mod scenario;
+mod semi_lagrangian;
+mod solver;
+mod stencil;
+mod time_integration;
+mod time_step;
+
+use std::{ops::Range, sync::Arc, time::Instant};
+
+use rayon::{ThreadPool, ThreadPoolBuilder, prelude::*};
+
+#[cfg(feature = "renderer")]
 use renderer::{Renderer, RenderResult};
 
-//Physical constants
-const GRIDELEMENTSCALE: f32 = 0.05;//The size of a grid element in meters(denoted in equations as delta x)
-const TIMESTEPSIZE: f32 = 0.05;//The size of a time step size in seconds
-const DENSITY: f32 = 997.0;//Density of the liquid in kg/m^{3}. We simulate water.
-const EXTERNALFORCE : [f32; 3] = [0.0,0.0, 0.0];//Gravity in N
-const VISCOSITY: f32 = 0.001;//Viscosity in Pa*s.
-const ATMOSPHERIC_PRESSURE: f32=101325.0;//101 325;//Atmospheric pressure in Pa
-const MAXITERATIONSPERTIMEFRAME:i32=30000;//This constant sets a maximum so the computer can not get in an infinite loop.
-const RELEXATION: f32=1.0;//Pressure correction is often underestimated, this factor should be between 1.4 and 1.8.
-const ALLOWEDERROR: f32=0.005; 
-
-//Pressure is measured in Pascal, because it is the standard SI unit for pressure.
-
-//Grid size(e.g. number of elements in each dimension)
-const PRESSUREGRIDSIZE: [usize; 3] = [50,50,50];//x,y,z
+pub use boundary::{BoundaryPatch, Face, PatchKind, WallCondition};
+pub use config::SimulationConfig;
+pub use convection::{ConvectionScheme, FluxLimiter};
+pub use diffusion::DiffusionScheme;
+pub use error::SolverError;
+pub use field::Field3D;
+pub use mesh::{MeshError, Triangle, TriangleMesh};
+pub use obstacle::{Obstacle, SolidMask};
+pub use profile::{ProfileFunction, SpatialProfile, TimeProfile, read_csv};
+pub use report::{PhaseTimes, StepReport};
+use report::PhaseSpan;
+pub use semi_lagrangian::AdvectionCorrection;
+pub use stencil::StencilKernels;
+pub use time_integration::TimeIntegration;
+pub use time_step::{AdaptiveTimeStep, TimeStepLimit};
+use time_step::Step;
+pub use solver::{ConjugateGradientSolver, CustomSolver, JacobiSolver, LinearSolver, MultigridCycle, MultigridSolver, Preconditioner, PressureSolver, ResidualNorms, SolveStatistics, SorSolver, StencilMatrix};
+
+//The number of ghost layers around every grid. One layer is enough for all stencils we use.
+const GHOST_LAYERS: usize = 1;
+
+//pressure_grid[[x,y,z]] is the pressure in the cell with storage coordinates (x,y,z)
+pub type PressureGrid = Field3D;
+//The velocity and the colour of every point that the renderer shows
+pub type VisualisationGrid = Vec<Vec<Vec<([f32; 3], [f32; 3])>>>;
+type ColorGrid = Field3D<[f32; 3]>;
 
 pub struct VelocityGrid{
-    grid: Vec<Vec<Vec<f32>>>,
+    grid: Field3D,
     dimension: usize,
 }
 
-pub fn initialize_simulation(){
+impl VelocityGrid{
+    //The velocities in one dimension are stored on the faces of the pressure cells that are orthogonal to that dimension
+    pub fn new(cells: [usize; 3], dimension: usize) -> Self{
+        return Self{grid: Field3D::staggered(cells, GHOST_LAYERS, dimension, 0.0), dimension}
+    }
+    pub fn grid(&self) -> &Field3D{
+        return &self.grid;
+    }
+    pub fn dimension(&self) -> usize{
+        return self.dimension;
+    }
+}
+
+//The fields that the prediction of the velocities reads: the velocities it starts from, the pressure of the last time step and the obstacles
+#[derive(Clone, Copy)]
+pub(crate) struct PredictionFields<'a>{
+    pub(crate) velocities: [&'a VelocityGrid; 3],
+    pub(crate) pressure_grid: &'a PressureGrid,
+    pub(crate) solid_mask: &'a SolidMask,
+}
+
+impl<'a> PredictionFields<'a>{
+    //The velocities of a dimension and of the two other dimensions in increasing order, the convection term adds them up in this order
+    pub(crate) fn velocity_and_orthogonal(&self, dimension: usize) -> (&'a VelocityGrid, &'a VelocityGrid, &'a VelocityGrid){
+        let others=match dimension{
+            0 => [1, 2],
+            1 => [0, 2],
+            _ => [0, 1],
+        };
+        return (self.velocities[dimension], self.velocities[others[0]], self.velocities[others[1]]);
+    }
+}
+
+//Everything that changes while the simulation runs
+pub struct SimulationState{
+    pub velocity_x: VelocityGrid,
+    pub velocity_y: VelocityGrid,
+    pub velocity_z: VelocityGrid,
+    pub pressure_grid: PressureGrid,
+    pub solid_mask: SolidMask,//The cells that are inside an obstacle, they do not change during the simulation
+    pub time_step: i32,//The number of the next time step
+    pub time: f32,//The simulated time in seconds
+    pub time_step_size: f32,//The size of the last time step in seconds
+    pub time_step_limit: TimeStepLimit,//What decided the size of the last time step
+    color_grid: ColorGrid,
+    poisson_problem: solver::PoissonProblem,
+    pressure_solver: Option<Box<dyn LinearSolver>>,//None for the legacy scheme
+    diffusion_problems: Option<[diffusion::DiffusionProblem; 3]>,//None for explicit diffusion
+    previous_tendency: time_integration::PreviousTendency,//Only kept for Adams-Bashforth
+    thread_pool: Arc<ThreadPool>,//The threads that calculate the time steps
+}
+
+impl SimulationState{
+    pub fn new(config: &SimulationConfig) -> Result<Self, SolverError>{
+        config.validate()?;
+        let thread_pool=create_thread_pool(config)?;
+        let mut pressure_grid: PressureGrid=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, 0.0);
+        initialize_pressure_grid(&mut pressure_grid, config);
+        let mut velocity_x=VelocityGrid::new(config.pressure_grid_size, 0);
+        let mut velocity_y=VelocityGrid::new(config.pressure_grid_size, 1);
+        let mut velocity_z=VelocityGrid::new(config.pressure_grid_size, 2);
+        initialize_velocity_grid(&mut velocity_x, config);
+        initialize_velocity_grid(&mut velocity_y, config);
+        initialize_velocity_grid(&mut velocity_z, config);
+        boundary::wrap_periodic_velocities(&mut velocity_x, &mut velocity_y, &mut velocity_z, config);
+        let solid_mask=obstacle::build_solid_mask(config);
+        obstacle::set_obstacle_boundary_conditions(&mut velocity_x, &mut velocity_y, &mut velocity_z, &solid_mask, config);
+        let poisson_problem=solver::PoissonProblem::new(config, &solid_mask);
+        let mut pressure_solver=config.pressure_solver.create_solver();
+        if let Some(pressure_solver)=pressure_solver.as_mut(){
+            pressure_solver.prepare(poisson_problem.matrix());
+        }
+        let diffusion_problems=if config.diffusion_scheme==DiffusionScheme::Explicit {None} else {
+            let [x, y, z]=[&velocity_x, &velocity_y, &velocity_z].map(|velocity_grid| diffusion::DiffusionProblem::new(velocity_grid, &solid_mask, config));
+            Some([x?, y?, z?])
+        };
+        return Ok(Self{
+            velocity_x,
+            velocity_y,
+            velocity_z,
+            pressure_grid,
+            solid_mask,
+            time_step: 0,
+            time: 0.0,
+            time_step_size: config.time_step_size,
+            time_step_limit: TimeStepLimit::Fixed,
+            color_grid: Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, [0.0; 3]),
+            poisson_problem,
+            pressure_solver,
+            diffusion_problems,
+            previous_tendency: None,
+            thread_pool: Arc::new(thread_pool),
+        });
+    }
+}
+
+//Run the simulation in a window, a new time step is calculated every time the user asks for one
+#[cfg(feature = "renderer")]
+pub fn initialize_simulation(config: &SimulationConfig) -> Result<(), SolverError>{
+    return resume_simulation(SimulationState::new(config)?, config, |_, _| {});
+}
+
+//Like initialize_simulation, but continue from a state, e.g. one read from a checkpoint. on_step is called with the state and the report after every time step.
+#[cfg(feature = "renderer")]
+pub fn resume_simulation<F: FnMut(&SimulationState, &StepReport)>(mut state: SimulationState, config: &SimulationConfig, mut on_step: F) -> Result<(), SolverError>{
     let renderer = Renderer::new(false);
-    let mut pressure_grid: Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>= Box::new([[[0.0; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]);//pressureGrid[x][y][z] is the pressure at coordinates (x,y,z)
-    let mut velocity_x = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 2]; PRESSUREGRIDSIZE[1] + 2]; PRESSUREGRIDSIZE[0] + 1], dimension: 0 });// z,y,x !!!
-    let mut velocity_y = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 2]; PRESSUREGRIDSIZE[1] + 1]; PRESSUREGRIDSIZE[0] + 2], dimension: 1 });
-    let mut velocity_z = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 1]; PRESSUREGRIDSIZE[1] + 2]; PRESSUREGRIDSIZE[0] + 2], dimension: 2 });
-    
-    initialize_pressure_grid(&mut pressure_grid);
-    let mut i: i32=0;
     loop{
-    //for i in 0..500{
-        let render_data = simulation_time_step(&mut velocity_x, &mut velocity_y, &mut velocity_z, &mut pressure_grid, i);
-        renderer.transform_grid(render_data);
+        let report = simulation_time_step(&mut state, config)?;
+        on_step(&state, &report);
+        renderer.transform_grid(convert_velocities_to_collocated_grid_and_visualise([0,4,0], [config.pressure_grid_size[0]-1, 4, config.pressure_grid_size[2]-1], [20,1,20], &state.velocity_x, &state.velocity_y, &state.velocity_z));
         match renderer.await_request(){
           RenderResult::NextStep => {}
-           RenderResult::Shutdown=>{return}
+           RenderResult::Shutdown=>{return Ok(())}
         };
-        i=i+1;
     }
-    println!("Simulation finished");
 }
-fn initialize_pressure_grid(pressure_grid: &mut [[[f32; PRESSUREGRIDSIZE[2]];PRESSUREGRIDSIZE[1]];PRESSUREGRIDSIZE[0]]){
-    for x in 0..(PRESSUREGRIDSIZE[0]-1){//velocty_grid has PRESSUREGRIDSIZE[dimension] elements, so loop from 0 to PRESSUREGRIDSIZE[dimension]-1.
-        for y in 0..(PRESSUREGRIDSIZE[1]-1){
-            for z in 0..(PRESSUREGRIDSIZE[2]-1){
+
+//Run the simulation without a window, for batch jobs and tests.
+//The simulation stops after max_steps time steps, or earlier as soon as stop_condition returns true for the state and the report after a time step.
+//A time step that fails stops the simulation with its error.
+pub fn run_headless<F: FnMut(&SimulationState, &StepReport) -> bool>(config: &SimulationConfig, max_steps: usize, stop_condition: F) -> Result<SimulationState, SolverError>{
+    return resume_headless(SimulationState::new(config)?, config, max_steps, stop_condition);
+}
+
+//Like run_headless, but continue from a state, e.g. one read from a checkpoint. max_steps counts the time steps of this run.
+pub fn resume_headless<F: FnMut(&SimulationState, &StepReport) -> bool>(mut state: SimulationState, config: &SimulationConfig, max_steps: usize, mut stop_condition: F) -> Result<SimulationState, SolverError>{
+    for _ in 0..max_steps{
+        let report = simulation_time_step(&mut state, config)?;
+        if stop_condition(&state, &report){
+            break;
+        }
+    }
+    return Ok(state);
+}
+
+fn initialize_pressure_grid(pressure_grid: &mut PressureGrid, config: &SimulationConfig){
+    let ghost=pressure_grid.ghost();
+    for x in pressure_grid.domain_range(0){
+        for y in pressure_grid.domain_range(1){
+            for z in pressure_grid.domain_range(2){
                 //The pressure should be the atmosferic pressure(101,325Pa) plus the pressure that is exercised by the water above a point on the water at that point. 
-                pressure_grid[x][y][z]=ATMOSPHERIC_PRESSURE-DENSITY*(PRESSUREGRIDSIZE[2] as f32 - z as f32)*GRIDELEMENTSCALE*EXTERNALFORCE[2];
+                pressure_grid[[x,y,z]]=config.atmospheric_pressure-config.density*(config.pressure_grid_size[2] as f32 - (z-ghost) as f32)*config.grid_element_scale*config.external_force[2];
             }
         }
     }
+    boundary::wrap_periodic_field(pressure_grid, config);
+    boundary::set_pressure_boundary_conditions(pressure_grid, config);
 }
 
-fn simulation_time_step(velocity_grid_x: &mut Box<VelocityGrid>, velocity_grid_y: &mut Box<VelocityGrid>, velocity_grid_z: &mut Box<VelocityGrid>,  pressure_grid: &mut Box<[[[f32; PRESSUREGRIDSIZE[2]];PRESSUREGRIDSIZE[1]];PRESSUREGRIDSIZE[0]]>, time_step: i32) -> Vec<Vec<Vec<([f32;3],[f32;3])>>>{
-    let mut color_grid: Box<[[[[f32; 3]; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]> = Box::new([[[[0.0; 3]; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]);
-        //let direction_has_changed=false;
-        //1) Predict u, v and w,
-        let mut provisional_velocity_x = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 2]; PRESSUREGRIDSIZE[1] + 2]; PRESSUREGRIDSIZE[0] + 1], dimension: 0 });
-        let mut provisional_velocity_y = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 2]; PRESSUREGRIDSIZE[1] + 1]; PRESSUREGRIDSIZE[0] + 2], dimension: 1 });
-        let mut provisional_velocity_z = Box::new(VelocityGrid { grid: vec![vec![vec![0.0; PRESSUREGRIDSIZE[2] + 1]; PRESSUREGRIDSIZE[1] + 2]; PRESSUREGRIDSIZE[0] + 2], dimension: 2 });
-    
-        //x-velocity
-        predict_velocity(&mut provisional_velocity_x, &velocity_grid_x, &velocity_grid_y, &velocity_grid_z, pressure_grid);
-        //y-velocity
-        predict_velocity(&mut provisional_velocity_y, &velocity_grid_y, &velocity_grid_x, &velocity_grid_z, pressure_grid);
-        //z-velocity
-        predict_velocity(&mut provisional_velocity_z, &velocity_grid_z, &velocity_grid_x, &velocity_grid_y, pressure_grid);
-        
-        //2)Update boundary conditions(i.e. set walls)
-        set_wall_boundary_conditions( &mut provisional_velocity_x,  &mut provisional_velocity_y,  &mut provisional_velocity_z, 1.0, time_step, &mut color_grid);
-        let i:&mut i32=&mut 0;
-    while *i<MAXITERATIONSPERTIMEFRAME {
+//Give all velocities inside the domain the initial velocity, the velocities on the walls are set by the boundary conditions in the first time step
+fn initialize_velocity_grid(velocity_grid: &mut VelocityGrid, config: &SimulationConfig){
+    for x in solved_range(velocity_grid, 0, config){
+        for y in solved_range(velocity_grid, 1, config){
+            for z in solved_range(velocity_grid, 2, config){
+                velocity_grid.grid[[x,y,z]]=config.initial_velocity[velocity_grid.dimension];
+            }
+        }
+    }
+}
+
+//config.threads threads, or one thread per core when it is 0
+//The loops over the grids run in parallel on the threads of the simulation. Every value is calculated the same way no matter how many threads there are, so the results do not depend on the number of threads.
+fn create_thread_pool(config: &SimulationConfig) -> Result<ThreadPool, SolverError>{
+    return ThreadPoolBuilder::new().num_threads(config.threads).build().map_err(|error| SolverError::InvalidConfig{field: "threads", message: error.to_string()});
+}
+
+//Calculate the next time step of a simulation with this config.
+//After an error the state is left somewhere in the failed time step, it should not be stepped further.
+pub fn simulation_time_step(state: &mut SimulationState, config: &SimulationConfig) -> Result<StepReport, SolverError>{
+    let start=Instant::now();
+    let thread_pool=state.thread_pool.clone();
+    let mut report=thread_pool.install(|| {
+        let (time_step_size, limit)=time_step::choose_time_step(state, config);
+        state.time_step_size=time_step_size;
+        state.time_step_limit=limit;
+        time_step::check_cfl_number(state, time_step_size, config)?;
+        return calculate_time_step(state, Step{index: state.time_step, time: state.time, dt: time_step_size}, config);
+    })?;
+    report.time_step_limit=state.time_step_limit;
+    report.phase_times.total=start.elapsed();
+    return Ok(report);
+}
+
+fn calculate_time_step(state: &mut SimulationState, step: Step, config: &SimulationConfig) -> Result<StepReport, SolverError>{
+    let SimulationState{velocity_x: velocity_grid_x, velocity_y: velocity_grid_y, velocity_z: velocity_grid_z, pressure_grid, solid_mask, color_grid, poisson_problem, pressure_solver, diffusion_problems, previous_tendency, ..}=state;
+    let time_step=step.index;
+    color_grid.fill([0.0; 3]);
+    let mut phase_times=PhaseTimes::default();
+    //1) Predict u, v and w,
+    //With semi-Lagrangian advection the velocities are carried first, the prediction then starts from the carried velocities
+    let advected;
+    let [last_x, last_y, last_z]=match config.convection_scheme{
+        ConvectionScheme::SemiLagrangian{correction} => {
+            let span=PhaseSpan::enter("solver", "advect", time_step);
+            advected=semi_lagrangian::advect_velocities([&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z], solid_mask, correction, step.dt, config);
+            phase_times.advection=span.finish();
+            [&advected[0], &advected[1], &advected[2]]
+        }
+        _ => [&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z],
+    };
+    let span=PhaseSpan::enter("solver", "predict", time_step);
+    let ([mut provisional_velocity_x, mut provisional_velocity_y, mut provisional_velocity_z], tendency)=time_integration::predict_velocities(PredictionFields{velocities: [last_x, last_y, last_z], pressure_grid, solid_mask}, [&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z], previous_tendency.as_ref(), step, color_grid, config);
+    phase_times.prediction=span.finish();
+    //The implicit part of the diffusion
+    let mut diffusion=None;
+    if let Some([problem_x, problem_y, problem_z])=diffusion_problems{
+        let span=PhaseSpan::enter("solver", "diffuse", time_step);
+        diffusion=Some([
+            problem_x.solve(&mut provisional_velocity_x, last_x, step, config)?,
+            problem_y.solve(&mut provisional_velocity_y, last_y, step, config)?,
+            problem_z.solve(&mut provisional_velocity_z, last_z, step, config)?,
+        ]);
+        phase_times.diffusion=span.finish();
+    }
+    //A blown up prediction would only show up as a pressure that does not converge
+    check_finite_velocities([&provisional_velocity_x, &provisional_velocity_y, &provisional_velocity_z], time_step)?;
+
+    //2)Update boundary conditions(i.e. set walls)
+    let span=PhaseSpan::enter("boundary", "boundary", time_step);
+    boundary::set_wall_boundary_conditions(&mut provisional_velocity_x, &mut provisional_velocity_y, &mut provisional_velocity_z, [&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z], step, color_grid, config);
+    obstacle::set_obstacle_boundary_conditions(&mut provisional_velocity_x, &mut provisional_velocity_y, &mut provisional_velocity_z, solid_mask, config);
+    phase_times.boundary=span.finish();
+    let span=PhaseSpan::enter("solver", "project", time_step);
+    let mut divergence_history=Vec::new();
+    let provisional_velocities=[&mut provisional_velocity_x, &mut provisional_velocity_y, &mut provisional_velocity_z];
+    let grids=ProjectionGrids{velocity_grids: [&mut *velocity_grid_x, &mut *velocity_grid_y, &mut *velocity_grid_z], pressure_grid: &mut *pressure_grid, solid_mask, color_grid};
+    let (iterations, divergence)=match pressure_solver{
+        None => correct_pressure_locally(provisional_velocities, grids, &mut divergence_history, step, config)?,
+        Some(pressure_solver) => project_velocities(pressure_solver.as_mut(), poisson_problem, provisional_velocities, grids, &mut divergence_history, step, config)?,
+    };
+    phase_times.pressure=span.finish();
+    if let Some(point)=find_not_finite(pressure_grid){
+        return Err(SolverError::NotFinite{time_step, field: "pressure", point});
+    }
+    state.previous_tendency=tendency;
+    state.time_step+=1;
+    state.time+=step.dt;
+    log::debug!(target: "solver", "Time step {}: {} s, {} iterations, divergence {} 1/s (L2 norm {}, root mean square {})", time_step, step.dt, iterations, divergence.max, divergence.l2, divergence.root_mean_square);
+    return Ok(StepReport{
+        time_step,
+        time: state.time,
+        time_step_size: step.dt,
+        time_step_limit: state.time_step_limit,
+        iterations,
+        divergence,
+        divergence_history,
+        diffusion,
+        kinetic_energy: report::kinetic_energy([&state.velocity_x, &state.velocity_y, &state.velocity_z], &state.solid_mask, config),
+        phase_times,
+    });
+}
+
+//The grids of the state that the projection changes: the velocities that the projected velocities replace, the pressure and the colours of the patches.
+//The obstacles are only read.
+struct ProjectionGrids<'a>{
+    velocity_grids: [&'a mut VelocityGrid; 3],
+    pressure_grid: &'a mut PressureGrid,
+    solid_mask: &'a SolidMask,
+    color_grid: &'a mut ColorGrid,
+}
+
+//The original scheme: the pressure in every cell is corrected with the divergence of that cell only, and this is repeated until the continuity equation holds everywhere
+fn correct_pressure_locally(provisional_velocities: [&mut VelocityGrid; 3], grids: ProjectionGrids, divergence_history: &mut Vec<ResidualNorms>, step: Step, config: &SimulationConfig) -> Result<(usize, ResidualNorms), SolverError>{
+    let [provisional_velocity_x, provisional_velocity_y, provisional_velocity_z]=provisional_velocities;
+    let ProjectionGrids{velocity_grids: [velocity_grid_x, velocity_grid_y, velocity_grid_z], pressure_grid, solid_mask, color_grid}=grids;
+    divergence_history.push(divergence_norms(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, config));
+    for iteration in 0..config.max_iterations_per_time_frame as usize{
         //3)Calculate pressure correction
-        let pressure_correction: Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>=calculate_pressure_correction(&provisional_velocity_x, &provisional_velocity_y, &provisional_velocity_z);
-        println!("Pressure correction at (1, 1, 1): {}, pressure correction at (6, 6, 2): {}", pressure_correction[1][1][1], pressure_correction[6][6][2]);
-        println!("Pressure at (1, 1, 1): {}, pressure at (6, 6, 2): {}", pressure_grid[1][1][1], pressure_grid[6][6][2]);
+        let pressure_correction: PressureGrid=calculate_pressure_correction(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, step.dt, config);
         //4)Update u and v
-        update_velocity_field(&mut provisional_velocity_x, &pressure_correction);
-        update_velocity_field(&mut provisional_velocity_y, &pressure_correction);
-        update_velocity_field(&mut provisional_velocity_z, &pressure_correction);
+        update_velocity_field(provisional_velocity_x, &pressure_correction, solid_mask, step.dt, config);
+        update_velocity_field(provisional_velocity_y, &pressure_correction, solid_mask, step.dt, config);
+        update_velocity_field(provisional_velocity_z, &pressure_correction, solid_mask, step.dt, config);
         
         //5)Update boundary values
-        set_wall_boundary_conditions( &mut provisional_velocity_x,  &mut provisional_velocity_y,  &mut provisional_velocity_z, 1.0, time_step, &mut color_grid);
+        boundary::set_wall_boundary_conditions(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, [&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z], step, color_grid, config);
+        obstacle::set_obstacle_boundary_conditions(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, config);
         
         //6)Check convergence
-        if check_convergence(&provisional_velocity_x, &provisional_velocity_y, &provisional_velocity_z) {// If the continuity equation has converged we can go to the next timestep
-            velocity_grid_x.grid=provisional_velocity_x.grid.clone();
-            velocity_grid_y.grid=provisional_velocity_y.grid.clone();
-            velocity_grid_z.grid=provisional_velocity_z.grid.clone();
-            println!("Finished in {} steps, inflow is {}", i,some_sigmoid_function(time_step));
-            println!("Pressure at (8,8,1) is {} on timestep {}", pressure_grid[8][8][1], time_step);    
-            *i=MAXITERATIONSPERTIMEFRAME;
-        }else{
-            //println!{"convergence has not yet been reached, trying again, iteration: {}, timestep {}", i, time_step};
-            //println!{"Pressure {} correction {} at (8,8,1)", pressure_grid[8][8][1], pressure_correction[8][8][1]};
-            if *i+1==MAXITERATIONSPERTIMEFRAME{//If the continuity equation has not converged after many iterations something probably went wrong. Therefore the program will have to be terminated then.
-                println!("Last iteration {} did not converge", i);
-                std::process::exit(1);
-            }
-            
+        let divergence=divergence_norms(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, config);
+        divergence_history.push(divergence);
+        if divergence.max<=config.allowed_error {// If the continuity equation has converged we can go to the next timestep
+            velocity_grid_x.grid.copy_from(&provisional_velocity_x.grid);
+            velocity_grid_y.grid.copy_from(&provisional_velocity_y.grid);
+            velocity_grid_z.grid.copy_from(&provisional_velocity_z.grid);
+            //7) Update pressure
+            update_pressure(pressure_grid, &pressure_correction);
+            boundary::set_pressure_boundary_conditions(pressure_grid, config);
+            return Ok((iteration+1, divergence));
         }
-        *i=*i+1;
-        //println!("i is {}", i);
         //7) Update pressure
         update_pressure(pressure_grid, &pressure_correction);
-    }  
-    return convert_velocities_to_collocated_grid_and_visualise([0,4,0], [PRESSUREGRIDSIZE[0]-1, 4, PRESSUREGRIDSIZE[2]-1], [20,1,20], velocity_grid_x, velocity_grid_y, velocity_grid_z, color_grid);
+        boundary::set_pressure_boundary_conditions(pressure_grid, config);
+    }
+    //If the continuity equation has not converged after many iterations something probably went wrong. Therefore the simulation has to stop then.
+    let (divergence, cell)=largest_divergence(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, |dimension| checked_range(dimension, config), config);
+    return Err(SolverError::PressureNotConverged{time_step: step.index, iterations: config.max_iterations_per_time_frame as usize, divergence, cell});
+}
+
+//Solve the pressure Poisson equation for the whole domain at once, one correction makes the velocities free of divergence
+fn project_velocities(pressure_solver: &mut dyn LinearSolver, poisson_problem: &solver::PoissonProblem, provisional_velocities: [&mut VelocityGrid; 3], grids: ProjectionGrids, divergence_history: &mut Vec<ResidualNorms>, step: Step, config: &SimulationConfig) -> Result<(usize, ResidualNorms), SolverError>{
+    let [provisional_velocity_x, provisional_velocity_y, provisional_velocity_z]=provisional_velocities;
+    let ProjectionGrids{velocity_grids: [velocity_grid_x, velocity_grid_y, velocity_grid_z], pressure_grid, solid_mask, color_grid}=grids;
+    //3)Solve for the pressure correction
+    let divergence=stencil::scaled_divergence(&provisional_velocity_x.grid, &provisional_velocity_y.grid, &provisional_velocity_z.grid, solid_mask, 1.0, config);
+    let (mut pressure_correction, statistics)=solver::solve_pressure_correction(pressure_solver, poisson_problem, &divergence, divergence_history, step.dt, config);
+    boundary::wrap_periodic_field(&mut pressure_correction, config);
+    boundary::set_pressure_correction_boundary_conditions(&mut pressure_correction, config);
+    //4)Update u, v and w
+    update_velocity_field(provisional_velocity_x, &pressure_correction, solid_mask, step.dt, config);
+    update_velocity_field(provisional_velocity_y, &pressure_correction, solid_mask, step.dt, config);
+    update_velocity_field(provisional_velocity_z, &pressure_correction, solid_mask, step.dt, config);
+    //5)Update boundary values
+    boundary::set_wall_boundary_conditions(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, [&*velocity_grid_x, &*velocity_grid_y, &*velocity_grid_z], step, color_grid, config);
+    obstacle::set_obstacle_boundary_conditions(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, config);
+    if !statistics.converged{//Like the legacy scheme, a pressure that can not be found means something went wrong
+        let (divergence, cell)=largest_divergence(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, solid_mask, |dimension| GHOST_LAYERS..GHOST_LAYERS+config.pressure_grid_size[dimension], config);
+        return Err(SolverError::PressureNotConverged{time_step: step.index, iterations: statistics.iterations, divergence, cell});
+    }
+    //6)Update pressure
+    update_pressure(pressure_grid, &pressure_correction);
+    boundary::set_pressure_boundary_conditions(pressure_grid, config);
+    velocity_grid_x.grid.copy_from(&provisional_velocity_x.grid);
+    velocity_grid_y.grid.copy_from(&provisional_velocity_y.grid);
+    velocity_grid_z.grid.copy_from(&provisional_velocity_z.grid);
+    return Ok((statistics.iterations, statistics.norms()));
 }
 
 //min_coords and max_coords are the pressure coordinates of which we want to know the velocities(this function will determine those velocities by taking the average of nearby velocities)
 //data_grid_point_size is the size of the grid we want to show to the user
-pub fn convert_velocities_to_collocated_grid_and_visualise(min_coords: [usize; 3], max_coords: [usize;3], data_grid_point_size: [usize; 3], velocity_grid_x: &VelocityGrid, velocity_grid_y: &VelocityGrid, velocity_grid_z: &VelocityGrid, color_grid: Box<[[[[f32; 3]; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>) -> Vec<Vec<Vec<([f32;3],[f32;3])>>>{
+pub fn convert_velocities_to_collocated_grid_and_visualise(min_coords: [usize; 3], max_coords: [usize;3], data_grid_point_size: [usize; 3], velocity_grid_x: &VelocityGrid, velocity_grid_y: &VelocityGrid, velocity_grid_z: &VelocityGrid) -> VisualisationGrid{
     let step_size=[calc_step_size(max_coords[0]-min_coords[0], data_grid_point_size[0]), calc_step_size(max_coords[1]-min_coords[1], data_grid_point_size[1]), calc_step_size(max_coords[2]-min_coords[2], data_grid_point_size[2])];
-    let mut return_data: Vec<Vec<Vec<([f32; 3],[f32;3])>>>=vec![vec![vec![([0.0; 3],[0.0,0.0,0.0]); data_grid_point_size[2]]; data_grid_point_size[1]]; data_grid_point_size[0]];
+    let mut return_data: VisualisationGrid=vec![vec![vec![([0.0; 3],[0.0,0.0,0.0]); data_grid_point_size[2]]; data_grid_point_size[1]]; data_grid_point_size[0]];
     //At first. determine the maximum current velocity
     let mut max_vel_squared=0.0;
     for x in 0..data_grid_point_size[0]{
@@ -122,7 +396,7 @@ pub fn convert_velocities_to_collocated_grid_and_visualise(min_coords: [usize; 3
                 let vel_x=get_velocity_at_pressure_point(&velocity_grid_x, x*step_size[0], y*step_size[1], z*step_size[2]);
                 let vel_y=get_velocity_at_pressure_point(&velocity_grid_y, x*step_size[0], y*step_size[1], z*step_size[2]);
                 let vel_z=get_velocity_at_pressure_point(&velocity_grid_z, x*step_size[0], y*step_size[1], z*step_size[2]);
-                if(vel_x.powf(2.0)+vel_y.powf(2.0)+vel_z.powf(2.0)>max_vel_squared){
+                if vel_x.powf(2.0)+vel_y.powf(2.0)+vel_z.powf(2.0)>max_vel_squared{
                     max_vel_squared=vel_x.powf(2.0)+vel_y.powf(2.0)+vel_z.powf(2.0);
                 }
             }
@@ -135,7 +409,7 @@ pub fn convert_velocities_to_collocated_grid_and_visualise(min_coords: [usize; 3
                 let vel_x=get_velocity_at_pressure_point(&velocity_grid_x, x*step_size[0], y*step_size[1], z*step_size[2]);
                 let vel_y=get_velocity_at_pressure_point(&velocity_grid_y, x*step_size[0], y*step_size[1], z*step_size[2]);
                 let vel_z=get_velocity_at_pressure_point(&velocity_grid_z, x*step_size[0], y*step_size[1], z*step_size[2]);
-                return_data[x][y][z]=([vel_x,  vel_y, vel_z], [1.0, 1.0- ((vel_x.powf(2.0)+vel_y.powf(2.0)+vel_z.powf(2.0))/max_vel_squared).sqrt(), 0.0,]/*color_grid[x *step_size[0]+min_coords[0]][y* step_size[1]+min_coords[1]][z*step_size[2]+min_coords[2]]*/);
+                return_data[x][y][z]=([vel_x,  vel_y, vel_z], [1.0, 1.0- ((vel_x.powf(2.0)+vel_y.powf(2.0)+vel_z.powf(2.0))/max_vel_squared).sqrt(), 0.0,]);
                 }
         }
     }
@@ -151,239 +425,226 @@ fn calc_step_size(from_dimension: usize, to_dimension: usize)->usize{
 } 
 
 
-fn predict_velocity(provisonal_velocity_field: &mut VelocityGrid, velocity_field_last_time_step: &VelocityGrid, orthogonal_velocity_field_a: &VelocityGrid, orthogonal_velocity_field_b: &VelocityGrid, pressure_grid: &Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>){
-    let dim=get_dimension(provisonal_velocity_field.dimension);
-    for x in 1..(PRESSUREGRIDSIZE[0]-dim[0]+1) {
-        for y in 1..(PRESSUREGRIDSIZE[1]-dim[1]+1) {
-            for z in 1..(PRESSUREGRIDSIZE[2]-dim[2]+1) {
-                //Diffusion term
-                let diffusion=VISCOSITY*(laplacian(velocity_field_last_time_step, x, y, z));
-                //And finally, the provisional velocity
-                provisonal_velocity_field.grid[x][y][z]=velocity_field_last_time_step.grid[x][y][z]+TIMESTEPSIZE/DENSITY*(-convection_term(velocity_field_last_time_step, orthogonal_velocity_field_a, orthogonal_velocity_field_b, x, y, z)-first_order_central_spatial_pressure_derivative(&pressure_grid, x-1, y-1, z-1, velocity_field_last_time_step.dimension)+diffusion+DENSITY*EXTERNALFORCE[velocity_field_last_time_step.dimension]);
+fn predict_velocity(provisonal_velocity_field: &mut VelocityGrid, fields: PredictionFields, dt: f32, config: &SimulationConfig){
+    //Only the velocities inside the domain are predicted, the velocities on the walls are set by the boundary conditions
+    let dimension=provisonal_velocity_field.dimension;
+    let (range_x, range_y, range_z)=(solved_range(provisonal_velocity_field, 0, config), solved_range(provisonal_velocity_field, 1, config), solved_range(provisonal_velocity_field, 2, config));
+    let row_length=provisonal_velocity_field.grid.shape()[2];
+    provisonal_velocity_field.grid.par_layers_mut(range_x).for_each(|(x, layer)| {
+        //The vectorized kernel only has central convection, the other schemes are calculated point by point
+        if config.stencil_kernels==StencilKernels::Vectorized && matches!(config.convection_scheme, ConvectionScheme::Central | ConvectionScheme::SemiLagrangian{..}){
+            stencil::predict_velocity_layer(layer, x, [range_y.clone(), range_z.clone()], fields, dimension, dt, config);
+            return;
+        }
+        for y in range_y.clone() {
+            for z in range_z.clone() {
+                //The velocities on and inside obstacles are set by the obstacle boundary conditions
+                if obstacle::is_solid_face(fields.solid_mask, dimension, x, y, z){
+                    continue;
+                }
+                layer[y*row_length+z]=predict_velocity_at_point(fields, dimension, [x, y, z], dt, config);
             }
         }
+    });
+    //Except for the velocities through patches with a fixed pressure, these follow from the pressure just like the velocities inside
+    for [x, y, z] in boundary::solved_wall_faces(provisonal_velocity_field, config){
+        provisonal_velocity_field.grid[[x,y,z]]=predict_velocity_at_point(fields, dimension, [x, y, z], dt, config);
     }
 }
 
-fn calculate_pressure_correction(x_velocity: & VelocityGrid, y_velocity: & VelocityGrid, z_velocity: & VelocityGrid)->Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>{
-    let mut pressure_correction: Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>= Box::new([[[0.0; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]);//Here we will store the pressure corrections.
-    let constant_term_pressure_equation=RELEXATION*DENSITY*GRIDELEMENTSCALE/(6.0*TIMESTEPSIZE);//The lower part of the equation is this constant.
-        for i in 0..PRESSUREGRIDSIZE[0] - 1{
-            for j in 0..PRESSUREGRIDSIZE[1] - 1{
-                for k in 0..PRESSUREGRIDSIZE[2] - 1{
-                    pressure_correction[i][j][k]=-constant_term_pressure_equation*(x_velocity.grid[i+1][j+1][k+1] - x_velocity.grid[i][j+1][k+1]+ y_velocity.grid[i+1][j+1][k+1] - y_velocity.grid[i+1][j][k+1] + z_velocity.grid[i+1][j+1][k+1]-z_velocity.grid[i+1][j+1][k]);
-                }
-            }
-        }
+fn predict_velocity_at_point(fields: PredictionFields, dimension: usize, point: [usize; 3], dt: f32, config: &SimulationConfig) -> f32{
+    let [x, y, z]=point;
+    let (velocity_field_last_time_step, orthogonal_velocity_field_a, orthogonal_velocity_field_b)=fields.velocity_and_orthogonal(dimension);
+    //Diffusion term
+    let diffusion=(1.0-config.diffusion_scheme.implicit_fraction())*config.viscosity*(laplacian(velocity_field_last_time_step, x, y, z, config));
+    //And finally, the provisional velocity
+    return velocity_field_last_time_step.grid[[x,y,z]]+dt/config.density*(-convection_term(velocity_field_last_time_step, orthogonal_velocity_field_a, orthogonal_velocity_field_b, x, y, z, config)-first_order_central_spatial_pressure_derivative(fields.pressure_grid, x, y, z, velocity_field_last_time_step.dimension, config)+diffusion+config.density*config.external_force[velocity_field_last_time_step.dimension]);
+}
+
+fn calculate_pressure_correction(x_velocity: & VelocityGrid, y_velocity: & VelocityGrid, z_velocity: & VelocityGrid, solid_mask: &SolidMask, dt: f32, config: &SimulationConfig)->PressureGrid{
+    let constant_term_pressure_equation=config.relaxation*config.density*config.grid_element_scale/(6.0*dt);//The lower part of the equation is this constant.
+    //Here we will store the pressure corrections.
+    let mut pressure_correction: PressureGrid=stencil::scaled_divergence(&x_velocity.grid, &y_velocity.grid, &z_velocity.grid, solid_mask, -constant_term_pressure_equation, config);
+    //The faces on a periodic wall are corrected with the cells on both sides of the seam
+    boundary::wrap_periodic_field(&mut pressure_correction, config);
+    //There is no correction on patches with a fixed pressure
+    boundary::set_pressure_correction_boundary_conditions(&mut pressure_correction, config);
     return pressure_correction;
 }
 
-fn convection_term(velocity_field_last_time_step: &VelocityGrid,orthogonal_velocity_field_a: &VelocityGrid, orthogonal_velocity_field_b: &VelocityGrid, x: usize, y:usize, z:usize ) -> f32{// calculate the convection term
-     return DENSITY*(velocity_field_last_time_step.grid[x][y][z]*second_order_spatial_derivative(&velocity_field_last_time_step, x, y, z, velocity_field_last_time_step.dimension)
-                +get_velocity_from_orthogonal_grid(&orthogonal_velocity_field_a, x, y, z, velocity_field_last_time_step.dimension)*second_order_spatial_derivative(velocity_field_last_time_step, x, y, z, orthogonal_velocity_field_a.dimension)
-                +get_velocity_from_orthogonal_grid(&orthogonal_velocity_field_b, x, y, z, velocity_field_last_time_step.dimension)*second_order_spatial_derivative(velocity_field_last_time_step, x, y, z, orthogonal_velocity_field_b.dimension));
+fn convection_term(velocity_field_last_time_step: &VelocityGrid,orthogonal_velocity_field_a: &VelocityGrid, orthogonal_velocity_field_b: &VelocityGrid, x: usize, y:usize, z:usize , config: &SimulationConfig) -> f32{// calculate the convection term
+    let velocity=velocity_field_last_time_step.grid[[x,y,z]];
+    let velocity_a=get_velocity_from_orthogonal_grid(&orthogonal_velocity_field_a, x, y, z, velocity_field_last_time_step.dimension);
+    let velocity_b=get_velocity_from_orthogonal_grid(&orthogonal_velocity_field_b, x, y, z, velocity_field_last_time_step.dimension);
+     return config.density*(velocity*convection::convective_derivative(velocity_field_last_time_step, x, y, z, velocity_field_last_time_step.dimension, velocity, config)
+                +velocity_a*convection::convective_derivative(velocity_field_last_time_step, x, y, z, orthogonal_velocity_field_a.dimension, velocity_a, config)
+                +velocity_b*convection::convective_derivative(velocity_field_last_time_step, x, y, z, orthogonal_velocity_field_b.dimension, velocity_b, config));
 }
 
-fn update_velocity_field(velocity_field: &mut VelocityGrid, pressure_correction : &[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]){
+fn update_velocity_field(velocity_field: &mut VelocityGrid, pressure_correction : &PressureGrid, solid_mask: &SolidMask, dt: f32, config: &SimulationConfig){
     let dim=get_dimension(velocity_field.dimension);
-    let constant_term_velocity_equation=TIMESTEPSIZE/(DENSITY*GRIDELEMENTSCALE);
-    for i in 1..PRESSUREGRIDSIZE[0]+1-dim[0]{
-        for j in 1..PRESSUREGRIDSIZE[1]+1-dim[1]{
-            for k in 1..PRESSUREGRIDSIZE[2]+1-dim[2]{
-                velocity_field.grid[i][j][k]=velocity_field.grid[i][j][k]-constant_term_velocity_equation*(pressure_correction[i+dim[0]-1][j+dim[1]-1][k+dim[2]-1]- pressure_correction[i-1][j-1][k-1]);
+    let constant_term_velocity_equation=dt/(config.density*config.grid_element_scale);
+    let dimension=velocity_field.dimension;
+    let (range_i, range_j, range_k)=(solved_range(velocity_field, 0, config), solved_range(velocity_field, 1, config), solved_range(velocity_field, 2, config));
+    let row_length=velocity_field.grid.shape()[2];
+    velocity_field.grid.par_layers_mut(range_i).for_each(|(i, layer)| {
+        for j in range_j.clone(){
+            for k in range_k.clone(){
+                if obstacle::is_solid_face(solid_mask, dimension, i, j, k){
+                    continue;
+                }
+                //The face (i,j,k) lies between the cells (i,j,k)-dim and (i,j,k)
+                layer[j*row_length+k]-=constant_term_velocity_equation*(pressure_correction[[i,j,k]]- pressure_correction[[i-dim[0],j-dim[1],k-dim[2]]]);
             }
         }
+    });
+    for [i, j, k] in boundary::solved_wall_faces(velocity_field, config){
+        velocity_field.grid[[i,j,k]]-=constant_term_velocity_equation*(pressure_correction[[i,j,k]]- pressure_correction[[i-dim[0],j-dim[1],k-dim[2]]]);
     }
 }
 
-fn update_pressure(pressure_grid: &mut [[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]], pressure_correction: &[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]){
-    for i in 0..PRESSUREGRIDSIZE[0]{
-        for j in 0..PRESSUREGRIDSIZE[1]{
-            for k in 0..PRESSUREGRIDSIZE[2]{
-                pressure_grid[i][j][k]=pressure_grid[i][j][k]+pressure_correction[i][j][k];
-            }
-        }
-    }
+fn update_pressure(pressure_grid: &mut PressureGrid, pressure_correction: &PressureGrid){
+    pressure_grid.as_mut_slice().par_iter_mut().zip(pressure_correction.as_slice().par_iter()).for_each(|(pressure, correction)| {
+        *pressure+=correction;
+    });
 }
 
-fn check_convergence_at_point(provisional_velocity_x: &VelocityGrid, provisional_velocity_y: &VelocityGrid, provisional_velocity_z: &VelocityGrid, x:usize, y:usize, z:usize)->f32{
-    return first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_x, x, y, z)
-    +first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_y, x, y, z)
-    +first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_z, x, y, z);
+fn check_convergence_at_point(provisional_velocity_x: &VelocityGrid, provisional_velocity_y: &VelocityGrid, provisional_velocity_z: &VelocityGrid, x:usize, y:usize, z:usize, config: &SimulationConfig)->f32{
+    return first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_x, x, y, z, config)
+    +first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_y, x, y, z, config)
+    +first_order_central_spatial_derivative_at_pressure_coordinates(&provisional_velocity_z, x, y, z, config);
 }
 
-fn check_convergence(provisional_velocity_x:&VelocityGrid, provisional_velocity_y: &VelocityGrid, provisional_velocity_z: &VelocityGrid)->bool{
-    
-    for x in 1..PRESSUREGRIDSIZE[0]-1{
-        for y in 1..PRESSUREGRIDSIZE[1]-1{
-            for z in 1..PRESSUREGRIDSIZE[2]-1{
-                let error=check_convergence_at_point(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, x, y, z);   
-                if error.abs()>ALLOWEDERROR{
-                    //println!("Convergence not yet reached, error is {} at ({}, {}, {})", error, x, y, z );
-                    return false;
-
+//The divergence in 1/s of the fluid cells that the legacy scheme checks
+fn divergence_norms(provisional_velocity_x: &VelocityGrid, provisional_velocity_y: &VelocityGrid, provisional_velocity_z: &VelocityGrid, solid_mask: &SolidMask, config: &SimulationConfig) -> ResidualNorms{
+    //The cells inside obstacles are not checked
+    //Every layer is measured in parallel and the layers are added in order, so the answer does not depend on the number of threads
+    let layers: Vec<(f32, f64, usize)>=checked_range(0, config).into_par_iter().map(|x| {
+        let (mut max, mut sum_of_squares, mut cells)=(0.0f32, 0.0f64, 0);
+        for y in checked_range(1, config){
+            for z in checked_range(2, config){
+                if solid_mask[[x,y,z]]{
+                    continue;
                 }
+                let error=check_convergence_at_point(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, x, y, z, config);
+                max=max.max(error.abs());
+                sum_of_squares+=(error as f64).powi(2);
+                cells+=1;
             }
         }
-    } 
-    return true;
+        (max, sum_of_squares, cells)
+    }).collect();
+    let max=layers.iter().fold(0.0f32, |max, layer| max.max(layer.0));
+    let sum_of_squares: f64=layers.iter().map(|layer| layer.1).sum();
+    let cells: usize=layers.iter().map(|layer| layer.2).sum();
+    return ResidualNorms::from_sum_of_squares(max, sum_of_squares, cells);
 }
 
-//Set the wall boundary conditions
-fn set_wall_boundary_conditions(velocity_grid_x: &mut VelocityGrid, velocity_grid_y: &mut VelocityGrid, velocity_grid_z: &mut VelocityGrid, x_wall_velocity: f32, time_step:i32, color_grid: &mut Box<[[[[f32; 3]; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>){
-    set_boundary_conditions_of_two_parallel_walls(velocity_grid_x, velocity_grid_y, velocity_grid_z, 0.0);
-    set_boundary_conditions_of_two_parallel_walls(velocity_grid_y, velocity_grid_x, velocity_grid_z, 0.0);
-    set_boundary_conditions_of_two_parallel_walls(velocity_grid_z, velocity_grid_x, velocity_grid_y, 0.0);
-    create_inflow_or_outflow(velocity_grid_x, velocity_grid_y, velocity_grid_z, [0,22,22], [0,28,28], -some_sigmoid_function(time_step), color_grid);
-    create_inflow_or_outflow(velocity_grid_z, velocity_grid_y, velocity_grid_x, [22,22,0], [28,28,0], some_sigmoid_function(time_step), color_grid);
-} 
-
-fn some_sigmoid_function_f(time_step: f32)->f32{
-    return 1.0/(f32::powf(2.7182818, 3.0-time_step)+1.0);
-}
-
-fn some_sigmoid_function(time_step: i32)->f32{
-    let t=time_step as f32;
-    return 0.1;//1.0/(f32::powf(2.7182818, 3.0-t)+1.0);
-}
-
-fn set_boundary_conditions_of_two_parallel_walls(orthogonal_velocity_grid: &mut VelocityGrid, parallel_velocity_grid_a: &mut VelocityGrid, parallel_velocity_grid_b: &mut VelocityGrid, orthogonal_velocity_grid_value: f32){
-    let dim= get_dimension(orthogonal_velocity_grid.dimension);
-    //Set the max positions, the position coordinate orthogonal to the wall will be set to zero later
-    let mut max_orthogonal_coords=[PRESSUREGRIDSIZE[0]+1, PRESSUREGRIDSIZE[1]+1, PRESSUREGRIDSIZE[2]+1];//max coordinates for orthogonal velocities  
-    let mut max_parallel_coords=PRESSUREGRIDSIZE;//Max coordinates for parallel velocities
-    //The coordinates of one wall have coordinate zero in one dimension
-    max_orthogonal_coords[orthogonal_velocity_grid.dimension]=0;//Take the wall that has the 0 coordinate in one direction
-    max_parallel_coords[orthogonal_velocity_grid.dimension]=0;// The sizes of the parallel grids are the same in the other dimensions, so we will loop through the same values.
-    //Set boundary conditions for the zero wall
-    set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid, [0,0,0], max_orthogonal_coords, orthogonal_velocity_grid_value);
-    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_a, [0,0,0], max_parallel_coords, false, orthogonal_velocity_grid.dimension);
-    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_b, [0,0,0], max_parallel_coords, false, orthogonal_velocity_grid.dimension);
-    //The other wall has one coordinate at the maximum, so set that coordinate to the maximum
-    max_orthogonal_coords[orthogonal_velocity_grid.dimension]=PRESSUREGRIDSIZE[orthogonal_velocity_grid.dimension];
-    max_parallel_coords[orthogonal_velocity_grid.dimension]=PRESSUREGRIDSIZE[orthogonal_velocity_grid.dimension]+1;
-    let minimum_parallel_coords=[(PRESSUREGRIDSIZE[0]+1)*dim[0], (PRESSUREGRIDSIZE[1]+1)*dim[1], (PRESSUREGRIDSIZE[2]+1)*dim[2]];
-    let minimum_orthogonal_coords=[PRESSUREGRIDSIZE[0]*dim[0],PRESSUREGRIDSIZE[1]*dim[1], PRESSUREGRIDSIZE[2]*dim[2]];
-    //Set boundary conditions for the maximum wall
-    set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid, minimum_orthogonal_coords, max_orthogonal_coords, orthogonal_velocity_grid_value);
-    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_a, minimum_parallel_coords, max_parallel_coords, true, orthogonal_velocity_grid.dimension);
-    set_parallel_boundary_condition_at_wall(parallel_velocity_grid_b, minimum_parallel_coords, max_parallel_coords, true, orthogonal_velocity_grid.dimension);
-    
-
+//The storage coordinates of the cells that the legacy scheme checks in a dimension: the cells next to the walls are not checked, periodic dimensions do not have walls
+fn checked_range(dimension: usize, config: &SimulationConfig) -> Range<usize>{
+    let ghost=GHOST_LAYERS;
+    return if config.periodic[dimension] {ghost..ghost+config.pressure_grid_size[dimension]} else {ghost+1..ghost+config.pressure_grid_size[dimension]-1};
 }
 
-
-//Set the orthogonal velocity to a certain value on a wall
-fn set_orthogonal_boundary_condition_at_wall(orthogonal_velocity_grid: &mut VelocityGrid, min_coords: [usize; 3], max_coords: [usize; 3], value: f32){
-    for x in min_coords[0]..=max_coords[0]{
-        for y in min_coords[1]..=max_coords[1]{
-            for z in min_coords[2]..=max_coords[2]{
-                orthogonal_velocity_grid.grid[x][y][z]=value;
+//The largest absolute divergence in 1/s of the fluid cells in the storage coordinates of checked_range, and the domain coordinates of the cell where it is
+fn largest_divergence<R: Fn(usize) -> Range<usize> + Sync>(provisional_velocity_x: &VelocityGrid, provisional_velocity_y: &VelocityGrid, provisional_velocity_z: &VelocityGrid, solid_mask: &SolidMask, checked_range: R, config: &SimulationConfig) -> (f32, [usize; 3]){
+    let ghost=GHOST_LAYERS;
+    //Every layer finds its own largest value, the layers are compared in order so the first of equal values wins on any number of threads
+    let layers: Vec<(f32, [usize; 3])>=checked_range(0).into_par_iter().map(|x| {
+        let mut largest=(0.0f32, [x-ghost, 0, 0]);
+        for y in checked_range(1){
+            for z in checked_range(2){
+                if solid_mask[[x,y,z]]{
+                    continue;
+                }
+                let divergence=check_convergence_at_point(provisional_velocity_x, provisional_velocity_y, provisional_velocity_z, x, y, z, config);
+                if is_larger(divergence.abs(), largest.0.abs()){
+                    largest=(divergence, [x-ghost, y-ghost, z-ghost]);
+                }
             }
         }
-    }
+        largest
+    }).collect();
+    return layers.into_iter().fold((0.0, [0, 0, 0]), |largest, layer| if is_larger(layer.0.abs(), largest.0.abs()) {layer} else {largest});
 }
 
-fn create_inflow_or_outflow(orthogonal_velocity_grid: &mut VelocityGrid, parallel_velocity_grid_a: &mut VelocityGrid, parallel_velocity_grid_b: &mut VelocityGrid, min_orthogonal_coords: [usize;3], max_orthogonal_coords: [usize; 3], flow: f32, color_grid: &mut Box<[[[[f32; 3]; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>){
-    let dim =get_dimension(orthogonal_velocity_grid.dimension);
-    
-    for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
-        for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
-            for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
-                orthogonal_velocity_grid.grid[x][y][z]=flow;
-            }
-        }
-    }
-    //For the parallel velocities the size should be one larger in all dimensions
-    for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]+1{
-        for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]+1{
-            for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]+1{
-                parallel_velocity_grid_a.grid[x][y][z]=0.0;
-                parallel_velocity_grid_b.grid[x][y][z]=0.0;
-            }
-        }
-    }
-    //Give the inflow and outflow a color.
-    if(min_orthogonal_coords[orthogonal_velocity_grid.dimension]==0){
-        for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
-            for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
-                for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
-                    color_grid[x+dim[0]-1][y+dim[1]-1][z+dim[2]-1]=[1.0,0.0,0.0];
-                }
-            }
-        }
-    }else{
-        for x in min_orthogonal_coords[0]..=max_orthogonal_coords[0]{
-            for y in min_orthogonal_coords[1]..=max_orthogonal_coords[1]{
-                for z in min_orthogonal_coords[2]..=max_orthogonal_coords[2]{
-                    color_grid[x-1][y-1][z-1]=[1.0,0.0,0.0];
+//Whether value should replace largest when looking for the largest value: NaN counts as larger than any number, and the first NaN is kept
+pub(crate) fn is_larger(value: f32, largest: f32) -> bool{
+    return !largest.is_nan() && (value.is_nan() || value>largest);
+}
+
+//The domain coordinates of the first value of the domain of a field that is NaN or infinite
+fn find_not_finite(field: &Field3D) -> Option<[usize; 3]>{
+    let ghost=field.ghost();
+    let (range_y, range_z)=(field.domain_range(1), field.domain_range(2));
+    return field.domain_range(0).into_par_iter().find_map_first(|x| {
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                if !field[[x,y,z]].is_finite(){
+                    return Some([x-ghost, y-ghost, z-ghost]);
                 }
             }
         }
-    }
-    
-    
+        return None;
+    });
 }
 
-//wall_is_on_lower_side=0 means the wall is on the side with lower coordinates seen from the dry side and wall_is_on_lower_side=1 means the wall is on the side with higher coordinates. 
-//orthogonal_dimension is the dimension number(0 for x, 1 for y, 2 for z) of the dimension orthogonal to the wall
-fn set_parallel_boundary_condition_at_wall(parallel_velocity_grid: &mut VelocityGrid, min_coords: [usize; 3], max_coords: [usize; 3], wall_is_on_lower_side: bool, orthogonal_dimension: usize){
-    let dim=get_dimension(orthogonal_dimension);
-    let transformation_in_one_dimension=1 - 2 * (wall_is_on_lower_side as isize);// -1 when a lower element is needed, +1 when a higher element is needed
-    let transformation_to_neighbor:[isize; 3]=[(dim[0] as isize) * transformation_in_one_dimension, (dim[1] as isize) * transformation_in_one_dimension, (dim[2] as isize) * transformation_in_one_dimension];// This is the transformation to the neighbor opposite of the wall
-    for x in min_coords[0]..=max_coords[0]{
-        for y in min_coords[1]..=max_coords[1]{
-            for z in min_coords[2]..=max_coords[2]{
-                //The parallel velocity should be the opposite of the parallel velocity on the other side of the wall, so that the average is zero.
-                parallel_velocity_grid.grid[x][y][z]=-parallel_velocity_grid.grid[(x as isize + transformation_to_neighbor[0])as usize][(y as isize + transformation_to_neighbor[1]) as usize][(z as isize+transformation_to_neighbor[2]) as usize];          
-            }
+fn check_finite_velocities(velocity_grids: [&VelocityGrid; 3], time_step: i32) -> Result<(), SolverError>{
+    for velocity_grid in velocity_grids{
+        if let Some(point)=find_not_finite(&velocity_grid.grid){
+            return Err(SolverError::NotFinite{time_step, field: ["x velocity", "y velocity", "z velocity"][velocity_grid.dimension], point});
         }
     }
+    return Ok(());
 }
 
-fn first_order_central_spatial_pressure_derivative(f: &Box<[[[f32; PRESSUREGRIDSIZE[2]]; PRESSUREGRIDSIZE[1]]; PRESSUREGRIDSIZE[0]]>, x:usize, y:usize, z:usize, dimension_number:usize) -> f32{
+//The derivative of the pressure at the velocity point (x,y,z), which lies between the pressure cells (x,y,z)-dim and (x,y,z)
+fn first_order_central_spatial_pressure_derivative(f: &PressureGrid, x:usize, y:usize, z:usize, dimension_number:usize, config: &SimulationConfig) -> f32{
     let position_difference=get_dimension(dimension_number);
-    return (f[x+position_difference[0]][y+position_difference[1]][z+position_difference[2]]-f[x][y][z])/GRIDELEMENTSCALE;
-}
-
-fn first_order_forward_spatial_derivative(f: &VelocityGrid, x:usize, y:usize, z:usize) -> f32{
-    let position_difference=get_dimension(f.dimension);
-    return (f.grid[x+position_difference[0]][y+position_difference[1]][z+position_difference[2]]-f.grid[x][y][z])/GRIDELEMENTSCALE;
+    return (f[[x,y,z]]-f[[x-position_difference[0],y-position_difference[1],z-position_difference[2]]])/config.grid_element_scale;
 }
 
-fn first_order_central_spatial_derivative_at_pressure_coordinates(f: &VelocityGrid, x: usize, y: usize, z:usize)->f32{//Calculates the central spatial derivative, uses pressure coordinates
+fn first_order_central_spatial_derivative_at_pressure_coordinates(f: &VelocityGrid, x: usize, y: usize, z:usize, config: &SimulationConfig)->f32{//Calculates the central spatial derivative, uses pressure coordinates
     let dim=get_dimension(f.dimension);
-    return (f.grid[x+1][y+1][z+1]-f.grid[x+1-dim[0]][y+1-dim[1]][z+1-dim[2]])/GRIDELEMENTSCALE;
+    return (f.grid[[x+dim[0],y+dim[1],z+dim[2]]]-f.grid[[x,y,z]])/config.grid_element_scale;
 }
 
-fn second_order_spatial_derivative(f:&VelocityGrid, x: usize, y:usize, z:usize, dimension_number:usize) -> f32{
-    let dim= get_dimension(dimension_number);
-    return (f.grid[x+dim[0]][y+dim[1]][z+dim[2]] - f.grid[x-dim[0]][y-dim[1]][z-dim[2]])/(2.0*GRIDELEMENTSCALE);
-}
-
-fn second_order_second_spatial_derivative(f: &VelocityGrid, x:usize, y:usize, z:usize, dimension_number:usize) -> f32{
+fn second_order_second_spatial_derivative(f: &VelocityGrid, x:usize, y:usize, z:usize, dimension_number:usize, config: &SimulationConfig) -> f32{
     let dim = get_dimension(dimension_number);
-    return (f.grid[x+dim[0]][y+dim[1]][z+dim[2]]-2.0*f.grid[x][y][z]+f.grid[x-dim[0]][y-dim[1]][z-dim[2]])/(GRIDELEMENTSCALE*GRIDELEMENTSCALE);
+    return (f.grid[[x+dim[0],y+dim[1],z+dim[2]]]-2.0*f.grid[[x,y,z]]+f.grid[[x-dim[0],y-dim[1],z-dim[2]]])/(config.grid_element_scale*config.grid_element_scale);
 }
 
 //Laplacian velocity grid
-fn laplacian(f: &VelocityGrid, x:usize, y:usize, z:usize)->f32{
-    return second_order_second_spatial_derivative(f, x, y, z, 0)+second_order_second_spatial_derivative(f, x, y, z, 1)+second_order_second_spatial_derivative(f, x, y, z, 2);
+fn laplacian(f: &VelocityGrid, x:usize, y:usize, z:usize, config: &SimulationConfig)->f32{
+    return second_order_second_spatial_derivative(f, x, y, z, 0, config)+second_order_second_spatial_derivative(f, x, y, z, 1, config)+second_order_second_spatial_derivative(f, x, y, z, 2, config);
 }
 
 //This function will retrieve the velocity of an orthogonal grid a grid point of another grid.
+//The velocity point (x,y,z) of the other grid lies between the cells (x,y,z)-dim_to and (x,y,z), the orthogonal grid has a velocity on the lower and the upper face of both cells.
 fn get_velocity_from_orthogonal_grid(orthogonal_grid: &VelocityGrid, x:usize, y:usize, z:usize, other_grid_dimension:usize) -> f32{
     let dim_to=get_dimension(other_grid_dimension);
     let dim_from=get_dimension(orthogonal_grid.dimension);
-    return 0.25*(orthogonal_grid.grid[x-dim_from[0]][y-dim_from[1]][z-dim_from[2]]//Left down
-        +orthogonal_grid.grid[x-dim_from[0]+dim_to[0]][y-dim_from[1]+dim_to[1]][z-dim_from[2]+dim_to[2]]//left up
-        +orthogonal_grid.grid[x][y][z]//right down
-        +orthogonal_grid.grid[x+dim_to[0]][y+dim_to[1]][z+dim_to[2]]);//right up
+    return 0.25*(orthogonal_grid.grid[[x-dim_to[0],y-dim_to[1],z-dim_to[2]]]//Left down
+        +orthogonal_grid.grid[[x-dim_to[0]+dim_from[0],y-dim_to[1]+dim_from[1],z-dim_to[2]+dim_from[2]]]//left up
+        +orthogonal_grid.grid[[x,y,z]]//right down
+        +orthogonal_grid.grid[[x+dim_from[0],y+dim_from[1],z+dim_from[2]]]);//right up
 }
 
+//x, y and z are the coordinates of the pressure cell counted from the first cell of the domain
 fn get_velocity_at_pressure_point(velocity_grid: &VelocityGrid, x: usize, y: usize, z: usize)->f32{
     let dim= get_dimension(velocity_grid.dimension);
-    return velocity_grid.grid[x+1][y+1][x+1]-velocity_grid.grid[x+1-dim[0]][y+1-dim[1]][z+1-dim[2]];//Just take the average
+    let ghost=velocity_grid.grid.ghost();
+    return 0.5*(velocity_grid.grid[[x+ghost,y+ghost,z+ghost]]+velocity_grid.grid[[x+ghost+dim[0],y+ghost+dim[1],z+ghost+dim[2]]]);//Just take the average of the velocities on both faces
+}
+
+//The velocities that the solver calculates in one dimension, the others are set by the boundary conditions.
+//In a periodic dimension the face on the lower wall is calculated as well, the face on the upper wall is the same face and gets a copy of it.
+pub(crate) fn solved_range(velocity_grid: &VelocityGrid, dimension: usize, config: &SimulationConfig) -> Range<usize>{
+    let grid=&velocity_grid.grid;
+    if config.periodic[dimension]{
+        return grid.ghost()..grid.ghost()+grid.cells()[dimension];
+    }
+    return grid.inner_range(dimension);
 }
 
 //Gives you the unit vector of the dimension with the given numer.
@@ -393,3 +654,120 @@ fn get_dimension(dimension_number:usize)->[usize; 3]{
     dim[dimension_number]=1;
     return dim;
 }
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    //A domain small enough for a test with an obstacle, a periodic dimension and an inflow and an outflow
+    pub(crate) fn small_config() -> SimulationConfig{
+        let mut config=SimulationConfig{
+            pressure_grid_size: [12, 10, 8],
+            periodic: [false, true, false],
+            initial_velocity: [0.05, 0.0, 0.02],
+            obstacles: vec![Obstacle::Sphere{center: [0.3, 0.25, 0.2], radius: 0.1}],
+            ..SimulationConfig::default()
+        };
+        config.boundary_patches[0].min_cells=[3, 2];
+        config.boundary_patches[0].max_cells=[6, 5];
+        config.boundary_patches[1].min_cells=[4, 3];
+        config.boundary_patches[1].max_cells=[7, 6];
+        return config;
+    }
+
+    //The bits of every value of the velocities and the pressure
+    pub(crate) fn bits(state: &SimulationState) -> Vec<u32>{
+        let fields=[&state.velocity_x.grid, &state.velocity_y.grid, &state.velocity_z.grid, &state.pressure_grid];
+        return fields.iter().flat_map(|field| field.as_slice().iter().map(|value| value.to_bits())).collect();
+    }
+
+    fn run_on_threads(config: &SimulationConfig, threads: usize) -> SimulationState{
+        return run_headless(&SimulationConfig{threads, ..config.clone()}, 3, |_, _| false).unwrap();
+    }
+
+    //Gauss-Seidel one row after the other, a solver like another crate could write with the public methods of StencilMatrix
+    struct SequentialGaussSeidel;
+
+    impl LinearSolver for SequentialGaussSeidel{
+        fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics{
+            for iteration in 0..max_iterations{
+                if matrix.max_residual(x, b)<=tolerance{
+                    return SolveStatistics::measure(matrix, x, b, iteration, tolerance);
+                }
+                for row in 0..matrix.size(){
+                    x[row]=matrix.solve_row(row, x, b);
+                }
+            }
+            return SolveStatistics::measure(matrix, x, b, max_iterations, tolerance);
+        }
+    }
+
+    #[test]
+    fn custom_solver_solves_own_matrix(){
+        //Three cells in a row between two walls with a fixed value
+        let mut matrix=StencilMatrix::new([3, 1, 1], vec![[0, 0, 0], [1, 0, 0], [2, 0, 0]]);
+        for row in 0..3{
+            matrix.add_to_diagonal(row, 2.0);
+        }
+        for row in 0..2{
+            matrix.add_coupling(row, row+1, 1.0);
+            matrix.add_coupling(row+1, row, 1.0);
+        }
+        let (mut x, b)=(vec![0.0; 3], vec![1.0, 0.0, 1.0]);
+        let statistics=SequentialGaussSeidel.solve(&matrix, &mut x, &b, 1e-6, 100);
+        assert!(statistics.converged);
+        for (value, expected) in x.iter().zip([1.0, 1.0, 1.0]){
+            assert!((value-expected).abs()<1e-5, "got {:?}", x);
+        }
+    }
+
+    #[test]
+    fn custom_solver_runs_simulation(){
+        let pressure_solver=PressureSolver::Custom(CustomSolver(Arc::new(|| Box::new(SequentialGaussSeidel))));
+        let config=SimulationConfig{pressure_solver, ..small_config()};
+        let mut reports=Vec::new();
+        run_headless(&config, 2, |_, report| {reports.push(report.clone()); false}).unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|report| report.iterations>0 && report.divergence.max<=config.allowed_error));
+    }
+
+    #[test]
+    fn results_do_not_depend_on_the_number_of_threads(){
+        let solvers=[
+            PressureSolver::Legacy,
+            PressureSolver::Sor{omega: 1.7},
+            PressureSolver::ConjugateGradient{preconditioner: Preconditioner::IncompleteCholesky},
+            PressureSolver::Multigrid{cycle: MultigridCycle::V, smoothing_steps: 2},
+        ];
+        for pressure_solver in solvers{
+            let config=SimulationConfig{pressure_solver: pressure_solver.clone(), ..small_config()};
+            assert!(bits(&run_on_threads(&config, 1))==bits(&run_on_threads(&config, 4)), "{:?} differs on 1 and 4 threads", pressure_solver);
+        }
+        let config=SimulationConfig{diffusion_scheme: DiffusionScheme::CrankNicolson, time_integration: TimeIntegration::AdamsBashforth, ..small_config()};
+        assert!(bits(&run_on_threads(&config, 1))==bits(&run_on_threads(&config, 4)), "Crank-Nicolson with Adams-Bashforth differs on 1 and 4 threads");
+    }
+
+    #[test]
+    fn nan_is_the_largest_and_the_first_nan_is_kept(){
+        assert!(is_larger(2.0, 1.0));
+        assert!(!is_larger(1.0, 1.0));
+        assert!(is_larger(f32::NAN, 1.0));
+        assert!(!is_larger(f32::NAN, f32::NAN));
+        assert!(!is_larger(f32::INFINITY, f32::NAN));
+    }
+
+    #[test]
+    fn diverging_simulation_returns_an_error(){
+        //Explicit diffusion with a diffusion number of 1000 blows up, semi-Lagrangian convection does not stop it at the CFL number first
+        let config=SimulationConfig{
+            viscosity: 50.0,
+            density: 1.0,
+            convection_scheme: ConvectionScheme::SemiLagrangian{correction: AdvectionCorrection::None},
+            max_iterations_per_time_frame: 2000,
+            ..small_config()
+        };
+        let mut state=SimulationState::new(&config).unwrap();
+        let error=(0..100).find_map(|_| simulation_time_step(&mut state, &config).err());
+        assert!(matches!(error, Some(SolverError::NotFinite{..} | SolverError::PressureNotConverged{..})), "expected the simulation to blow up, got {:?}", error);
+    }
+}
@@ -0,0 +1,254 @@
+//Triangle meshes from OBJ and STL files, used as obstacles so we can simulate the flow around real parts instead of only simple shapes.
+use std::{fmt, path::{Path, PathBuf}};
+
+use tobj::LoadOptions;
+
+pub type Triangle = [[f32; 3]; 3];
+
+#[derive(Debug)]
+pub enum MeshError{
+    Io{path: PathBuf, error: std::io::Error},//The file could not be read
+    Obj{path: PathBuf, error: tobj::LoadError},//The OBJ file could not be parsed
+    Stl{path: PathBuf, message: String},//The STL file could not be parsed
+    UnknownFormat(PathBuf),//The file does not end in .obj or .stl
+    Empty(PathBuf),//The file does not contain any triangles
+    NotFinite(PathBuf),//A vertex has a coordinate that is infinite or not a number
+}
+
+impl fmt::Display for MeshError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return match self{
+            MeshError::Io{path, error} => write!(f, "Failed to read {}: {}", path.display(), error),
+            MeshError::Obj{path, error} => write!(f, "Failed to load OBJ file {}: {}", path.display(), error),
+            MeshError::Stl{path, message} => write!(f, "Failed to load STL file {}: {}", path.display(), message),
+            MeshError::UnknownFormat(path) => write!(f, "{} is not an OBJ or STL file", path.display()),
+            MeshError::Empty(path) => write!(f, "{} does not contain any triangles", path.display()),
+            MeshError::NotFinite(path) => write!(f, "{} has a vertex with a coordinate that is not a finite number", path.display()),
+        };
+    }
+}
+
+impl std::error::Error for MeshError{}
+
+//A closed surface made of triangles. A point is inside when a ray from the point crosses the surface an odd number of times, so the mesh has to be watertight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TriangleMesh{
+    pub triangles: Vec<Triangle>,
+}
+
+impl TriangleMesh{
+    //Load a mesh from an OBJ or STL file, the format is determined by the extension
+    pub fn load(path: &Path) -> Result<Self, MeshError>{
+        let extension=path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let triangles=match extension.as_deref(){
+            Some("obj") => load_obj(path)?,
+            Some("stl") => load_stl(path)?,
+            _ => {return Err(MeshError::UnknownFormat(path.to_path_buf()))}
+        };
+        if triangles.is_empty(){
+            return Err(MeshError::Empty(path.to_path_buf()));
+        }
+        //The voxelization can not tell on which side of such a vertex a cell is
+        if !triangles.iter().flatten().flatten().all(|coordinate| coordinate.is_finite()){
+            return Err(MeshError::NotFinite(path.to_path_buf()));
+        }
+        return Ok(Self{triangles});
+    }
+    //Scale the mesh around the origin of the file and then move it, this is how a part is placed in the domain
+    pub fn transformed(&self, scale: f32, translation: [f32; 3]) -> Self{
+        let triangles=self.triangles.iter().map(|triangle| triangle.map(|vertex| [0, 1, 2].map(|i| vertex[i]*scale+translation[i]))).collect();
+        return Self{triangles};
+    }
+    //The lowest and the highest coordinates of the mesh
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]){
+        let mut min=[f32::INFINITY; 3];
+        let mut max=[f32::NEG_INFINITY; 3];
+        for vertex in self.triangles.iter().flatten(){
+            for i in 0..3{
+                min[i]=min[i].min(vertex[i]);
+                max[i]=max[i].max(vertex[i]);
+            }
+        }
+        return (min, max);
+    }
+    pub fn contains(&self, point: [f32; 3]) -> bool{
+        let crossings=self.ray_crossings(point[0], point[1]).iter().filter(|&&z| z>point[2] as f64).count();
+        return crossings%2==1;
+    }
+    //The z coordinates where the line parallel to the z axis through (x, y) crosses the surface, sorted from low to high.
+    //Every crossing is found exactly once, also when the line goes exactly through an edge or a vertex shared by several triangles.
+    pub fn ray_crossings(&self, x: f32, y: f32) -> Vec<f64>{
+        let point=[x as f64, y as f64];
+        let mut crossings=Vec::new();
+        for triangle in self.triangles.iter(){
+            let mut vertices=triangle.map(|vertex| vertex.map(|coordinate| coordinate as f64));
+            let mut area=edge_function(vertices[0], vertices[1], [vertices[2][0], vertices[2][1]]);
+            if area==0.0{//The triangle is parallel to the z axis, the line can not cross it
+                continue;
+            }
+            if area<0.0{//Make all triangles counterclockwise when seen from above
+                vertices.swap(1, 2);
+                area=area.abs();
+            }
+            let weights=[
+                edge_function(vertices[1], vertices[2], point),
+                edge_function(vertices[2], vertices[0], point),
+                edge_function(vertices[0], vertices[1], point),
+            ];
+            let edges=[(vertices[1], vertices[2]), (vertices[2], vertices[0]), (vertices[0], vertices[1])];
+            let inside=weights.iter().zip(edges.iter()).all(|(&weight, &(from, to))| weight>0.0 || (weight==0.0 && owns_edge(from, to)));
+            if inside{
+                crossings.push((weights[0]*vertices[0][2]+weights[1]*vertices[1][2]+weights[2]*vertices[2][2])/area);
+            }
+        }
+        crossings.sort_by(f64::total_cmp);
+        return crossings;
+    }
+}
+
+//Twice the signed area of the triangle (a, b, p) seen from above, positive when p lies left of the edge from a to b
+fn edge_function(a: [f64; 3], b: [f64; 3], p: [f64; 2]) -> f64{
+    return (b[0]-a[0])*(p[1]-a[1])-(b[1]-a[1])*(p[0]-a[0]);
+}
+
+//When a point lies exactly on an edge, only one of the two triangles sharing that edge may count it. The edge goes in the opposite direction in the other triangle, so this rule picks exactly one of them.
+fn owns_edge(from: [f64; 3], to: [f64; 3]) -> bool{
+    let (dx, dy)=(to[0]-from[0], to[1]-from[1]);
+    return dy>0.0 || (dy==0.0 && dx<0.0);
+}
+
+fn load_obj(path: &Path) -> Result<Vec<Triangle>, MeshError>{
+    let (models, _)=tobj::load_obj(path, &LoadOptions{triangulate: true, ..Default::default()}).map_err(|error| MeshError::Obj{path: path.to_path_buf(), error})?;
+    let mut triangles=Vec::new();
+    for model in models{
+        let positions=&model.mesh.positions;
+        let vertex=|index: u32| [positions[3*index as usize], positions[3*index as usize+1], positions[3*index as usize+2]];
+        for face in model.mesh.indices.chunks_exact(3){
+            triangles.push([vertex(face[0]), vertex(face[1]), vertex(face[2])]);
+        }
+    }
+    return Ok(triangles);
+}
+
+//STL files come in a binary and a text version. A binary file has an 80 byte header, the number of triangles and then 50 bytes for every triangle.
+fn load_stl(path: &Path) -> Result<Vec<Triangle>, MeshError>{
+    let bytes=std::fs::read(path).map_err(|error| MeshError::Io{path: path.to_path_buf(), error})?;
+    if bytes.len()>=84{
+        let count=u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len()==84+50*count{
+            return Ok(bytes[84..].chunks_exact(50).map(|record| {
+                //The first 12 bytes are the normal, which we do not need
+                let float=|offset: usize| f32::from_le_bytes([record[offset], record[offset+1], record[offset+2], record[offset+3]]);
+                [0, 1, 2].map(|vertex| [0, 1, 2].map(|i| float(12+12*vertex+4*i)))
+            }).collect());
+        }
+    }
+    let text=match std::str::from_utf8(&bytes){
+        Ok(text) => text,
+        Err(_) => {return Err(MeshError::Stl{path: path.to_path_buf(), message: String::from("the file is neither a valid binary STL file nor a text STL file")})}
+    };
+    let mut vertices=Vec::new();
+    for (line_number, line) in text.lines().enumerate(){
+        let mut words=line.split_whitespace();
+        if words.next()!=Some("vertex"){
+            continue;
+        }
+        let coordinates: Vec<f32>=words.map(|word| word.parse::<f32>()).collect::<Result<_, _>>().map_err(|error| MeshError::Stl{path: path.to_path_buf(), message: format!("line {}: {}", line_number+1, error)})?;
+        if coordinates.len()!=3{
+            return Err(MeshError::Stl{path: path.to_path_buf(), message: format!("line {}: a vertex needs 3 coordinates, got {}", line_number+1, coordinates.len())});
+        }
+        vertices.push([coordinates[0], coordinates[1], coordinates[2]]);
+    }
+    if vertices.len()%3!=0{
+        return Err(MeshError::Stl{path: path.to_path_buf(), message: format!("found {} vertices, which is not a multiple of 3", vertices.len())});
+    }
+    return Ok(vertices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]]).collect());
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{Obstacle, SimulationConfig, obstacle::build_solid_mask};
+
+    //The corners of the unit cube and its six sides, every side counterclockwise seen from outside
+    const CORNERS: [[f32; 3]; 8] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 1.0], [1.0, 1.0, 1.0], [0.0, 1.0, 1.0]];
+    const SIDES: [[usize; 4]; 6] = [[0, 3, 2, 1], [4, 5, 6, 7], [0, 1, 5, 4], [1, 2, 6, 5], [2, 3, 7, 6], [3, 0, 4, 7]];
+
+    //A file in the temporary directory that is removed at the end of the test
+    struct TemporaryFile(PathBuf);
+
+    impl TemporaryFile{
+        fn new(name: &str, contents: &str) -> Self{
+            let path=std::env::temp_dir().join(format!("pws_{}_{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            return Self(path);
+        }
+    }
+
+    impl Drop for TemporaryFile{
+        fn drop(&mut self){
+            let _=std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn obj_cube() -> String{
+        let mut text=String::new();
+        for corner in CORNERS.iter(){
+            text+=&format!("v {} {} {}\n", corner[0], corner[1], corner[2]);
+        }
+        for side in SIDES.iter(){
+            text+=&format!("f {} {} {} {}\n", side[0]+1, side[1]+1, side[2]+1, side[3]+1);
+        }
+        return text;
+    }
+
+    fn stl_cube(corners: [[f32; 3]; 8]) -> String{
+        let mut text=String::from("solid cube\n");
+        for side in SIDES.iter(){
+            for triangle in [[side[0], side[1], side[2]], [side[0], side[2], side[3]]]{
+                text+="facet normal 0 0 0\nouter loop\n";
+                for corner in triangle{
+                    text+=&format!("vertex {} {} {}\n", corners[corner][0], corners[corner][1], corners[corner][2]);
+                }
+                text+="endloop\nendfacet\n";
+            }
+        }
+        return text+"endsolid cube\n";
+    }
+
+    //The cube scaled to 2 m at (1, 1, 1) in a domain of 4x4x4 cells of 1 m fills the 8 cells in the middle
+    fn assert_voxelized_cube(mesh: TriangleMesh){
+        assert_eq!(mesh.triangles.len(), 12);
+        let config=SimulationConfig{grid_element_scale: 1.0, pressure_grid_size: [4, 4, 4], boundary_patches: Vec::new(), obstacles: vec![Obstacle::Mesh(mesh.transformed(2.0, [1.0; 3]))], ..SimulationConfig::default()};
+        let solid_mask=build_solid_mask(&config);
+        let ghost=solid_mask.ghost();
+        for x in 0..4{
+            for y in 0..4{
+                for z in 0..4{
+                    let inside=[x, y, z].iter().all(|&coordinate| coordinate==1 || coordinate==2);
+                    assert_eq!(solid_mask[[x+ghost,y+ghost,z+ghost]], inside, "cell {:?}", [x, y, z]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn obj_cube_is_voxelized(){
+        let file=TemporaryFile::new("cube.obj", &obj_cube());
+        assert_voxelized_cube(TriangleMesh::load(&file.0).unwrap());
+    }
+
+    #[test]
+    fn stl_cube_is_voxelized(){
+        let file=TemporaryFile::new("cube.stl", &stl_cube(CORNERS));
+        assert_voxelized_cube(TriangleMesh::load(&file.0).unwrap());
+    }
+
+    #[test]
+    fn rejects_vertex_that_is_not_a_number(){
+        let mut corners=CORNERS;
+        corners[6]=[1.0, f32::NAN, 1.0];
+        let file=TemporaryFile::new("nan.stl", &stl_cube(corners));
+        assert!(matches!(TriangleMesh::load(&file.0), Err(MeshError::NotFinite(_))));
+    }
+}
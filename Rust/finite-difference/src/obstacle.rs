@@ -0,0 +1,248 @@
+use crate::{Field3D, SimulationConfig, TriangleMesh, VelocityGrid, GHOST_LAYERS, boundary, get_dimension, solved_range};
+
+//solid_mask[[x,y,z]] is true when the pressure cell with storage coordinates (x,y,z) is inside an obstacle. The ghost layers are never solid, the walls are handled by the boundary conditions.
+pub type SolidMask = Field3D<bool>;
+
+//A solid object inside the domain. All positions are in meters, measured from the corner of the domain where all coordinates are lowest.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Obstacle{
+    Box{min: [f32; 3], max: [f32; 3]},
+    //A cylinder around an axis parallel to one of the coordinate axes, it goes through the whole domain when length is None
+    Cylinder{center: [f32; 3], radius: f32, axis: usize, length: Option<f32>},
+    Sphere{center: [f32; 3], radius: f32},
+    //A closed triangle mesh, already scaled and moved to its place in the domain
+    Mesh(TriangleMesh),
+}
+
+impl Obstacle{
+    pub fn contains(&self, point: [f32; 3]) -> bool{
+        return match self{
+            Obstacle::Box{min, max} => (0..3).all(|i| point[i]>=min[i] && point[i]<=max[i]),
+            Obstacle::Cylinder{center, radius, axis, length} => {
+                let distance_squared: f32=(0..3).filter(|i| i!=axis).map(|i| (point[i]-center[i]).powi(2)).sum();
+                let within_length=match length{
+                    Some(length) => (point[*axis]-center[*axis]).abs()<=0.5*length,
+                    None => true,
+                };
+                distance_squared<=radius*radius && within_length
+            }
+            Obstacle::Sphere{center, radius} => (0..3).map(|i| (point[i]-center[i]).powi(2)).sum::<f32>()<=radius*radius,
+            Obstacle::Mesh(mesh) => mesh.contains(point),
+        };
+    }
+}
+
+//A cell belongs to an obstacle when its center lies inside the obstacle
+pub fn build_solid_mask(config: &SimulationConfig) -> SolidMask{
+    let mut solid_mask=SolidMask::cell_centered(config.pressure_grid_size, GHOST_LAYERS, false);
+    for obstacle in config.obstacles.iter(){
+        mark_obstacle(&mut solid_mask, config, obstacle);
+    }
+    //An obstacle on a periodic wall continues on the other side
+    boundary::wrap_periodic_field(&mut solid_mask, config);
+    return solid_mask;
+}
+
+pub(crate) fn mark_obstacle(solid_mask: &mut SolidMask, config: &SimulationConfig, obstacle: &Obstacle){
+    match obstacle{
+        Obstacle::Mesh(mesh) => voxelize_mesh(solid_mask, config, mesh),
+        _ => mark_solid_cells(solid_mask, config, |point| obstacle.contains(point)),
+    }
+}
+
+//Testing every cell against every triangle is too slow for real parts. Instead we shoot one ray in the z direction through every column of cells,
+//the cells between the first and the second crossing with the surface are inside, just like the cells between the third and the fourth, and so on.
+fn voxelize_mesh(solid_mask: &mut SolidMask, config: &SimulationConfig, mesh: &TriangleMesh){
+    let ghost=solid_mask.ghost();
+    let center=|coordinate: usize| ((coordinate-ghost) as f32+0.5)*config.grid_element_scale;
+    for x in solid_mask.domain_range(0){
+        for y in solid_mask.domain_range(1){
+            let crossings=mesh.ray_crossings(center(x), center(y));
+            for pair in crossings.chunks_exact(2){
+                for z in solid_mask.domain_range(2){
+                    let z_center=center(z) as f64;
+                    if z_center>=pair[0] && z_center<pair[1]{
+                        solid_mask[[x,y,z]]=true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+//Mark every cell of the domain whose center satisfies is_inside as solid
+fn mark_solid_cells<F: Fn([f32; 3]) -> bool>(solid_mask: &mut SolidMask, config: &SimulationConfig, is_inside: F){
+    let ghost=solid_mask.ghost();
+    for x in solid_mask.domain_range(0){
+        for y in solid_mask.domain_range(1){
+            for z in solid_mask.domain_range(2){
+                let center=[x, y, z].map(|coordinate| ((coordinate-ghost) as f32+0.5)*config.grid_element_scale);
+                if is_inside(center){
+                    solid_mask[[x,y,z]]=true;
+                }
+            }
+        }
+    }
+}
+
+//A velocity face touches an obstacle when one of the two cells next to it is solid, the velocity through it has to be zero
+pub(crate) fn is_solid_face(solid_mask: &SolidMask, dimension: usize, x: usize, y: usize, z: usize) -> bool{
+    let dim=get_dimension(dimension);
+    return solid_mask[[x,y,z]] || solid_mask[[x-dim[0],y-dim[1],z-dim[2]]];
+}
+
+//Enforce no-slip on the surfaces of the obstacles.
+//The velocities through the surfaces are zero. The velocities inside an obstacle get the opposite value of the fluid velocities next to the surface, so the parallel velocity on the surface is zero, just like at the walls.
+pub(crate) fn set_obstacle_boundary_conditions(velocity_grid_x: &mut VelocityGrid, velocity_grid_y: &mut VelocityGrid, velocity_grid_z: &mut VelocityGrid, solid_mask: &SolidMask, config: &SimulationConfig){
+    if config.obstacles.is_empty(){
+        return;
+    }
+    set_obstacle_boundary_conditions_for_grid(velocity_grid_x, solid_mask, config);
+    set_obstacle_boundary_conditions_for_grid(velocity_grid_y, solid_mask, config);
+    set_obstacle_boundary_conditions_for_grid(velocity_grid_z, solid_mask, config);
+    boundary::wrap_periodic_velocities(velocity_grid_x, velocity_grid_y, velocity_grid_z, config);
+}
+
+fn set_obstacle_boundary_conditions_for_grid(velocity_grid: &mut VelocityGrid, solid_mask: &SolidMask, config: &SimulationConfig){
+    let dimension=velocity_grid.dimension;
+    let dim=get_dimension(dimension);
+    for x in solved_range(velocity_grid, 0, config){
+        for y in solved_range(velocity_grid, 1, config){
+            for z in solved_range(velocity_grid, 2, config){
+                let lower_cell_is_solid=solid_mask[[x-dim[0],y-dim[1],z-dim[2]]];
+                let upper_cell_is_solid=solid_mask[[x,y,z]];
+                if lower_cell_is_solid && upper_cell_is_solid{
+                    velocity_grid.grid[[x,y,z]]=mirrored_fluid_velocity(velocity_grid, solid_mask, x, y, z);
+                }else if lower_cell_is_solid || upper_cell_is_solid{
+                    velocity_grid.grid[[x,y,z]]=0.0;
+                }
+            }
+        }
+    }
+}
+
+//The average of the opposite velocities of the neighbouring faces in the fluid, or zero if the face is surrounded by the obstacle
+fn mirrored_fluid_velocity(velocity_grid: &VelocityGrid, solid_mask: &SolidMask, x: usize, y: usize, z: usize) -> f32{
+    let mut sum=0.0;
+    let mut count=0;
+    for neighbor_dimension in (0..3).filter(|&d| d!=velocity_grid.dimension){
+        let step=get_dimension(neighbor_dimension);
+        for neighbor in [[x-step[0], y-step[1], z-step[2]], [x+step[0], y+step[1], z+step[2]]]{
+            if !is_solid_face(solid_mask, velocity_grid.dimension, neighbor[0], neighbor[1], neighbor[2]){
+                sum-=velocity_grid.grid[neighbor];
+                count+=1;
+            }
+        }
+    }
+    return if count==0 {0.0} else {sum/count as f32};
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{SimulationState, simulation_time_step, stencil, tests::small_config};
+
+    //The domain coordinates of the solid cells
+    fn solid_cells(solid_mask: &SolidMask) -> Vec<[usize; 3]>{
+        let ghost=solid_mask.ghost();
+        let mut cells=Vec::new();
+        for x in solid_mask.domain_range(0){
+            for y in solid_mask.domain_range(1){
+                for z in solid_mask.domain_range(2){
+                    if solid_mask[[x,y,z]]{
+                        cells.push([x-ghost, y-ghost, z-ghost]);
+                    }
+                }
+            }
+        }
+        return cells;
+    }
+
+    //Cells of 1 m, so the centers are at i+0.5
+    fn mask_of(obstacle: Obstacle) -> Vec<[usize; 3]>{
+        let config=SimulationConfig{grid_element_scale: 1.0, pressure_grid_size: [6, 6, 4], boundary_patches: Vec::new(), obstacles: vec![obstacle], ..SimulationConfig::default()};
+        return solid_cells(&build_solid_mask(&config));
+    }
+
+    #[test]
+    fn cylinder_marks_the_cells_with_their_center_inside(){
+        //Only the centers 2.5 and 3.5 are within 1 m of the axis in x and y
+        let through=mask_of(Obstacle::Cylinder{center: [3.0, 3.0, 2.0], radius: 1.0, axis: 2, length: None});
+        let mut expected=Vec::new();
+        for x in 2..4{
+            for y in 2..4{
+                for z in 0..4{
+                    expected.push([x, y, z]);
+                }
+            }
+        }
+        assert_eq!(through, expected);
+        //A length of 2 m around z=2 only holds the centers 1.5 and 2.5
+        let short=mask_of(Obstacle::Cylinder{center: [3.0, 3.0, 2.0], radius: 1.0, axis: 2, length: Some(2.0)});
+        assert_eq!(short, expected.into_iter().filter(|cell| cell[2]==1 || cell[2]==2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn box_and_sphere_mark_the_cells_with_their_center_inside(){
+        assert_eq!(mask_of(Obstacle::Box{min: [1.0, 2.0, 0.0], max: [3.0, 3.0, 1.0]}), vec![[1, 2, 0], [2, 2, 0]]);
+        assert_eq!(mask_of(Obstacle::Sphere{center: [3.0, 3.0, 2.0], radius: 0.9}), vec![[2, 2, 1], [2, 2, 2], [2, 3, 1], [2, 3, 2], [3, 2, 1], [3, 2, 2], [3, 3, 1], [3, 3, 2]]);
+    }
+
+    #[test]
+    fn obstacle_faces_are_no_slip_after_a_time_step(){
+        //A box of 4x4x4 cells, its faces inside have neighbours in the fluid
+        let config=SimulationConfig{obstacles: vec![Obstacle::Box{min: [0.15, 0.15, 0.1], max: [0.35, 0.35, 0.3]}], ..small_config()};
+        let mut state=SimulationState::new(&config).unwrap();
+        for _ in 0..2{
+            simulation_time_step(&mut state, &config).unwrap();
+        }
+        let solid_mask=&state.solid_mask;
+        let (mut surface_faces, mut mirrored_faces)=(0, 0);
+        for velocity_grid in [&state.velocity_x, &state.velocity_y, &state.velocity_z]{
+            let dimension=velocity_grid.dimension;
+            let dim=get_dimension(dimension);
+            for x in solved_range(velocity_grid, 0, &config){
+                for y in solved_range(velocity_grid, 1, &config){
+                    for z in solved_range(velocity_grid, 2, &config){
+                        let (lower, upper)=(solid_mask[[x-dim[0],y-dim[1],z-dim[2]]], solid_mask[[x,y,z]]);
+                        if lower!=upper{
+                            //Nothing flows through the surface
+                            assert_eq!(velocity_grid.grid[[x,y,z]], 0.0, "face {:?} of dimension {}", [x, y, z], dimension);
+                            surface_faces+=1;
+                        }else if lower && upper{
+                            //With one fluid face next to it the velocity on the surface in between is zero
+                            let fluid: Vec<[usize; 3]>=(0..3).filter(|&d| d!=dimension).flat_map(|d| {
+                                let step=get_dimension(d);
+                                [[x-step[0], y-step[1], z-step[2]], [x+step[0], y+step[1], z+step[2]]]
+                            }).filter(|face| !is_solid_face(solid_mask, dimension, face[0], face[1], face[2])).collect();
+                            if fluid.len()==1{
+                                assert_eq!(velocity_grid.grid[[x,y,z]]+velocity_grid.grid[fluid[0]], 0.0);
+                                mirrored_faces+=1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        assert!(surface_faces>0 && mirrored_faces>0);
+    }
+
+    #[test]
+    fn divergence_is_not_calculated_inside_obstacles(){
+        let config=small_config();
+        let solid_mask=build_solid_mask(&config);
+        let velocities=[0, 1, 2].map(|dimension| {
+            let mut velocity=VelocityGrid::new(config.pressure_grid_size, dimension);
+            for (i, value) in velocity.grid.as_mut_slice().iter_mut().enumerate(){
+                *value=(i%7) as f32;
+            }
+            velocity
+        });
+        let divergence=stencil::scaled_divergence(&velocities[0].grid, &velocities[1].grid, &velocities[2].grid, &solid_mask, 1.0, &config);
+        let cells=solid_cells(&solid_mask);
+        assert!(!cells.is_empty());
+        for cell in cells{
+            assert_eq!(divergence[cell.map(|coordinate| coordinate+GHOST_LAYERS)], 0.0);
+        }
+    }
+}
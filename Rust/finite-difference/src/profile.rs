@@ -0,0 +1,186 @@
+//How the speed of an inflow or outflow patch changes over time and over the patch.
+//The velocity on a face of a patch is speed * TimeProfile::factor * SpatialProfile::factor.
+use std::{fmt, path::Path, sync::Arc};
+
+//How the speed of a patch changes over time, time is measured in seconds from the start of the simulation
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimeProfile{
+    Constant,
+    Sigmoid{midpoint: f32, width: f32},//Starts at zero and smoothly reaches the full speed, half of it at the midpoint
+    Ramp{start: f32, duration: f32},//Zero until start, then grows linearly to the full speed in duration seconds
+    Sinusoid{mean: f32, amplitude: f32, frequency: f32, phase: f32},//A pulsating flow, mean + amplitude * sin(2 pi frequency time + phase). frequency is in Hz and phase in radians.
+    Table{times: Vec<f32>, factors: Vec<f32>},//Linear interpolation between tabulated values, the first and last value are kept before and after the table. times has to be increasing.
+}
+
+impl TimeProfile{
+    //The fraction of the full speed at the given time
+    pub fn factor(&self, time: f32) -> f32{
+        return match self{
+            TimeProfile::Constant => 1.0,
+            TimeProfile::Sigmoid{midpoint, width} => 1.0/(((midpoint-time)/width).exp()+1.0),
+            TimeProfile::Ramp{start, duration} => ((time-start)/duration).clamp(0.0, 1.0),
+            TimeProfile::Sinusoid{mean, amplitude, frequency, phase} => mean+amplitude*(2.0*std::f32::consts::PI*frequency*time+phase).sin(),
+            TimeProfile::Table{times, factors} => interpolate(times, factors, time),
+        };
+    }
+}
+
+fn interpolate(times: &[f32], factors: &[f32], time: f32) -> f32{
+    let next=times.partition_point(|&t| t<=time);
+    if next==0{
+        return factors[0];
+    }
+    if next==times.len(){
+        return factors[times.len()-1];
+    }
+    let fraction=(time-times[next-1])/(times[next]-times[next-1]);
+    return factors[next-1]+fraction*(factors[next]-factors[next-1]);
+}
+
+//How the speed is distributed over a patch. Positions on a patch are given in the two dimensions along the face (see Face::tangential_dimensions),
+//scaled from 0 on the lower edge of the patch to 1 on the upper edge.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SpatialProfile{
+    Uniform,
+    //The laminar profile of a channel or duct, the speed is zero on the edges of the patch and the full speed in the middle.
+    //across is the index (0 or 1) of the dimension along the face in which the speed changes, None for both.
+    Parabolic{across: Option<usize>},
+    //The usual approximation of a turbulent profile, (distance to the edge / half the width)^(1/exponent). The exponent is 7 for the 1/7 power law.
+    PowerLaw{exponent: f32, across: Option<usize>},
+    //The factor for every cell of the patch, values[i][j] is the cell i in the first and j in the second dimension along the face
+    Table{values: Vec<Vec<f32>>},
+    //Any function of time in seconds and position on the patch
+    Function(ProfileFunction),
+}
+
+impl SpatialProfile{
+    //The fraction of the full speed in cell `cell` of a patch with `cells` cells in both dimensions along the face, at the given time
+    pub fn factor(&self, cell: [usize; 2], cells: [usize; 2], time: f32) -> f32{
+        let position=[0, 1].map(|i| (cell[i] as f32+0.5)/cells[i] as f32);
+        return match self{
+            SpatialProfile::Uniform => 1.0,
+            SpatialProfile::Parabolic{across} => varying_dimensions(*across).map(|i| 4.0*position[i]*(1.0-position[i])).product(),
+            SpatialProfile::PowerLaw{exponent, across} => varying_dimensions(*across).map(|i| (2.0*position[i].min(1.0-position[i])).powf(1.0/exponent)).product(),
+            SpatialProfile::Table{values} => values[cell[0]][cell[1]],
+            SpatialProfile::Function(function) => (function.0)(time, position),
+        };
+    }
+}
+
+fn varying_dimensions(across: Option<usize>) -> impl Iterator<Item = usize>{
+    return match across{
+        Some(dimension) => dimension..dimension+1,
+        None => 0..2,
+    };
+}
+
+//A user supplied profile, called with the time in seconds and the position on the patch. Two functions are only equal when they are the same function.
+#[derive(Clone)]
+pub struct ProfileFunction(pub Arc<dyn Fn(f32, [f32; 2]) -> f32 + Send + Sync>);
+
+impl fmt::Debug for ProfileFunction{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return write!(f, "ProfileFunction");
+    }
+}
+
+impl PartialEq for ProfileFunction{
+    fn eq(&self, other: &Self) -> bool{
+        return Arc::ptr_eq(&self.0, &other.0);
+    }
+}
+
+//Read a CSV file with numbers, a first line that is not a number (a header) is skipped. Every line becomes one row.
+pub fn read_csv(path: &Path) -> Result<Vec<Vec<f32>>, String>{
+    let text=std::fs::read_to_string(path).map_err(|error| format!("Failed to read {}: {}", path.display(), error))?;
+    let mut rows=Vec::new();
+    for (line_number, line) in text.lines().enumerate(){
+        if line.trim().is_empty(){
+            continue;
+        }
+        let row: Result<Vec<f32>, _>=line.split(',').map(|value| value.trim().parse::<f32>()).collect();
+        match row{
+            Ok(row) => rows.push(row),
+            Err(_) if rows.is_empty() && line_number==0 => {}//The header
+            Err(error) => {return Err(format!("{} line {}: {}", path.display(), line_number+1, error))}
+        }
+    }
+    return Ok(rows);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::path::PathBuf;
+
+    //A file in the temporary directory that is removed again at the end of the test
+    struct TemporaryFile(PathBuf);
+
+    impl TemporaryFile{
+        fn new(name: &str, contents: &str) -> Self{
+            let path=std::env::temp_dir().join(format!("pws_{}_{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            return Self(path);
+        }
+    }
+
+    impl Drop for TemporaryFile{
+        fn drop(&mut self){
+            let _=std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn assert_close(value: f32, expected: f32){
+        assert!((value-expected).abs()<1e-6, "got {}, expected {}", value, expected);
+    }
+
+    #[test]
+    fn time_profiles_at_known_times(){
+        let sigmoid=TimeProfile::Sigmoid{midpoint: 2.0, width: 0.5};
+        assert_close(sigmoid.factor(2.0), 0.5);
+        assert_close(sigmoid.factor(2.5), 1.0/((-1.0f32).exp()+1.0));
+        assert_close(sigmoid.factor(1.5), 1.0/(1.0f32.exp()+1.0));
+        let ramp=TimeProfile::Ramp{start: 1.0, duration: 2.0};
+        for (time, expected) in [(0.0, 0.0), (1.0, 0.0), (1.5, 0.25), (2.0, 0.5), (3.0, 1.0), (10.0, 1.0)]{
+            assert_close(ramp.factor(time), expected);
+        }
+        let table=TimeProfile::Table{times: vec![0.0, 1.0, 3.0], factors: vec![0.0, 2.0, 1.0]};
+        for (time, expected) in [(-1.0, 0.0), (0.0, 0.0), (0.5, 1.0), (1.0, 2.0), (2.0, 1.5), (3.0, 1.0), (5.0, 1.0)]{
+            assert_close(table.factor(time), expected);
+        }
+    }
+
+    #[test]
+    fn parabolic_profile_is_full_in_the_center_and_low_on_the_edges(){
+        let across_first=SpatialProfile::Parabolic{across: Some(0)};
+        //The center of the middle one of five cells is the center of the patch, the center of the first cell is a tenth of the width from the edge
+        assert_close(across_first.factor([2, 0], [5, 3], 0.0), 1.0);
+        assert_close(across_first.factor([0, 1], [5, 3], 0.0), 4.0*0.1*0.9);
+        assert_close(across_first.factor([4, 2], [5, 3], 0.0), 4.0*0.1*0.9);
+        let duct=SpatialProfile::Parabolic{across: None};
+        assert_close(duct.factor([2, 1], [5, 3], 0.0), 1.0);
+        assert_close(duct.factor([0, 0], [5, 3], 0.0), 4.0*0.1*0.9*4.0*(1.0/6.0)*(5.0/6.0));
+    }
+
+    #[test]
+    fn read_csv_skips_the_header_and_reports_bad_rows(){
+        let table=TemporaryFile::new("profile_table.csv", "time, factor\n0.0, 0.5\n\n1.0, 1.0\n");
+        assert_eq!(read_csv(&table.0), Ok(vec![vec![0.0, 0.5], vec![1.0, 1.0]]));
+        let bad_row=TemporaryFile::new("profile_bad_row.csv", "0.0, 0.5\n1.0, fast\n");
+        let message=read_csv(&bad_row.0).unwrap_err();
+        assert!(message.contains("line 2"), "{}", message);
+        //A header is only allowed on the first line
+        let late_header=TemporaryFile::new("profile_late_header.csv", "0.0, 0.5\ntime, factor\n");
+        assert!(read_csv(&late_header.0).is_err());
+        assert!(read_csv(Path::new("/nonexistent/profile.csv")).unwrap_err().starts_with("Failed to read"));
+    }
+
+    #[test]
+    fn table_profile_with_decreasing_times_is_rejected(){
+        let table=TemporaryFile::new("profile_decreasing.csv", "0.0, 0.5\n2.0, 1.0\n1.0, 0.0\n");
+        let file_name=table.0.file_name().unwrap().to_str().unwrap();
+        let text=format!("[domain]\ncells = [10, 10, 10]\nspacing = 0.01\n\n[[boundary]]\nname = \"inflow\"\nface = \"z_min\"\nmin = [2, 2]\nmax = [4, 4]\ntype = \"inflow\"\nspeed = 0.1\nprofile = {{type = \"table\", file = \"{}\"}}\n", file_name);
+        let message=crate::scenario::parse_scenario_relative_to(&text, &std::env::temp_dir()).unwrap_err().to_string();
+        assert!(message.contains("increasing"), "{}", message);
+    }
+}
@@ -0,0 +1,110 @@
+//What happened in a time step, for the application to show, log or check. The library itself does not print anything.
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::{ResidualNorms, SimulationConfig, SolidMask, SolveStatistics, TimeStepLimit, VelocityGrid};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepReport{
+    pub time_step: i32,//The number of the time step
+    pub time: f32,//The simulated time at the end of the step in s
+    pub time_step_size: f32,//in s
+    pub time_step_limit: TimeStepLimit,//What decided the size of the step
+    pub iterations: usize,//The iterations of the pressure solver, or the number of local corrections of the legacy scheme
+    pub divergence: ResidualNorms,//The divergence in 1/s that was left when the pressure was accepted
+    //The divergence in 1/s before the first and after every iteration, so iterations+1 values. Empty for a custom solver that does not keep track of it.
+    //The legacy scheme does not check the cells next to the walls, they are left out of its divergence.
+    pub divergence_history: Vec<ResidualNorms>,
+    pub diffusion: Option<[SolveStatistics; 3]>,//The solves of the implicit diffusion of the x, y and z velocities, None for explicit diffusion
+    pub kinetic_energy: f32,//The kinetic energy of the fluid at the end of the step in J
+    pub phase_times: PhaseTimes,
+}
+
+//The wall clock time of the parts of a time step
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PhaseTimes{
+    pub advection: Duration,//The semi-Lagrangian step, zero for the other convection schemes
+    pub prediction: Duration,//The provisional velocities, with every stage of the time integration
+    pub diffusion: Duration,//The implicit diffusion, zero for explicit diffusion
+    pub boundary: Duration,//The boundary conditions of the provisional velocities
+    pub pressure: Duration,//The pressure correction of the velocities, with the boundary conditions of every correction
+    pub total: Duration,//The whole step, with choosing its size and the checks
+}
+
+//A timed phase of a time step, logged to the target of its part of the solver. finish logs how long the phase took and returns that time for the PhaseTimes.
+pub(crate) struct PhaseSpan{
+    target: &'static str,
+    name: &'static str,
+    time_step: i32,
+    start: Instant,
+}
+
+impl PhaseSpan{
+    pub(crate) fn enter(target: &'static str, name: &'static str, time_step: i32) -> Self{
+        log::trace!(target: target, "Time step {}: {} started", time_step, name);
+        return Self{target, name, time_step, start: Instant::now()};
+    }
+    pub(crate) fn finish(self) -> Duration{
+        let elapsed=self.start.elapsed();
+        log::debug!(target: self.target, "Time step {}: {} took {:.3} ms", self.time_step, self.name, elapsed.as_secs_f64()*1000.0);
+        return elapsed;
+    }
+}
+
+//0.5 * density * |u|^2 summed over the fluid cells times their volume, with the velocity in a cell the average of the velocities on its faces
+pub(crate) fn kinetic_energy(velocities: [&VelocityGrid; 3], solid_mask: &SolidMask, config: &SimulationConfig) -> f32{
+    let (range_y, range_z)=(solid_mask.domain_range(1), solid_mask.domain_range(2));
+    //The layers are added in order, so the sum is the same for every number of threads
+    let layers: Vec<f64>=solid_mask.domain_range(0).into_par_iter().map(|x| {
+        let mut sum=0.0f64;
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                if solid_mask[[x,y,z]]{
+                    continue;
+                }
+                for (dimension, velocity_grid) in velocities.iter().enumerate(){
+                    let mut upper=[x, y, z];
+                    upper[dimension]+=1;
+                    let velocity=0.5*(velocity_grid.grid[[x,y,z]]+velocity_grid.grid[upper]);
+                    sum+=(velocity as f64).powi(2);
+                }
+            }
+        }
+        sum
+    }).collect();
+    let volume=(config.grid_element_scale as f64).powi(3);
+    return (0.5*config.density as f64*volume*layers.iter().sum::<f64>()) as f32;
+}
+
+#[cfg(test)]
+mod tests{
+    use crate::{MultigridCycle, PressureSolver, Preconditioner, SimulationConfig, SimulationState, simulation_time_step, tests::small_config};
+
+    #[test]
+    fn divergence_history_ends_with_the_reported_divergence(){
+        for pressure_solver in [PressureSolver::Legacy, PressureSolver::Sor{omega: 1.7}, PressureSolver::ConjugateGradient{preconditioner: Preconditioner::IncompleteCholesky}, PressureSolver::Multigrid{cycle: MultigridCycle::V, smoothing_steps: 2}]{
+            let config=SimulationConfig{pressure_solver: pressure_solver.clone(), ..small_config()};
+            let mut state=SimulationState::new(&config).unwrap();
+            for _ in 0..2{
+                let report=simulation_time_step(&mut state, &config).unwrap();
+                //The divergence before the first iteration is the first value, so there is one value more than there are iterations
+                assert_eq!(report.divergence_history.len(), report.iterations+1, "{:?}", pressure_solver);
+                assert_eq!(report.divergence_history.last(), Some(&report.divergence), "{:?}", pressure_solver);
+                assert!(report.divergence.max<=config.allowed_error);
+                assert!(report.divergence_history[0].max>config.allowed_error, "{:?} did not have anything to correct", pressure_solver);
+            }
+        }
+    }
+
+    #[test]
+    fn kinetic_energy_of_a_uniform_flow(){
+        //Without walls, patches and forces a uniform flow stays the same
+        let config=SimulationConfig{pressure_grid_size: [6, 5, 4], periodic: [true, true, true], boundary_patches: Vec::new(), initial_velocity: [0.3, -0.2, 0.1], ..SimulationConfig::default()};
+        let mut state=SimulationState::new(&config).unwrap();
+        let report=simulation_time_step(&mut state, &config).unwrap();
+        let volume=120.0*config.grid_element_scale.powi(3);
+        let expected=0.5*config.density*(0.3f32*0.3+0.2*0.2+0.1*0.1)*volume;
+        assert!((report.kinetic_energy-expected).abs()<=1e-5*expected, "kinetic energy {} J instead of {} J", report.kinetic_energy, expected);
+    }
+}
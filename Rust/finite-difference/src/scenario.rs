@@ -0,0 +1,760 @@
+//Reading a simulation setup from a TOML scenario file, see scenarios/default.toml for an example with all options.
+use std::{fmt, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+use crate::{AdaptiveTimeStep, AdvectionCorrection, BoundaryPatch, ConvectionScheme, DiffusionScheme, FluxLimiter, Face, MultigridCycle, Obstacle, PatchKind, Preconditioner, PressureSolver, SimulationConfig, SolidMask, SolverError, SpatialProfile, StencilKernels, TimeIntegration, TimeProfile, TriangleMesh, WallCondition, GHOST_LAYERS, obstacle, read_csv};
+
+#[derive(Debug)]
+pub enum ScenarioError{
+    Io{path: PathBuf, error: std::io::Error},//The file could not be read
+    Parse(toml::de::Error),//The file is not valid TOML or does not have the expected structure
+    Invalid{field: String, message: String},//A value in the file is not allowed, field is the path to the value in the file
+}
+
+impl fmt::Display for ScenarioError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return match self{
+            ScenarioError::Io{path, error} => write!(f, "Failed to read {}: {}", path.display(), error),
+            ScenarioError::Parse(error) => write!(f, "{}", error),
+            ScenarioError::Invalid{field, message} => write!(f, "{}: {}", field, message),
+        };
+    }
+}
+
+impl std::error::Error for ScenarioError{}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile{
+    domain: DomainSection,
+    #[serde(default)]
+    fluid: FluidSection,
+    #[serde(default)]
+    time: TimeSection,
+    #[serde(default)]
+    solver: SolverSection,
+    #[serde(default)]
+    schemes: SchemesSection,
+    #[serde(default)]
+    initial: InitialSection,
+    #[serde(default)]
+    walls: WallsSection,
+    #[serde(default)]
+    boundary: Vec<BoundarySection>,
+    #[serde(default)]
+    obstacle: Vec<ObstacleSection>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DomainSection{
+    cells: [usize; 3],
+    spacing: f32,
+    #[serde(default)]
+    periodic: Vec<AxisName>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FluidSection{
+    density: Option<f32>,
+    viscosity: Option<f32>,
+    external_force: Option<[f32; 3]>,
+    atmospheric_pressure: Option<f32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct TimeSection{
+    step_size: Option<f32>,
+    adaptive: Option<bool>,
+    cfl_number: Option<f32>,//Only when adaptive
+    diffusion_number: Option<f32>,//Only when adaptive
+    min_step_size: Option<f32>,//Only when adaptive
+    max_step_size: Option<f32>,//Only when adaptive, defaults to step_size
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SolverSection{
+    max_iterations: Option<i32>,
+    threads: Option<usize>,//0 for one thread per core
+    kernels: Option<KernelsName>,
+    relaxation: Option<f32>,
+    allowed_error: Option<f32>,
+    method: Option<SolverMethodName>,
+    omega: Option<f32>,//Only for sor
+    preconditioner: Option<PreconditionerName>,//Only for conjugate_gradient
+    cycle: Option<CycleName>,//Only for multigrid
+    smoothing_steps: Option<usize>,//Only for multigrid
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SolverMethodName{
+    Legacy, Jacobi, RedBlackGaussSeidel, Sor, ConjugateGradient, Multigrid,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum KernelsName{
+    Scalar, Vectorized,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PreconditionerName{
+    Jacobi, IncompleteCholesky,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum CycleName{
+    V, W,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct SchemesSection{
+    convection: Option<ConvectionName>,
+    limiter: Option<LimiterName>,//Only for tvd
+    correction: Option<CorrectionName>,//Only for semi_lagrangian
+    diffusion: Option<DiffusionName>,
+    diffusion_solver: Option<SolverMethodName>,//Only for implicit and crank_nicolson
+    diffusion_tolerance: Option<f32>,//Only for implicit and crank_nicolson
+    time_integration: Option<TimeIntegrationName>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum TimeIntegrationName{
+    ForwardEuler, Heun, SspRk3, AdamsBashforth,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum DiffusionName{
+    Explicit, Implicit, CrankNicolson,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum ConvectionName{
+    Central, Upwind, Hybrid, Quick, Tvd, SemiLagrangian,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum CorrectionName{
+    None, MacCormack, Bfecc,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum LimiterName{
+    Minmod, VanLeer, Superbee,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct InitialSection{
+    velocity: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct WallsSection{
+    x_min: Option<WallSection>,
+    x_max: Option<WallSection>,
+    y_min: Option<WallSection>,
+    y_max: Option<WallSection>,
+    z_min: Option<WallSection>,
+    z_max: Option<WallSection>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum WallSection{
+    NoSlip,
+    FreeSlip,
+    Moving{velocity: [f32; 3]},
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BoundarySection{
+    name: Option<String>,
+    face: FaceName,
+    min: [usize; 2],
+    max: [usize; 2],
+    #[serde(rename = "type")]
+    kind: PatchKindName,
+    speed: Option<f32>,//Required for inflow and outflow, optional for convective patches
+    pressure: Option<f32>,//Only for pressure patches, the atmospheric pressure when left out
+    #[serde(default)]
+    profile: ProfileSection,
+    #[serde(default)]
+    spatial_profile: SpatialProfileSection,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum FaceName{
+    XMin, XMax, YMin, YMax, ZMin, ZMax,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum PatchKindName{
+    Inflow, Outflow, Pressure, ZeroGradient, Convective,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ProfileSection{
+    #[default]
+    Constant,
+    Sigmoid{midpoint: f32, width: f32},
+    Ramp{#[serde(default)] start: f32, duration: f32},
+    Sinusoid{#[serde(default = "one")] mean: f32, amplitude: f32, frequency: f32, #[serde(default)] phase: f32},
+    //A CSV file with a time in s and a factor on every line, relative to the directory of the scenario file
+    Table{file: PathBuf},
+}
+
+#[derive(Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum SpatialProfileSection{
+    #[default]
+    Uniform,
+    Parabolic{across: Option<AxisName>},
+    PowerLaw{#[serde(default = "seven")] exponent: f32, across: Option<AxisName>},
+    //A CSV file with one line for every cell of the patch in the first dimension along the face, and one value for every cell in the second dimension
+    Table{file: PathBuf},
+}
+
+fn one() -> f32{
+    return 1.0;
+}
+
+fn seven() -> f32{
+    return 7.0;
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "shape", rename_all = "snake_case", deny_unknown_fields)]
+enum ObstacleSection{
+    Box{min: [f32; 3], max: [f32; 3]},
+    Cylinder{center: [f32; 3], radius: f32, axis: AxisName, length: Option<f32>},
+    Sphere{center: [f32; 3], radius: f32},
+    //file is relative to the directory of the scenario file, the mesh is first scaled and then translated
+    Mesh{file: PathBuf, scale: Option<f32>, translation: Option<[f32; 3]>},
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum AxisName{
+    X, Y, Z,
+}
+
+impl AxisName{
+    fn dimension(&self) -> usize{
+        return match self{
+            AxisName::X => 0,
+            AxisName::Y => 1,
+            AxisName::Z => 2,
+        };
+    }
+}
+
+//Read a scenario file and turn it into the configuration of a simulation
+pub fn load_scenario(path: &Path) -> Result<SimulationConfig, ScenarioError>{
+    let text=match std::fs::read_to_string(path){
+        Ok(text) => text,
+        Err(error) => {return Err(ScenarioError::Io{path: path.to_path_buf(), error})}
+    };
+    let base_directory=path.parent().unwrap_or(Path::new("."));
+    return parse_scenario_relative_to(&text, base_directory);
+}
+
+//Parse the contents of a scenario file, values that are left out get the value of SimulationConfig::default()
+//Files that are referenced in the scenario, like meshes, are looked up relative to the current directory.
+pub fn parse_scenario(text: &str) -> Result<SimulationConfig, ScenarioError>{
+    return parse_scenario_relative_to(text, Path::new("."));
+}
+
+//Parse the contents of a scenario file, files that are referenced in the scenario are looked up relative to base_directory
+pub fn parse_scenario_relative_to(text: &str, base_directory: &Path) -> Result<SimulationConfig, ScenarioError>{
+    let file: ScenarioFile=toml::from_str(text).map_err(ScenarioError::Parse)?;
+    let mut config=SimulationConfig::default();
+
+    for (dimension, &cells) in file.domain.cells.iter().enumerate(){
+        if cells<3{
+            return Err(invalid("domain.cells", format!("the domain needs at least 3 cells in every dimension, but has {} in the {} dimension", cells, ["x", "y", "z"][dimension])));
+        }
+    }
+    config.pressure_grid_size=file.domain.cells;
+    config.grid_element_scale=positive("domain.spacing", file.domain.spacing)?;
+    for axis in file.domain.periodic{
+        config.periodic[axis.dimension()]=true;
+    }
+
+    if let Some(density)=file.fluid.density{config.density=positive("fluid.density", density)?;}
+    if let Some(viscosity)=file.fluid.viscosity{config.viscosity=not_negative("fluid.viscosity", viscosity)?;}
+    if let Some(external_force)=file.fluid.external_force{config.external_force=finite_vector("fluid.external_force", external_force)?;}
+    if let Some(atmospheric_pressure)=file.fluid.atmospheric_pressure{config.atmospheric_pressure=finite("fluid.atmospheric_pressure", atmospheric_pressure)?;}
+
+    if let Some(step_size)=file.time.step_size{config.time_step_size=positive("time.step_size", step_size)?;}
+    config.adaptive_time_step=convert_adaptive_time_step(&file.time, config.time_step_size)?;
+
+    if let Some(max_iterations)=file.solver.max_iterations{
+        if max_iterations<1{
+            return Err(invalid("solver.max_iterations", format!("must be at least 1, got {}", max_iterations)));
+        }
+        config.max_iterations_per_time_frame=max_iterations;
+    }
+    if let Some(relaxation)=file.solver.relaxation{config.relaxation=positive("solver.relaxation", relaxation)?;}
+    if let Some(allowed_error)=file.solver.allowed_error{config.allowed_error=positive("solver.allowed_error", allowed_error)?;}
+    config.pressure_solver=convert_solver_method(&file.solver)?;
+    if let Some(threads)=file.solver.threads{config.threads=threads;}
+    match file.solver.kernels{
+        Some(KernelsName::Scalar) => config.stencil_kernels=StencilKernels::Scalar,
+        Some(KernelsName::Vectorized) => config.stencil_kernels=StencilKernels::Vectorized,
+        None => {}
+    }
+    config.convection_scheme=convert_convection_scheme(&file.schemes)?;
+    match file.schemes.diffusion{
+        Some(DiffusionName::Explicit) => config.diffusion_scheme=DiffusionScheme::Explicit,
+        Some(DiffusionName::Implicit) => config.diffusion_scheme=DiffusionScheme::Implicit,
+        Some(DiffusionName::CrankNicolson) => config.diffusion_scheme=DiffusionScheme::CrankNicolson,
+        None => {}
+    }
+    if config.diffusion_scheme==DiffusionScheme::Explicit{
+        if file.schemes.diffusion_solver.is_some(){
+            return Err(invalid("schemes.diffusion_solver", String::from("is only used by implicit and crank_nicolson diffusion")));
+        }
+        if file.schemes.diffusion_tolerance.is_some(){
+            return Err(invalid("schemes.diffusion_tolerance", String::from("is only used by implicit and crank_nicolson diffusion")));
+        }
+    }
+    match file.schemes.diffusion_solver{
+        Some(SolverMethodName::Legacy) => {return Err(invalid("schemes.diffusion_solver", String::from("can not be legacy, the legacy scheme only corrects the pressure")));}
+        Some(method) => config.diffusion_solver=default_solver(method),
+        None => {}
+    }
+    if let Some(tolerance)=file.schemes.diffusion_tolerance{config.diffusion_tolerance=positive("schemes.diffusion_tolerance", tolerance)?;}
+    match file.schemes.time_integration{
+        Some(TimeIntegrationName::ForwardEuler) => config.time_integration=TimeIntegration::ForwardEuler,
+        Some(TimeIntegrationName::Heun) => config.time_integration=TimeIntegration::Heun,
+        Some(TimeIntegrationName::SspRk3) => config.time_integration=TimeIntegration::SspRk3,
+        Some(TimeIntegrationName::AdamsBashforth) => config.time_integration=TimeIntegration::AdamsBashforth,
+        None => {}
+    }
+
+    if let Some(velocity)=file.initial.velocity{config.initial_velocity=finite_vector("initial.velocity", velocity)?;}
+
+    let walls=file.walls;
+    for (face, wall) in Face::ALL.into_iter().zip([walls.x_min, walls.x_max, walls.y_min, walls.y_max, walls.z_min, walls.z_max]){
+        if let Some(wall)=wall{
+            if config.periodic[face.dimension]{
+                return Err(invalid(&format!("walls.{}", face.name()), format!("the domain is periodic in {}, so there is no wall on this face", ["x", "y", "z"][face.dimension])));
+            }
+            config.walls[face.index()]=convert_wall(face, wall)?;
+        }
+    }
+
+    //A scenario without boundary patches is a closed box
+    config.boundary_patches=Vec::new();
+    for (i, boundary) in file.boundary.into_iter().enumerate(){
+        let patch=convert_boundary(i, boundary, &config, base_directory)?;
+        if let Some(other)=config.boundary_patches.iter().find(|other| patches_overlap(other, &patch)){
+            return Err(invalid(&format!("boundary[{}]", i), format!("patch \"{}\" overlaps with patch \"{}\" on the {} face", patch.name, other.name, patch.face.name())));
+        }
+        config.boundary_patches.push(patch);
+    }
+
+    for (i, obstacle) in file.obstacle.into_iter().enumerate(){
+        let obstacle=convert_obstacle(i, obstacle, base_directory)?;
+        //An obstacle that is smaller than a cell would silently disappear, which is most likely a mistake in the file
+        let mut solid_mask=SolidMask::cell_centered(config.pressure_grid_size, GHOST_LAYERS, false);
+        obstacle::mark_obstacle(&mut solid_mask, &config, &obstacle);
+        if !solid_mask.as_slice().contains(&true){
+            return Err(invalid(&format!("obstacle[{}]", i), String::from("does not contain the center of any cell, make it larger or use a smaller spacing")));
+        }
+        config.obstacles.push(obstacle);
+    }
+    //The values that are wrong for any config, not only for one from a file
+    config.validate().map_err(|error| match error{
+        SolverError::InvalidConfig{field, message} => invalid(field, message),
+        error => invalid("scenario", error.to_string()),
+    })?;
+    return Ok(config);
+}
+
+//A solver of the given method with the settings we use when the scenario does not give them
+fn default_solver(method: SolverMethodName) -> PressureSolver{
+    return match method{
+        SolverMethodName::Legacy => PressureSolver::Legacy,
+        SolverMethodName::Jacobi => PressureSolver::Jacobi,
+        SolverMethodName::RedBlackGaussSeidel => PressureSolver::RedBlackGaussSeidel,
+        SolverMethodName::Sor => PressureSolver::Sor{omega: 1.7},
+        SolverMethodName::ConjugateGradient => PressureSolver::ConjugateGradient{preconditioner: Preconditioner::IncompleteCholesky},
+        SolverMethodName::Multigrid => PressureSolver::Multigrid{cycle: MultigridCycle::V, smoothing_steps: 2},
+    };
+}
+
+fn convert_solver_method(solver: &SolverSection) -> Result<PressureSolver, ScenarioError>{
+    let method=match solver.method{
+        Some(method) => default_solver(method),
+        None => SimulationConfig::default().pressure_solver,
+    };
+    let method=match (method, solver.omega){
+        (PressureSolver::Sor{..}, Some(omega)) => PressureSolver::Sor{omega: finite("solver.omega", omega)?},
+        (_, Some(_)) => {return Err(invalid("solver.omega", String::from("is only used by the sor method")))}
+        (method, None) => method,
+    };
+    let method=match (method, solver.preconditioner){
+        (PressureSolver::ConjugateGradient{..}, Some(preconditioner)) => PressureSolver::ConjugateGradient{preconditioner: match preconditioner{
+            PreconditionerName::Jacobi => Preconditioner::Jacobi,
+            PreconditionerName::IncompleteCholesky => Preconditioner::IncompleteCholesky,
+        }},
+        (_, Some(_)) => {return Err(invalid("solver.preconditioner", String::from("is only used by the conjugate_gradient method")))}
+        (method, None) => method,
+    };
+    if let PressureSolver::Multigrid{cycle, smoothing_steps}=method{
+        let cycle=match solver.cycle{
+            Some(CycleName::V) => MultigridCycle::V,
+            Some(CycleName::W) => MultigridCycle::W,
+            None => cycle,
+        };
+        let smoothing_steps=solver.smoothing_steps.unwrap_or(smoothing_steps);
+        return Ok(PressureSolver::Multigrid{cycle, smoothing_steps});
+    }
+    if solver.cycle.is_some(){
+        return Err(invalid("solver.cycle", String::from("is only used by the multigrid method")));
+    }
+    if solver.smoothing_steps.is_some(){
+        return Err(invalid("solver.smoothing_steps", String::from("is only used by the multigrid method")));
+    }
+    return Ok(method);
+}
+
+fn convert_wall(face: Face, wall: WallSection) -> Result<WallCondition, ScenarioError>{
+    return Ok(match wall{
+        WallSection::NoSlip => WallCondition::NoSlip,
+        WallSection::FreeSlip => WallCondition::FreeSlip,
+        WallSection::Moving{velocity} => {
+            let field=format!("walls.{}.velocity", face.name());
+            finite_vector(&field, velocity)?;
+            if velocity[face.dimension]!=0.0{
+                return Err(invalid(&field, format!("a wall can only move along itself, so the {} component has to be zero, got {}", ["x", "y", "z"][face.dimension], velocity[face.dimension])));
+            }
+            WallCondition::Moving{velocity}
+        }
+    });
+}
+
+fn convert_boundary(i: usize, boundary: BoundarySection, config: &SimulationConfig, base_directory: &Path) -> Result<BoundaryPatch, ScenarioError>{
+    let field=format!("boundary[{}]", i);
+    let face=match boundary.face{
+        FaceName::XMin => Face{dimension: 0, upper: false},
+        FaceName::XMax => Face{dimension: 0, upper: true},
+        FaceName::YMin => Face{dimension: 1, upper: false},
+        FaceName::YMax => Face{dimension: 1, upper: true},
+        FaceName::ZMin => Face{dimension: 2, upper: false},
+        FaceName::ZMax => Face{dimension: 2, upper: true},
+    };
+    if config.periodic[face.dimension]{
+        return Err(invalid(&format!("{}.face", field), format!("the domain is periodic in {}, so there is no wall for a patch on the {} face", ["x", "y", "z"][face.dimension], face.name())));
+    }
+    let tangential_dimensions=face.tangential_dimensions();
+    let speed=match boundary.speed{
+        Some(speed) => Some(not_negative(&format!("{}.speed", field), speed)?),
+        None => None,
+    };
+    if boundary.pressure.is_some() && !matches!(boundary.kind, PatchKindName::Pressure){
+        return Err(invalid(&format!("{}.pressure", field), String::from("only patches of type \"pressure\" have a pressure")));
+    }
+    let kind=match boundary.kind{
+        PatchKindName::Inflow | PatchKindName::Outflow if speed.is_none() => {return Err(invalid(&format!("{}.speed", field), String::from("inflow and outflow patches need a speed")))}
+        PatchKindName::Inflow => PatchKind::Inflow,
+        PatchKindName::Outflow => PatchKind::Outflow,
+        PatchKindName::Pressure => PatchKind::Pressure{pressure: finite(&format!("{}.pressure", field), boundary.pressure.unwrap_or(config.atmospheric_pressure))?},
+        PatchKindName::ZeroGradient => PatchKind::ZeroGradient,
+        PatchKindName::Convective => PatchKind::Convective{speed},
+    };
+    if speed.is_some() && matches!(kind, PatchKind::Pressure{..} | PatchKind::ZeroGradient){
+        return Err(invalid(&format!("{}.speed", field), String::from("the speed through an open boundary follows from the flow, only inflow, outflow and convective patches have a speed")));
+    }
+    if kind!=PatchKind::Inflow && kind!=PatchKind::Outflow && !(matches!(boundary.profile, ProfileSection::Constant) && matches!(boundary.spatial_profile, SpatialProfileSection::Uniform)){
+        return Err(invalid(&field, String::from("only inflow and outflow patches can have a profile, the velocity through an open boundary follows from the flow")));
+    }
+    let profile=match boundary.profile{
+        ProfileSection::Constant => TimeProfile::Constant,
+        ProfileSection::Sigmoid{midpoint, width} => TimeProfile::Sigmoid{
+            midpoint: finite(&format!("{}.profile.midpoint", field), midpoint)?,
+            width: finite(&format!("{}.profile.width", field), width)?,
+        },
+        ProfileSection::Ramp{start, duration} => TimeProfile::Ramp{
+            start: finite(&format!("{}.profile.start", field), start)?,
+            duration: finite(&format!("{}.profile.duration", field), duration)?,
+        },
+        ProfileSection::Sinusoid{mean, amplitude, frequency, phase} => TimeProfile::Sinusoid{
+            mean: finite(&format!("{}.profile.mean", field), mean)?,
+            amplitude: finite(&format!("{}.profile.amplitude", field), amplitude)?,
+            frequency: not_negative(&format!("{}.profile.frequency", field), frequency)?,
+            phase: finite(&format!("{}.profile.phase", field), phase)?,
+        },
+        ProfileSection::Table{file} => {
+            let file_field=format!("{}.profile.file", field);
+            let rows=read_csv(&base_directory.join(file)).map_err(|message| invalid(&file_field, message))?;
+            if let Some(row)=rows.iter().position(|row| row.len()!=2){
+                return Err(invalid(&file_field, format!("every line needs a time and a factor, line {} of the table has {} values", row+1, rows[row].len())));
+            }
+            TimeProfile::Table{times: rows.iter().map(|row| row[0]).collect(), factors: rows.iter().map(|row| row[1]).collect()}
+        }
+    };
+    let across=|across: Option<AxisName>| -> Result<Option<usize>, ScenarioError>{
+        return match across{
+            Some(axis) => match tangential_dimensions.iter().position(|&dimension| dimension==axis.dimension()){
+                Some(index) => Ok(Some(index)),
+                None => Err(invalid(&format!("{}.spatial_profile.across", field), format!("{} is orthogonal to the {} face, the profile has to vary along the face", ["x", "y", "z"][axis.dimension()], face.name()))),
+            },
+            None => Ok(None),
+        };
+    };
+    let spatial_profile=match boundary.spatial_profile{
+        SpatialProfileSection::Uniform => SpatialProfile::Uniform,
+        SpatialProfileSection::Parabolic{across: axis} => SpatialProfile::Parabolic{across: across(axis)?},
+        SpatialProfileSection::PowerLaw{exponent, across: axis} => SpatialProfile::PowerLaw{
+            exponent: finite(&format!("{}.spatial_profile.exponent", field), exponent)?,
+            across: across(axis)?,
+        },
+        SpatialProfileSection::Table{file} => {
+            let file_field=format!("{}.spatial_profile.file", field);
+            SpatialProfile::Table{values: read_csv(&base_directory.join(file)).map_err(|message| invalid(&file_field, message))?}
+        }
+    };
+    return Ok(BoundaryPatch{
+        name: boundary.name.unwrap_or(field),
+        face,
+        min_cells: boundary.min,
+        max_cells: boundary.max,
+        kind,
+        speed: speed.unwrap_or(0.0),
+        profile,
+        spatial_profile,
+    });
+}
+
+fn convert_obstacle(i: usize, obstacle: ObstacleSection, base_directory: &Path) -> Result<Obstacle, ScenarioError>{
+    let field=format!("obstacle[{}]", i);
+    return Ok(match obstacle{
+        ObstacleSection::Box{min, max} => {
+            finite_vector(&format!("{}.min", field), min)?;
+            finite_vector(&format!("{}.max", field), max)?;
+            if (0..3).any(|j| min[j]>=max[j]){
+                return Err(invalid(&format!("{}.min", field), format!("{:?} must be smaller than max {:?} in every dimension", min, max)));
+            }
+            Obstacle::Box{min, max}
+        }
+        ObstacleSection::Cylinder{center, radius, axis, length} => Obstacle::Cylinder{
+            center: finite_vector(&format!("{}.center", field), center)?,
+            radius: positive(&format!("{}.radius", field), radius)?,
+            axis: axis.dimension(),
+            length: match length{
+                Some(length) => Some(positive(&format!("{}.length", field), length)?),
+                None => None,
+            },
+        },
+        ObstacleSection::Sphere{center, radius} => Obstacle::Sphere{
+            center: finite_vector(&format!("{}.center", field), center)?,
+            radius: positive(&format!("{}.radius", field), radius)?,
+        },
+        ObstacleSection::Mesh{file, scale, translation} => {
+            let scale=positive(&format!("{}.scale", field), scale.unwrap_or(1.0))?;
+            let translation=finite_vector(&format!("{}.translation", field), translation.unwrap_or([0.0; 3]))?;
+            let mesh=TriangleMesh::load(&base_directory.join(file)).map_err(|error| invalid(&format!("{}.file", field), error.to_string()))?;
+            Obstacle::Mesh(mesh.transformed(scale, translation))
+        }
+    });
+}
+
+fn patches_overlap(a: &BoundaryPatch, b: &BoundaryPatch) -> bool{
+    return a.face==b.face
+        && a.min_cells[0]<=b.max_cells[0] && b.min_cells[0]<=a.max_cells[0]
+        && a.min_cells[1]<=b.max_cells[1] && b.min_cells[1]<=a.max_cells[1];
+}
+
+fn convert_convection_scheme(schemes: &SchemesSection) -> Result<ConvectionScheme, ScenarioError>{
+    let scheme=match schemes.convection{
+        Some(ConvectionName::Central) => ConvectionScheme::Central,
+        Some(ConvectionName::Upwind) => ConvectionScheme::Upwind,
+        Some(ConvectionName::Hybrid) => ConvectionScheme::Hybrid,
+        Some(ConvectionName::Quick) => ConvectionScheme::Quick,
+        Some(ConvectionName::Tvd) => ConvectionScheme::Tvd{limiter: FluxLimiter::VanLeer},
+        Some(ConvectionName::SemiLagrangian) => ConvectionScheme::SemiLagrangian{correction: AdvectionCorrection::MacCormack},
+        None => SimulationConfig::default().convection_scheme,
+    };
+    let scheme=match (scheme, schemes.correction){
+        (ConvectionScheme::SemiLagrangian{..}, Some(correction)) => ConvectionScheme::SemiLagrangian{correction: match correction{
+            CorrectionName::None => AdvectionCorrection::None,
+            CorrectionName::MacCormack => AdvectionCorrection::MacCormack,
+            CorrectionName::Bfecc => AdvectionCorrection::Bfecc,
+        }},
+        (_, Some(_)) => {return Err(invalid("schemes.correction", String::from("is only used by the semi_lagrangian convection scheme")));}
+        (scheme, None) => scheme,
+    };
+    return match (scheme, schemes.limiter){
+        (ConvectionScheme::Tvd{..}, Some(limiter)) => Ok(ConvectionScheme::Tvd{limiter: match limiter{
+            LimiterName::Minmod => FluxLimiter::Minmod,
+            LimiterName::VanLeer => FluxLimiter::VanLeer,
+            LimiterName::Superbee => FluxLimiter::Superbee,
+        }}),
+        (_, Some(_)) => Err(invalid("schemes.limiter", String::from("is only used by the tvd convection scheme"))),
+        (scheme, None) => Ok(scheme),
+    };
+}
+
+//The limits of the adaptive time step, or None for a fixed time step
+fn convert_adaptive_time_step(time: &TimeSection, step_size: f32) -> Result<Option<AdaptiveTimeStep>, ScenarioError>{
+    if !time.adaptive.unwrap_or(false){
+        for (field, value) in [("time.cfl_number", time.cfl_number), ("time.diffusion_number", time.diffusion_number), ("time.min_step_size", time.min_step_size), ("time.max_step_size", time.max_step_size)]{
+            if value.is_some(){
+                return Err(invalid(field, String::from("is only used when time.adaptive is true")));
+            }
+        }
+        return Ok(None);
+    }
+    let cfl_number=finite("time.cfl_number", time.cfl_number.unwrap_or(0.5))?;
+    let diffusion_number=finite("time.diffusion_number", time.diffusion_number.unwrap_or(0.15))?;
+    let max_step_size=positive("time.max_step_size", time.max_step_size.unwrap_or(step_size))?;
+    let min_step_size=positive("time.min_step_size", time.min_step_size.unwrap_or(max_step_size/1000.0))?;
+    if min_step_size>max_step_size{
+        return Err(invalid("time.min_step_size", format!("must not be larger than the maximum step size {}, got {}", max_step_size, min_step_size)));
+    }
+    return Ok(Some(AdaptiveTimeStep{cfl_number, diffusion_number, min_step_size, max_step_size}));
+}
+
+fn invalid(field: &str, message: String) -> ScenarioError{
+    return ScenarioError::Invalid{field: field.to_string(), message};
+}
+
+fn finite(field: &str, value: f32) -> Result<f32, ScenarioError>{
+    if !value.is_finite(){
+        return Err(invalid(field, format!("must be a finite number, got {}", value)));
+    }
+    return Ok(value);
+}
+
+fn positive(field: &str, value: f32) -> Result<f32, ScenarioError>{
+    //finite has already rejected NaN
+    if finite(field, value)?<=0.0{
+        return Err(invalid(field, format!("must be larger than zero, got {}", value)));
+    }
+    return Ok(value);
+}
+
+fn not_negative(field: &str, value: f32) -> Result<f32, ScenarioError>{
+    if finite(field, value)?<0.0{
+        return Err(invalid(field, format!("can not be negative, got {}", value)));
+    }
+    return Ok(value);
+}
+
+fn finite_vector(field: &str, vector: [f32; 3]) -> Result<[f32; 3], ScenarioError>{
+    for value in vector{
+        finite(field, value)?;
+    }
+    return Ok(vector);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn shipped_scenario(name: &str) -> SimulationConfig{
+        let path=Path::new(env!("CARGO_MANIFEST_DIR")).join("../scenarios").join(name);
+        return load_scenario(&path).unwrap_or_else(|error| panic!("{}: {}", name, error));
+    }
+
+    //The message of a scenario that is a small closed box with the given sections added
+    fn rejected(sections: &str) -> String{
+        let text=format!("[domain]\ncells = [10, 10, 10]\nspacing = 0.01\n\n{}", sections);
+        return match parse_scenario(&text){
+            Ok(_) => panic!("the scenario was accepted:\n{}", sections),
+            Err(error) => error.to_string(),
+        };
+    }
+
+    #[test]
+    fn default_scenario_is_the_default_config(){
+        let config=shipped_scenario("default.toml");
+        let default=SimulationConfig::default();
+        assert_eq!(config.pressure_grid_size, default.pressure_grid_size);
+        assert_eq!(config.grid_element_scale, default.grid_element_scale);
+        assert_eq!(config.density, default.density);
+        assert_eq!(config.viscosity, default.viscosity);
+        assert_eq!(config.time_step_size, default.time_step_size);
+        assert_eq!(config.pressure_solver, PressureSolver::Sor{omega: 1.7});
+        assert_eq!(config.walls, default.walls);
+        assert_eq!(config.boundary_patches.len(), 2);
+        for (patch, expected) in config.boundary_patches.iter().zip(default.boundary_patches.iter()){
+            assert_eq!(patch.name, expected.name);
+            assert_eq!(patch.face, expected.face);
+            assert_eq!((patch.min_cells, patch.max_cells), (expected.min_cells, expected.max_cells));
+            assert_eq!(patch.kind, expected.kind);
+            assert_eq!(patch.speed, expected.speed);
+        }
+    }
+
+    #[test]
+    fn lid_driven_cavity_scenario(){
+        let config=shipped_scenario("lid_driven_cavity.toml");
+        assert_eq!(config.pressure_grid_size, [20, 20, 20]);
+        assert!(config.boundary_patches.is_empty());
+        assert_eq!(config.walls[Face{dimension: 2, upper: true}.index()], WallCondition::Moving{velocity: [0.01, 0.0, 0.0]});
+        assert_eq!(config.walls[Face{dimension: 1, upper: false}.index()], WallCondition::FreeSlip);
+        assert_eq!(config.walls[Face{dimension: 0, upper: false}.index()], WallCondition::NoSlip);
+    }
+
+    #[test]
+    fn periodic_channel_scenario(){
+        let config=shipped_scenario("periodic_channel.toml");
+        assert_eq!(config.pressure_grid_size, [10, 10, 16]);
+        assert_eq!(config.periodic, [true, true, false]);
+        assert_eq!(config.external_force, [0.01, 0.0, 0.0]);
+        assert!(config.boundary_patches.is_empty());
+    }
+
+    #[test]
+    fn unknown_field_is_named(){
+        let message=rejected("[fluid]\nviscosty = 0.001\n");
+        assert!(message.contains("viscosty"), "{}", message);
+    }
+
+    #[test]
+    fn overlapping_patches_are_rejected(){
+        let patch=|name: &str, min: &str, max: &str| format!("[[boundary]]\nname = \"{}\"\nface = \"x_min\"\nmin = {}\nmax = {}\ntype = \"inflow\"\nspeed = 0.1\n\n", name, min, max);
+        let message=rejected(&(patch("first", "[2, 2]", "[5, 5]")+&patch("second", "[5, 4]", "[7, 7]")));
+        assert_eq!(message, "boundary[1]: patch \"second\" overlaps with patch \"first\" on the x_min face");
+        //Patches that only touch are allowed
+        let text=format!("[domain]\ncells = [10, 10, 10]\nspacing = 0.01\n\n{}{}", patch("first", "[2, 2]", "[5, 5]"), patch("second", "[6, 2]", "[7, 5]"));
+        assert_eq!(parse_scenario(&text).unwrap().boundary_patches.len(), 2);
+    }
+
+    #[test]
+    fn obstacle_between_the_cell_centers_is_rejected(){
+        //The cell centers are at 0.005, 0.015, ..., so this box lies between two of them in every dimension
+        let message=rejected("[[obstacle]]\nshape = \"box\"\nmin = [0.046, 0.046, 0.046]\nmax = [0.054, 0.054, 0.054]\n");
+        assert!(message.starts_with("obstacle[0]: does not contain the center of any cell"), "{}", message);
+    }
+
+    #[test]
+    fn method_specific_keys_need_their_method(){
+        assert_eq!(rejected("[solver]\nmethod = \"sor\"\ncycle = \"w\"\n"), "solver.cycle: is only used by the multigrid method");
+        assert_eq!(rejected("[solver]\nmethod = \"jacobi\"\nsmoothing_steps = 3\n"), "solver.smoothing_steps: is only used by the multigrid method");
+        assert_eq!(rejected("[solver]\nmethod = \"multigrid\"\nomega = 1.5\n"), "solver.omega: is only used by the sor method");
+        assert_eq!(rejected("[solver]\nmethod = \"sor\"\npreconditioner = \"jacobi\"\n"), "solver.preconditioner: is only used by the conjugate_gradient method");
+    }
+}
@@ -0,0 +1,180 @@
+//Semi-Lagrangian advection: the new velocity at a point is the old velocity at the place where the fluid at that point came from (the departure point).
+//The departure point is found by tracing the velocities back in time and the old velocity there is interpolated from the points around it.
+//An interpolated value is never larger or smaller than the values it comes from, so this is stable for any time step, unlike the explicit convection term.
+//Positions are measured in cells from the lower corner of the domain, so the point of cell (i,j,k) has position (i+0.5, j+0.5, k+0.5) and its lower x face (i, j+0.5, k+0.5).
+use rayon::prelude::*;
+
+use crate::{Field3D, SimulationConfig, SolidMask, VelocityGrid, boundary, obstacle, solved_range};
+
+//A correction of the error of the interpolation. Both trace the result back again and use the difference with the old velocities to make the result second order.
+//The corrected value is kept between the values it was interpolated from, so the correction does not make the step unstable.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdvectionCorrection{
+    None,//First order, the interpolation smooths the flow a lot
+    MacCormack,//Corrects the result with half the error of tracing it back, three traces per step
+    Bfecc,//Back and forth error compensation: corrects the old velocities with half the error and traces them again, four traces per step
+}
+
+//The velocities of the three grids carried by themselves over one time step
+pub(crate) fn advect_velocities(velocities: [&VelocityGrid; 3], solid_mask: &SolidMask, correction: AdvectionCorrection, dt: f32, config: &SimulationConfig) -> [VelocityGrid; 3]{
+    let [mut velocity_x, mut velocity_y, mut velocity_z]=[0, 1, 2].map(|dimension| VelocityGrid{grid: advect(&velocities[dimension].grid, velocities, dimension, solid_mask, correction, dt, config), dimension});
+    //The stencils of the prediction read across the periodic seams
+    boundary::wrap_periodic_velocities(&mut velocity_x, &mut velocity_y, &mut velocity_z, config);
+    return [velocity_x, velocity_y, velocity_z];
+}
+
+//A field with the layout of velocity grid `dimension` carried by the velocities over one time step
+fn advect(field: &Field3D, velocities: [&VelocityGrid; 3], dimension: usize, solid_mask: &SolidMask, correction: AdvectionCorrection, dt: f32, config: &SimulationConfig) -> Field3D{
+    let forward=trace(field, velocities, dimension, solid_mask, dt, config, |_, departure| interpolate(field, departure, config).0);
+    if correction==AdvectionCorrection::None{
+        return forward;
+    }
+    //Carried back again the result should give the old field, the difference is the error of the interpolation
+    let backward=trace(&forward, velocities, dimension, solid_mask, -dt, config, |_, departure| interpolate(&forward, departure, config).0);
+    if correction==AdvectionCorrection::MacCormack{
+        return trace(field, velocities, dimension, solid_mask, dt, config, |point, departure| {
+            let (_, min, max)=interpolate(field, departure, config);
+            (forward[point]+0.5*(field[point]-backward[point])).clamp(min, max)
+        });
+    }
+    let mut corrected=field.clone();
+    for ((corrected, old), backward) in corrected.as_mut_slice().iter_mut().zip(field.as_slice()).zip(backward.as_slice()){
+        *corrected=old+0.5*(old-backward);
+    }
+    return trace(field, velocities, dimension, solid_mask, dt, config, |_, departure| {
+        let (_, min, max)=interpolate(field, departure, config);
+        interpolate(&corrected, departure, config).0.clamp(min, max)
+    });
+}
+
+//A copy of field (with the layout of velocity grid `dimension`) where every velocity that the solver calculates is value(point, departure point),
+//with the departure point the place where the fluid at the point was `time` seconds ago. A negative time gives the place where it will be.
+fn trace<F: Fn([usize; 3], [f32; 3]) -> f32 + Sync>(field: &Field3D, velocities: [&VelocityGrid; 3], dimension: usize, solid_mask: &SolidMask, time: f32, config: &SimulationConfig, value: F) -> Field3D{
+    let mut traced=field.clone();
+    let velocity_grid=velocities[dimension];
+    let (range_x, range_y, range_z)=(solved_range(velocity_grid, 0, config), solved_range(velocity_grid, 1, config), solved_range(velocity_grid, 2, config));
+    let row_length=field.shape()[2];
+    let (ghost, staggering)=(field.ghost(), field.staggering());
+    let cells_per_second=time/config.grid_element_scale;
+    traced.par_layers_mut(range_x).for_each(|(x, layer)| {
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                //The velocities on and inside obstacles are set by the obstacle boundary conditions
+                if obstacle::is_solid_face(solid_mask, dimension, x, y, z){
+                    continue;
+                }
+                let point=[x, y, z];
+                let position=[0, 1, 2].map(|k| point[k] as f32-ghost as f32+0.5*(1-staggering[k]) as f32);
+                //Second order Runge-Kutta: the velocity halfway gives the departure point
+                let velocity=velocity_at(velocities, position, config);
+                let halfway=[0, 1, 2].map(|k| position[k]-0.5*cells_per_second*velocity[k]);
+                let velocity=velocity_at(velocities, halfway, config);
+                let departure=[0, 1, 2].map(|k| position[k]-cells_per_second*velocity[k]);
+                layer[y*row_length+z]=value(point, departure);
+            }
+        }
+    });
+    return traced;
+}
+
+fn velocity_at(velocities: [&VelocityGrid; 3], position: [f32; 3], config: &SimulationConfig) -> [f32; 3]{
+    return velocities.map(|velocity_grid| interpolate(&velocity_grid.grid, position, config).0);
+}
+
+//Trilinear interpolation of a field at a position, together with the smallest and the largest of the eight values it is interpolated from.
+//Positions outside of the stored values get the value on the edge, in a periodic dimension the position wraps around.
+fn interpolate(field: &Field3D, position: [f32; 3], config: &SimulationConfig) -> (f32, f32, f32){
+    let (ghost, staggering, shape)=(field.ghost(), field.staggering(), field.shape());
+    let mut lower=[0; 3];
+    let mut weight=[0.0; 3];
+    for k in 0..3{
+        let mut index=position[k]+ghost as f32-0.5*(1-staggering[k]) as f32;
+        if config.periodic[k]{
+            index=ghost as f32+(index-ghost as f32).rem_euclid(field.cells()[k] as f32);
+        }
+        let index=index.clamp(0.0, (shape[k]-1) as f32);
+        lower[k]=(index.floor() as usize).min(shape[k]-2);
+        weight[k]=index-lower[k] as f32;
+    }
+    let (mut value, mut min, mut max)=(0.0, f32::INFINITY, f32::NEG_INFINITY);
+    for corner in 0..8{
+        let offset=[(corner>>2)&1, (corner>>1)&1, corner&1];
+        let corner_value=field[[lower[0]+offset[0], lower[1]+offset[1], lower[2]+offset[2]]];
+        let corner_weight: f32=(0..3).map(|k| if offset[k]==1 {weight[k]} else {1.0-weight[k]}).product();
+        value+=corner_weight*corner_value;
+        min=min.min(corner_value);
+        max=max.max(corner_value);
+    }
+    return (value, min, max);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::obstacle::build_solid_mask;
+
+    //A periodic domain of 8x6x4 cells with cells of a quarter meter, so the distances are exact in floating point
+    fn periodic_config() -> SimulationConfig{
+        return SimulationConfig{pressure_grid_size: [8, 6, 4], grid_element_scale: 0.25, periodic: [true, true, true], boundary_patches: Vec::new(), ..SimulationConfig::default()};
+    }
+
+    //The same velocity everywhere
+    fn uniform_flow(velocity: [f32; 3], config: &SimulationConfig) -> [VelocityGrid; 3]{
+        return [0, 1, 2].map(|dimension| VelocityGrid{grid: Field3D::staggered(config.pressure_grid_size, crate::GHOST_LAYERS, dimension, velocity[dimension]), dimension});
+    }
+
+    //A rough field with the layout of the z velocities, the ghost layers are wrapped around
+    fn rough_field(config: &SimulationConfig) -> Field3D{
+        let mut field=Field3D::staggered(config.pressure_grid_size, crate::GHOST_LAYERS, 2, 0.0);
+        for (index, value) in field.as_mut_slice().iter_mut().enumerate(){
+            *value=((index*7919)%101) as f32/101.0;
+        }
+        boundary::wrap_periodic_field(&mut field, config);
+        return field;
+    }
+
+    #[test]
+    fn uniform_flow_moves_a_profile_by_whole_cells(){
+        let config=periodic_config();
+        let flow=uniform_flow([1.0, -0.5, 0.0], &config);
+        let field=rough_field(&config);
+        let ghost=field.ghost();
+        //In 0.5 s the flow moves 2 cells in x and -1 cell in y
+        for correction in [AdvectionCorrection::None, AdvectionCorrection::MacCormack, AdvectionCorrection::Bfecc]{
+            let advected=advect(&field, [&flow[0], &flow[1], &flow[2]], 2, &build_solid_mask(&config), correction, 0.5, &config);
+            let velocity_grid=VelocityGrid{grid: field.clone(), dimension: 2};
+            for x in solved_range(&velocity_grid, 0, &config){
+                for y in solved_range(&velocity_grid, 1, &config){
+                    for z in solved_range(&velocity_grid, 2, &config){
+                        let departure=[(x-ghost+8-2)%8+ghost, (y-ghost+1)%6+ghost, z];
+                        assert_eq!(advected[[x,y,z]], field[departure], "{:?} at {:?}", correction, [x, y, z]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrections_stay_between_the_interpolated_values(){
+        let config=periodic_config();
+        let flow=uniform_flow([1.0, 0.4, 0.0], &config);
+        let field=rough_field(&config);
+        let solid_mask=build_solid_mask(&config);
+        let uncorrected=advect(&field, [&flow[0], &flow[1], &flow[2]], 2, &solid_mask, AdvectionCorrection::None, 0.075, &config);
+        //In 0.075 s the flow moves 0.3 cells in x and 0.12 cells in y, so every value is interpolated from the eight points from one lower in x and y to one higher in z
+        for correction in [AdvectionCorrection::MacCormack, AdvectionCorrection::Bfecc]{
+            let advected=advect(&field, [&flow[0], &flow[1], &flow[2]], 2, &solid_mask, correction, 0.075, &config);
+            assert!(advected!=uncorrected, "{:?} does not correct anything", correction);
+            let velocity_grid=VelocityGrid{grid: field.clone(), dimension: 2};
+            for x in solved_range(&velocity_grid, 0, &config){
+                for y in solved_range(&velocity_grid, 1, &config){
+                    for z in solved_range(&velocity_grid, 2, &config){
+                        let neighbors=(0..8).map(|corner| field[[x-1+((corner>>2)&1), y-1+((corner>>1)&1), z+(corner&1)]]);
+                        let (min, max)=neighbors.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)));
+                        assert!(advected[[x,y,z]]>=min && advected[[x,y,z]]<=max, "{:?} gives {} at {:?}, outside of {} to {}", correction, advected[[x,y,z]], [x, y, z], min, max);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//The preconditioned conjugate gradient method. The matrix is only applied with its stencil, it is never stored in full.
+//The number of iterations grows with the number of cells in one dimension instead of with the number of cells, so it is much faster than SOR on large grids.
+use rayon::prelude::*;
+
+use super::{LinearSolver, Preconditioner, ResidualNorms, SolveStatistics, StencilMatrix, matrix::{deterministic_sum, norms}};
+
+#[derive(Clone, Debug)]
+pub struct ConjugateGradientSolver{
+    preconditioner: Preconditioner,
+    incomplete_cholesky: Vec<f32>,//The diagonal of the incomplete Cholesky factor, the other nonzeros of the factor follow from it
+}
+
+impl ConjugateGradientSolver{
+    pub fn new(preconditioner: Preconditioner) -> Self{
+        return Self{preconditioner, incomplete_cholesky: Vec::new()};
+    }
+    //z = M^-1 r
+    fn precondition(&self, matrix: &StencilMatrix, residual: &[f32], z: &mut [f32]){
+        match self.preconditioner{
+            Preconditioner::Jacobi => {
+                z.par_iter_mut().enumerate().for_each(|(row, z)| {
+                    let diagonal=matrix.diagonal(row);
+                    *z=if diagonal==0.0 {0.0} else {residual[row]/diagonal};
+                });
+            }
+            Preconditioner::IncompleteCholesky => {
+                let factor=&self.incomplete_cholesky;
+                //The triangular solves depend on the previous unknowns, so they can not run in parallel
+                //Solve L q = r from the first unknown to the last, q is stored in z
+                for row in 0..z.len(){
+                    if factor[row]==0.0{
+                        z[row]=0.0;
+                        continue;
+                    }
+                    let lower: f32=matrix.neighbors(row).filter(|&(column, _)| column<row).map(|(column, weight)| weight*z[column]/factor[column]).sum();
+                    z[row]=(residual[row]+lower)/factor[row];
+                }
+                //Solve L^T z = q from the last unknown to the first
+                for row in (0..z.len()).rev(){
+                    if factor[row]==0.0{
+                        continue;
+                    }
+                    let upper: f32=matrix.neighbors(row).filter(|&(column, _)| column>row).map(|(column, weight)| weight*z[column]).sum();
+                    z[row]=(z[row]+upper/factor[row])/factor[row];
+                }
+            }
+        }
+    }
+}
+
+impl LinearSolver for ConjugateGradientSolver{
+    fn prepare(&mut self, matrix: &StencilMatrix){
+        self.incomplete_cholesky=match self.preconditioner{
+            Preconditioner::IncompleteCholesky => incomplete_cholesky(matrix),
+            Preconditioner::Jacobi => Vec::new(),
+        };
+    }
+    fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics{
+        return self.solve_with_history(matrix, x, b, tolerance, max_iterations, &mut Vec::new());
+    }
+    fn solve_with_history(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize, history: &mut Vec<ResidualNorms>) -> SolveStatistics{
+        let n=matrix.size();
+        let mut residual=vec![0.0; n];
+        matrix.residual(x, b, &mut residual);
+        let mut z=vec![0.0; n];
+        self.precondition(matrix, &residual, &mut z);
+        let mut direction=z.clone();
+        let mut product=vec![0.0; n];
+        let mut residual_dot_z=dot(&residual, &z);
+        for iteration in 0..max_iterations{
+            //The updated residual, it drifts a little from b - A x by rounding
+            let norms=norms(n, |row| residual[row]);
+            history.push(norms);
+            if norms.max<=tolerance{
+                return measure_last(matrix, x, b, iteration, tolerance, history);
+            }
+            matrix.apply(&direction, &mut product);
+            let curvature=dot(&direction, &product);
+            if curvature<=0.0{//Only happens when the remaining residual can not be reduced any further, for example in a cell that is cut off from the rest of the fluid
+                return measure_last(matrix, x, b, iteration, tolerance, history);
+            }
+            let step=(residual_dot_z/curvature) as f32;
+            x.par_iter_mut().zip(direction.par_iter()).for_each(|(x, direction)| *x+=step*direction);
+            residual.par_iter_mut().zip(product.par_iter()).for_each(|(residual, product)| *residual-=step*product);
+            self.precondition(matrix, &residual, &mut z);
+            let next_residual_dot_z=dot(&residual, &z);
+            let beta=(next_residual_dot_z/residual_dot_z) as f32;
+            residual_dot_z=next_residual_dot_z;
+            direction.par_iter_mut().zip(z.par_iter()).for_each(|(direction, z)| *direction=z+beta*(*direction));
+        }
+        let statistics=SolveStatistics::measure(matrix, x, b, max_iterations, tolerance);
+        history.push(statistics.norms());
+        return statistics;
+    }
+}
+
+//The statistics of the final x. The last value of the history is the updated residual, it is replaced by b - A x so the history ends with the reported residual.
+fn measure_last(matrix: &StencilMatrix, x: &[f32], b: &[f32], iterations: usize, tolerance: f32, history: &mut [ResidualNorms]) -> SolveStatistics{
+    let statistics=SolveStatistics::measure(matrix, x, b, iterations, tolerance);
+    if let Some(last)=history.last_mut(){
+        *last=statistics.norms();
+    }
+    return statistics;
+}
+
+//The diagonal of the incomplete Cholesky factor L of the matrix, A ~ L L^T with L only nonzero where A is.
+//The value of L below the diagonal in row i and column j is -weight_ij/L_jj.
+fn incomplete_cholesky(matrix: &StencilMatrix) -> Vec<f32>{
+    let mut factor=vec![0.0f32; matrix.size()];
+    for row in 0..matrix.size(){
+        let diagonal=matrix.diagonal(row);
+        let mut value=diagonal;
+        for (column, weight) in matrix.neighbors(row).filter(|&(column, _)| column<row){
+            value-=(weight/factor[column]).powi(2);
+        }
+        //The incomplete factorisation can break down, then we fall back to the diagonal for this unknown
+        if value<0.25*diagonal{
+            value=diagonal;
+        }
+        factor[row]=value.sqrt();
+    }
+    return factor;
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f64{
+    return deterministic_sum(a.len(), |i| a[i] as f64*b[i] as f64);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::solver::tests::assert_solves;
+
+    #[test]
+    fn jacobi_preconditioned_solves_the_manufactured_problem(){
+        for periodic in [false, true]{
+            assert_solves(&mut ConjugateGradientSolver::new(Preconditioner::Jacobi), 8, periodic);
+        }
+    }
+
+    #[test]
+    fn incomplete_cholesky_preconditioned_solves_the_manufactured_problem_in_fewer_iterations(){
+        for periodic in [false, true]{
+            let jacobi=assert_solves(&mut ConjugateGradientSolver::new(Preconditioner::Jacobi), 8, periodic);
+            let incomplete_cholesky=assert_solves(&mut ConjugateGradientSolver::new(Preconditioner::IncompleteCholesky), 8, periodic);
+            assert!(incomplete_cholesky<jacobi, "{} iterations with incomplete Cholesky against {} with Jacobi", incomplete_cholesky, jacobi);
+        }
+    }
+}
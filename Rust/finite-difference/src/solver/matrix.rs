@@ -0,0 +1,149 @@
+//The matrix of the pressure equation. It is never stored as a full matrix: every row belongs to a fluid cell and couples it to at most six neighbouring cells.
+//Row i is the equation diagonal_i x_i - sum over the neighbours j of weight_ij x_j = b_i. The matrix is symmetric, weight_ij = weight_ji.
+//The operations on all rows run in parallel on the threads of rayon.
+use rayon::prelude::*;
+
+use super::ResidualNorms;
+
+//The number of values that one thread adds up at a time in deterministic_sum and norms
+const BLOCK: usize = 4096;
+
+//A symmetric matrix with the 7 point stencil of a grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct StencilMatrix{
+    cells: [usize; 3],
+    coordinates: Vec<[usize; 3]>,
+    diagonal: Vec<f32>,
+    neighbors: Vec<[usize; 6]>,//Only the first neighbor_count are used
+    weights: Vec<[f32; 6]>,
+    neighbor_count: Vec<u8>,
+    red: Vec<usize>,
+    black: Vec<usize>,
+}
+
+impl StencilMatrix{
+    //A matrix without couplings, for the cells of a grid with `cells` cells in every dimension. coordinates are the coordinates of the cell of every row, counted from 0.
+    pub fn new(cells: [usize; 3], coordinates: Vec<[usize; 3]>) -> Self{
+        let n=coordinates.len();
+        let (mut red, mut black)=(Vec::new(), Vec::new());
+        for (row, coordinate) in coordinates.iter().enumerate(){
+            if (coordinate[0]+coordinate[1]+coordinate[2])%2==0 {red.push(row)} else {black.push(row)}
+        }
+        return Self{cells, coordinates, diagonal: vec![0.0; n], neighbors: vec![[0; 6]; n], weights: vec![[0.0; 6]; n], neighbor_count: vec![0; n], red, black};
+    }
+    pub fn add_to_diagonal(&mut self, row: usize, value: f32){
+        self.diagonal[row]+=value;
+    }
+    //Add weight to the coupling of row to column, only this row is changed
+    pub fn add_coupling(&mut self, row: usize, column: usize, weight: f32){
+        let count=self.neighbor_count[row] as usize;
+        match self.neighbors[row][..count].iter().position(|&neighbor| neighbor==column){
+            Some(position) => self.weights[row][position]+=weight,
+            None => {
+                assert!(count<6, "A cell can not have more than six neighbours");
+                self.neighbors[row][count]=column;
+                self.weights[row][count]=weight;
+                self.neighbor_count[row]+=1;
+            }
+        }
+    }
+    //The number of rows, which is the number of unknowns
+    pub fn size(&self) -> usize{
+        return self.diagonal.len();
+    }
+    //The number of cells of the grid in every dimension, some of them may not have a row because they are solid
+    pub fn cells(&self) -> [usize; 3]{
+        return self.cells;
+    }
+    //The grid coordinates of the cell of a row, counted from 0
+    pub fn coordinates(&self, row: usize) -> [usize; 3]{
+        return self.coordinates[row];
+    }
+    pub fn diagonal(&self, row: usize) -> f32{
+        return self.diagonal[row];
+    }
+    //The columns and weights of the couplings of a row, the matrix has -weight in these columns
+    pub fn neighbors(&self, row: usize) -> impl Iterator<Item = (usize, f32)> + '_{
+        let count=self.neighbor_count[row] as usize;
+        return self.neighbors[row][..count].iter().copied().zip(self.weights[row][..count].iter().copied());
+    }
+    //The sum of weight_ij x_j over the neighbours of a row
+    pub fn neighbor_sum(&self, row: usize, x: &[f32]) -> f32{
+        let mut sum=0.0;
+        for k in 0..self.neighbor_count[row] as usize{
+            sum+=self.weights[row][k]*x[self.neighbors[row][k]];
+        }
+        return sum;
+    }
+    //The rows whose cell has an even sum of coordinates and the other rows. Rows of the same colour are not coupled,
+    //except across a periodic seam with an odd number of cells, so they can be updated in any order by Gauss-Seidel.
+    pub fn red(&self) -> &[usize]{
+        return &self.red;
+    }
+    pub fn black(&self) -> &[usize]{
+        return &self.black;
+    }
+    //result = A x
+    pub fn apply(&self, x: &[f32], result: &mut [f32]){
+        result.par_iter_mut().enumerate().for_each(|(row, value)| {
+            *value=self.diagonal[row]*x[row]-self.neighbor_sum(row, x);
+        });
+    }
+    //residual = b - A x
+    pub fn residual(&self, x: &[f32], b: &[f32], residual: &mut [f32]){
+        residual.par_iter_mut().enumerate().for_each(|(row, value)| {
+            *value=b[row]-(self.diagonal[row]*x[row]-self.neighbor_sum(row, x));
+        });
+    }
+    //The largest absolute value of b - A x
+    pub fn max_residual(&self, x: &[f32], b: &[f32]) -> f32{
+        return (0..self.size()).into_par_iter().map(|row| (b[row]-(self.diagonal[row]*x[row]-self.neighbor_sum(row, x))).abs()).reduce(|| 0.0, f32::max);
+    }
+    //The norms of b - A x
+    pub fn residual_norms(&self, x: &[f32], b: &[f32]) -> ResidualNorms{
+        return norms(self.size(), |row| b[row]-(self.diagonal[row]*x[row]-self.neighbor_sum(row, x)));
+    }
+    //The value of x_i that satisfies its own equation with the current values of its neighbours.
+    //A row without couplings and diagonal belongs to a cell that is cut off from the rest of the fluid, its value stays what it is.
+    pub fn solve_row(&self, row: usize, x: &[f32], b: &[f32]) -> f32{
+        if self.diagonal[row]==0.0{
+            return x[row];
+        }
+        return (b[row]+self.neighbor_sum(row, x))/self.diagonal[row];
+    }
+    //One red-black Gauss-Seidel sweep with over-relaxation factor omega, 1 for plain Gauss-Seidel.
+    //The new values of a colour are all calculated from the old values before any of them is stored, so the result does not depend on the number of threads.
+    pub fn gauss_seidel_sweep(&self, x: &mut [f32], b: &[f32], omega: f32){
+        //One buffer for both colours, collect_into_vec keeps its allocation
+        let mut values: Vec<f32>=Vec::with_capacity(self.red.len().max(self.black.len()));
+        for colour in [&self.red, &self.black]{
+            colour.par_iter().map(|&row| x[row]+omega*(self.solve_row(row, x, b)-x[row])).collect_into_vec(&mut values);
+            for (&row, &value) in colour.iter().zip(values.iter()){
+                x[row]=value;
+            }
+        }
+    }
+}
+
+//The sum of term(i) for i in 0..n. The terms are added in blocks of a fixed size and then the sums of the blocks in order,
+//so the rounding and with it the result is the same for every number of threads.
+pub(crate) fn deterministic_sum<F: Fn(usize) -> f64 + Sync>(n: usize, term: F) -> f64{
+    let blocks: Vec<f64>=(0..n.div_ceil(BLOCK)).into_par_iter().map(|block| (block*BLOCK..n.min((block+1)*BLOCK)).map(&term).sum()).collect();
+    return blocks.iter().sum();
+}
+
+//The norms of the n values value(i), every value is calculated once. The squares are added like in deterministic_sum, so the result is the same for every number of threads.
+pub(crate) fn norms<F: Fn(usize) -> f32 + Sync>(n: usize, value: F) -> ResidualNorms{
+    let blocks: Vec<(f32, f64)>=(0..n.div_ceil(BLOCK)).into_par_iter().map(|block| {
+        let (mut max, mut sum_of_squares)=(0.0f32, 0.0f64);
+        for i in block*BLOCK..n.min((block+1)*BLOCK){
+            let value=value(i);
+            max=max.max(value.abs());
+            sum_of_squares+=(value as f64).powi(2);
+        }
+        (max, sum_of_squares)
+    }).collect();
+    let max=blocks.iter().fold(0.0f32, |max, block| max.max(block.0));
+    let sum_of_squares: f64=blocks.iter().map(|block| block.1).sum();
+    return ResidualNorms::from_sum_of_squares(max, sum_of_squares, n);
+}
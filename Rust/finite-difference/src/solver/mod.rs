@@ -0,0 +1,272 @@
+//Solving the pressure Poisson equation of the projection step.
+//The pressure correction phi makes the velocity field free of divergence: u = u* - dt/density * grad(phi). Requiring div(u) = 0 in every fluid cell gives
+//    diagonal * phi_cell - sum of phi_neighbour over the open faces = -density * dx / dt * (sum of the velocity differences of u* over the cell)
+//which is a symmetric positive (semi)definite system. A face is open when the velocity through it is calculated by the solver: faces between two fluid cells
+//and faces on patches with a fixed pressure. The velocities through walls, obstacles and the other patches are fixed, those faces do not couple.
+//The solvers work on vectors with one value for every fluid cell (an unknown), in the order of the cells in the pressure grid.
+mod conjugate_gradient;
+mod matrix;
+mod multigrid;
+mod stationary;
+
+use std::{fmt, sync::Arc};
+
+use crate::{Face, Field3D, PressureGrid, SimulationConfig, SolidMask, GHOST_LAYERS, boundary, get_dimension};
+
+pub use conjugate_gradient::ConjugateGradientSolver;
+pub use matrix::StencilMatrix;
+pub use multigrid::MultigridSolver;
+pub use stationary::{JacobiSolver, SorSolver};
+
+//A method to solve the pressure equation. Other crates can implement this to try their own solver, see PressureSolver::Custom.
+pub trait LinearSolver: Send{
+    //Called once before the first solve with the matrix that will be solved, for the work that only depends on the matrix (like a factorisation)
+    fn prepare(&mut self, _matrix: &StencilMatrix){}
+    //Improve the guess x of the solution of A x = b until the largest absolute value of b - A x is at most tolerance, but do not use more than max_iterations iterations
+    fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics;
+    //Like solve, and also add the norms of the residuals before the first and after every iteration to history, so it gets iterations+1 entries.
+    //Solvers that do not keep track of their convergence can leave this out, history then stays empty.
+    fn solve_with_history(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize, _history: &mut Vec<ResidualNorms>) -> SolveStatistics{
+        return self.solve(matrix, x, b, tolerance, max_iterations);
+    }
+}
+
+//The method used to find the pressure correction in every time step
+#[derive(Clone, Debug, PartialEq)]
+pub enum PressureSolver{
+    //The original scheme: a local correction from the divergence of every cell, repeated until the divergence is small enough. Kept for comparison.
+    Legacy,
+    Jacobi,
+    RedBlackGaussSeidel,
+    Sor{omega: f32},//Red-black successive over-relaxation, omega has to be strictly between 0 and 2
+    ConjugateGradient{preconditioner: Preconditioner},
+    //Multigrid with 2x2x2 aggregates as coarse cells and a scaled piecewise constant correction, the work per cycle and the number of cycles hardly grow with the number of cells. smoothing_steps Gauss-Seidel sweeps are done before and after every coarse grid correction.
+    Multigrid{cycle: MultigridCycle, smoothing_steps: usize},
+    Custom(CustomSolver),
+}
+
+impl PressureSolver{
+    //A new solver of this kind, None for the legacy scheme which does not solve the equation as a whole
+    pub fn create_solver(&self) -> Option<Box<dyn LinearSolver>>{
+        return match self{
+            PressureSolver::Legacy => None,
+            PressureSolver::Jacobi => Some(Box::new(JacobiSolver::new())),
+            PressureSolver::RedBlackGaussSeidel => Some(Box::new(SorSolver::new(1.0))),
+            PressureSolver::Sor{omega} => Some(Box::new(SorSolver::new(*omega))),
+            PressureSolver::ConjugateGradient{preconditioner} => Some(Box::new(ConjugateGradientSolver::new(*preconditioner))),
+            PressureSolver::Multigrid{cycle, smoothing_steps} => Some(Box::new(MultigridSolver::new(*cycle, *smoothing_steps))),
+            PressureSolver::Custom(custom) => Some((custom.0)()),
+        };
+    }
+}
+
+//Creates a user supplied solver, every simulation gets its own. Two of them are only equal when they are the same function.
+#[derive(Clone)]
+pub struct CustomSolver(pub Arc<dyn Fn() -> Box<dyn LinearSolver> + Send + Sync>);
+
+impl fmt::Debug for CustomSolver{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        return write!(f, "CustomSolver");
+    }
+}
+
+impl PartialEq for CustomSolver{
+    fn eq(&self, other: &Self) -> bool{
+        return Arc::ptr_eq(&self.0, &other.0);
+    }
+}
+
+//The order in which the grids of the multigrid hierarchy are visited
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MultigridCycle{
+    V,//Down to the coarsest grid and back up once
+    W,//Every coarser grid is visited twice, more work per cycle but fewer cycles
+}
+
+//An approximation of the inverse of the matrix that makes the conjugate gradient method converge in fewer iterations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Preconditioner{
+    Jacobi,//Divide by the diagonal, cheap but only helps a little on a uniform grid
+    IncompleteCholesky,//The Cholesky factorisation restricted to the nonzeros of the matrix, each iteration is more expensive but far fewer are needed
+}
+
+//Norms of the residuals of a set of equations
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ResidualNorms{
+    pub max: f32,//The largest absolute value
+    pub l2: f32,//The square root of the sum of the squares, it grows with the number of equations
+    pub root_mean_square: f32,//The L2 norm divided by the square root of the number of equations, comparable between grids of different sizes
+}
+
+impl ResidualNorms{
+    //The norms of count values with the given largest absolute value and sum of squares
+    pub(crate) fn from_sum_of_squares(max: f32, sum_of_squares: f64, count: usize) -> Self{
+        return Self{max, l2: sum_of_squares.sqrt() as f32, root_mean_square: (sum_of_squares/count.max(1) as f64).sqrt() as f32};
+    }
+    pub fn scaled(&self, factor: f32) -> Self{
+        return Self{max: self.max*factor, l2: self.l2*factor, root_mean_square: self.root_mean_square*factor};
+    }
+}
+
+//How a solver did. A LinearSolver gives the residuals of its equations, the simulation reports them as the remaining divergence in 1/s.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolveStatistics{
+    pub iterations: usize,
+    pub residual: f32,//The largest absolute residual
+    pub residual_l2: f32,//The L2 norm of the residuals
+    pub residual_norm: f32,//The root mean square of the residuals
+    pub converged: bool,
+}
+
+impl SolveStatistics{
+    //The statistics of the solution x of A x = b after the given number of iterations
+    pub fn measure(matrix: &StencilMatrix, x: &[f32], b: &[f32], iterations: usize, tolerance: f32) -> Self{
+        let norms=matrix.residual_norms(x, b);
+        return Self{iterations, residual: norms.max, residual_l2: norms.l2, residual_norm: norms.root_mean_square, converged: norms.max<=tolerance};
+    }
+    pub fn norms(&self) -> ResidualNorms{
+        return ResidualNorms{max: self.residual, l2: self.residual_l2, root_mean_square: self.residual_norm};
+    }
+}
+
+//The pressure Poisson equation for the cells of one simulation. It only depends on the grid, the obstacles and the kinds of boundaries, so it is built once.
+pub(crate) struct PoissonProblem{
+    layout: PressureGrid,//An empty field with the layout of the pressure grid, the solution is stored in fields like this
+    unknown_cells: Vec<[usize; 3]>,//The storage coordinates of every fluid cell
+    matrix: StencilMatrix,
+}
+
+impl PoissonProblem{
+    pub(crate) fn new(config: &SimulationConfig, solid_mask: &SolidMask) -> Self{
+        let layout: PressureGrid=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, 0.0);
+        let ghost=layout.ghost();
+        //Number the fluid cells first, so the neighbours can refer to them
+        let mut unknown_index: Field3D<Option<usize>>=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, None);
+        let mut unknown_cells=Vec::new();
+        for x in layout.domain_range(0){
+            for y in layout.domain_range(1){
+                for z in layout.domain_range(2){
+                    if !solid_mask[[x,y,z]]{
+                        unknown_index[[x,y,z]]=Some(unknown_cells.len());
+                        unknown_cells.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        let mut matrix=StencilMatrix::new(config.pressure_grid_size, unknown_cells.iter().map(|cell| cell.map(|coordinate| coordinate-ghost)).collect());
+        for (index, &cell) in unknown_cells.iter().enumerate(){
+            let [x, y, z]=cell;
+            for face in Face::ALL{
+                let dim=get_dimension(face.dimension);
+                let cells=config.pressure_grid_size[face.dimension];
+                let at_wall=if face.upper {cell[face.dimension]==ghost+cells-1} else {cell[face.dimension]==ghost};
+                let mut neighbor=if face.upper {[x+dim[0], y+dim[1], z+dim[2]]} else {[x-dim[0], y-dim[1], z-dim[2]]};
+                if at_wall{
+                    if !config.periodic[face.dimension]{
+                        //The correction on a patch with a fixed pressure is zero, so the ghost value is minus the value inside
+                        if boundary::is_on_pressure_patch(face, cell, config){
+                            matrix.add_to_diagonal(index, 2.0);
+                        }
+                        continue;
+                    }
+                    //The neighbour is on the other side of the domain
+                    neighbor[face.dimension]=if face.upper {ghost} else {ghost+cells-1};
+                }
+                if let Some(neighbor)=unknown_index[neighbor]{
+                    matrix.add_to_diagonal(index, 1.0);
+                    matrix.add_coupling(index, neighbor, 1.0);
+                }
+            }
+        }
+        return Self{layout, unknown_cells, matrix};
+    }
+    pub(crate) fn matrix(&self) -> &StencilMatrix{
+        return &self.matrix;
+    }
+}
+
+//Find the pressure correction phi for the divergence of the provisional velocities. divergence has the sum of the velocity differences over every cell.
+//Returns the correction in a field with the layout of the pressure grid, and the statistics with the residuals as divergence in 1/s.
+//The residuals of every iteration are added to history, also as divergence.
+pub(crate) fn solve_pressure_correction(solver: &mut dyn LinearSolver, problem: &PoissonProblem, divergence: &PressureGrid, history: &mut Vec<ResidualNorms>, dt: f32, config: &SimulationConfig) -> (PressureGrid, SolveStatistics){
+    let scale=config.density*config.grid_element_scale/dt;
+    let b: Vec<f32>=problem.unknown_cells.iter().map(|&cell| -scale*divergence[cell]).collect();
+    //A residual r leaves a divergence of r * dt / (density * dx^2) in the cell
+    let residual_to_divergence=1.0/(scale*config.grid_element_scale);
+    let tolerance=config.allowed_error/residual_to_divergence;
+    let max_iterations=config.max_iterations_per_time_frame.max(1) as usize;
+    let mut x=vec![0.0; problem.matrix.size()];
+    let start=history.len();
+    let mut statistics=solver.solve_with_history(&problem.matrix, &mut x, &b, tolerance, max_iterations, history);
+    statistics.residual*=residual_to_divergence;
+    statistics.residual_l2*=residual_to_divergence;
+    statistics.residual_norm*=residual_to_divergence;
+    for norms in history[start..].iter_mut(){
+        *norms=norms.scaled(residual_to_divergence);
+    }
+    let mut phi=problem.layout.clone();
+    for (&cell, &value) in problem.unknown_cells.iter().zip(x.iter()){
+        phi[cell]=value;
+    }
+    return (phi, statistics);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::obstacle::build_solid_mask;
+
+    //The Poisson matrix of a closed or fully periodic cube with the given number of cells in every dimension, a solution with zero mean and the right hand side that belongs to it.
+    //Without a pressure patch the solution is only known up to a constant, the zero mean picks one.
+    pub(crate) fn manufactured_problem(cells: usize, periodic: bool) -> (StencilMatrix, Vec<f32>, Vec<f32>){
+        let config=SimulationConfig{pressure_grid_size: [cells; 3], periodic: [periodic; 3], boundary_patches: Vec::new(), ..SimulationConfig::default()};
+        let matrix=PoissonProblem::new(&config, &build_solid_mask(&config)).matrix().clone();
+        //A smooth wave with a rough part on top, so the solution is not a single eigenvector of the matrix
+        let wave=|coordinate: usize| 2.0*std::f32::consts::PI*(coordinate as f32+0.5)/cells as f32;
+        let mut solution: Vec<f32>=(0..matrix.size()).map(|row| {
+            let [x, y, z]=matrix.coordinates(row).map(wave);
+            return x.sin()*y.cos()+z.cos()+((row*7919)%101) as f32/101.0;
+        }).collect();
+        let mean=solution.iter().sum::<f32>()/solution.len() as f32;
+        for value in solution.iter_mut(){
+            *value-=mean;
+        }
+        let mut b=vec![0.0; matrix.size()];
+        matrix.apply(&solution, &mut b);
+        return (matrix, solution, b);
+    }
+
+    //Solve the manufactured problem from zero and check the residual and the error, returns the number of iterations
+    pub(crate) fn assert_solves(solver: &mut dyn LinearSolver, cells: usize, periodic: bool) -> usize{
+        let (matrix, solution, b)=manufactured_problem(cells, periodic);
+        let mut x=vec![0.0; matrix.size()];
+        solver.prepare(&matrix);
+        let statistics=solver.solve(&matrix, &mut x, &b, 1e-4, 10000);
+        assert!(statistics.converged, "not converged after {} iterations, residual {}", statistics.iterations, statistics.residual);
+        assert!(matrix.max_residual(&x, &b)<=1e-4, "the reported residual {} is not the residual {}", statistics.residual, matrix.max_residual(&x, &b));
+        let mean=x.iter().sum::<f32>()/x.len() as f32;
+        let error=x.iter().zip(solution.iter()).map(|(value, expected)| (value-mean-expected).abs()).fold(0.0, f32::max);
+        assert!(error<1e-2, "error {} after {} iterations", error, statistics.iterations);
+        return statistics.iterations;
+    }
+
+    #[test]
+    fn poisson_matrix_couples_across_the_periodic_seam(){
+        let config=SimulationConfig{pressure_grid_size: [5, 4, 3], periodic: [true, false, false], boundary_patches: Vec::new(), ..SimulationConfig::default()};
+        let problem=PoissonProblem::new(&config, &build_solid_mask(&config));
+        let matrix=problem.matrix();
+        let row_of=|coordinates: [usize; 3]| (0..matrix.size()).find(|&row| matrix.coordinates(row)==coordinates).unwrap();
+        for y in 0..4{
+            for z in 0..3{
+                let (first, last)=(row_of([0, y, z]), row_of([4, y, z]));
+                assert!(matrix.neighbors(first).any(|(column, weight)| column==last && weight==1.0), "no coupling from {:?} to {:?}", [0, y, z], [4, y, z]);
+                assert!(matrix.neighbors(last).any(|(column, weight)| column==first && weight==1.0), "no coupling from {:?} to {:?}", [4, y, z], [0, y, z]);
+            }
+        }
+        //A cell in a corner of the periodic dimension has both neighbours in x, so only the walls in y and z remove couplings
+        assert_eq!(matrix.diagonal(row_of([0, 0, 0])), 4.0);
+        assert_eq!(matrix.diagonal(row_of([0, 1, 1])), 6.0);
+        //In y there is no seam
+        assert!(!matrix.neighbors(row_of([2, 0, 1])).any(|(column, _)| column==row_of([2, 3, 1])));
+    }
+}
@@ -0,0 +1,155 @@
+//Multigrid by aggregation. Smoothing quickly removes the parts of the error that change from cell to cell, but is slow for smooth errors.
+//A smooth error looks rough on a coarser grid, so it is removed there and the correction is carried back to the fine grid.
+//Every coarse cell is an aggregate of the 2x2x2 block of cells of the finer grid. The coarse equations are the sum of the equations of the fluid cells in the block (restriction),
+//for a correction that is the same in the whole block (piecewise constant prolongation). This is not classical geometric multigrid with trilinear prolongation:
+//a piecewise constant correction only has the right size for some errors, so it is scaled to reduce the error as much as possible before it is added.
+//Walls, obstacles, patches with a fixed pressure and periodic seams are carried to the coarse grids this way without handling them separately.
+use super::{LinearSolver, ResidualNorms, MultigridCycle, SolveStatistics, StencilMatrix, matrix::deterministic_sum};
+
+//Stop coarsening when a grid has this many unknowns or less, it is then solved with many smoothing sweeps
+const COARSEST_UNKNOWNS: usize = 64;
+const COARSEST_SWEEPS: usize = 50;
+
+#[derive(Clone, Debug)]
+pub struct MultigridSolver{
+    cycle: MultigridCycle,
+    smoothing_steps: usize,
+    coarse_levels: Vec<StencilMatrix>,//The coarser grids from fine to coarse, the finest grid is the matrix that is solved
+    parents: Vec<Vec<usize>>,//For every grid but the coarsest, the unknown of the next coarser grid that contains each unknown
+}
+
+impl MultigridSolver{
+    //smoothing_steps Gauss-Seidel sweeps are done before and after every coarse grid correction
+    pub fn new(cycle: MultigridCycle, smoothing_steps: usize) -> Self{
+        return Self{cycle, smoothing_steps, coarse_levels: Vec::new(), parents: Vec::new()};
+    }
+    //Improve the solution x of grid `level` (0 is the finest) with one cycle
+    fn run_cycle(&self, levels: &[&StencilMatrix], level: usize, x: &mut [f32], b: &[f32]){
+        let matrix=levels[level];
+        if level+1==levels.len(){
+            for _ in 0..COARSEST_SWEEPS{
+                smooth(matrix, x, b);
+            }
+            return;
+        }
+        for _ in 0..self.smoothing_steps{
+            smooth(matrix, x, b);
+        }
+        let mut residual=vec![0.0; matrix.size()];
+        matrix.residual(x, b, &mut residual);
+        let parents=&self.parents[level];
+        let mut coarse_b=vec![0.0; levels[level+1].size()];
+        for (row, residual) in residual.iter().enumerate(){
+            coarse_b[parents[row]]+=residual;
+        }
+        let mut coarse_x=vec![0.0; coarse_b.len()];
+        //A W-cycle visits every coarser level twice, which helps when the smoother alone does not do enough
+        let visits=match self.cycle{
+            MultigridCycle::V => 1,
+            MultigridCycle::W => 2,
+        };
+        for _ in 0..visits{
+            self.run_cycle(levels, level+1, &mut coarse_x, &coarse_b);
+        }
+        //A piecewise constant correction is too large or too small depending on how smooth the error is, so it is scaled to reduce the error as much as possible
+        let correction: Vec<f32>=parents.iter().map(|&parent| coarse_x[parent]).collect();
+        let mut product=vec![0.0; matrix.size()];
+        matrix.apply(&correction, &mut product);
+        let curvature=deterministic_sum(matrix.size(), |row| correction[row] as f64*product[row] as f64);
+        if curvature>0.0{
+            let scale=(deterministic_sum(matrix.size(), |row| correction[row] as f64*residual[row] as f64)/curvature) as f32;
+            for (x, correction) in x.iter_mut().zip(correction.iter()){
+                *x+=scale*correction;
+            }
+        }
+        for _ in 0..self.smoothing_steps{
+            smooth(matrix, x, b);
+        }
+    }
+}
+
+impl LinearSolver for MultigridSolver{
+    fn prepare(&mut self, matrix: &StencilMatrix){
+        self.coarse_levels.clear();
+        self.parents.clear();
+        loop{
+            let finest=self.coarse_levels.last().unwrap_or(matrix);
+            if finest.size()<=COARSEST_UNKNOWNS || finest.cells().iter().all(|&cells| cells==1){
+                break;
+            }
+            let (coarse, parents)=coarsen(finest);
+            self.coarse_levels.push(coarse);
+            self.parents.push(parents);
+        }
+    }
+    fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics{
+        return self.solve_with_history(matrix, x, b, tolerance, max_iterations, &mut Vec::new());
+    }
+    fn solve_with_history(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize, history: &mut Vec<ResidualNorms>) -> SolveStatistics{
+        let levels: Vec<&StencilMatrix>=std::iter::once(matrix).chain(self.coarse_levels.iter()).collect();
+        for iteration in 0..max_iterations{
+            let norms=matrix.residual_norms(x, b);
+            history.push(norms);
+            if norms.max<=tolerance{
+                return SolveStatistics::measure(matrix, x, b, iteration, tolerance);
+            }
+            self.run_cycle(&levels, 0, x, b);
+        }
+        let statistics=SolveStatistics::measure(matrix, x, b, max_iterations, tolerance);
+        history.push(statistics.norms());
+        return statistics;
+    }
+}
+
+//Build the next coarser grid, returns its matrix and the coarse unknown of every fine unknown
+fn coarsen(fine: &StencilMatrix) -> (StencilMatrix, Vec<usize>){
+    let cells=fine.cells();
+    let coarse_cells=cells.map(|cells| cells.div_ceil(2));
+    let mut coarse_index: Vec<Option<usize>>=vec![None; coarse_cells[0]*coarse_cells[1]*coarse_cells[2]];
+    let mut coarse_coordinates=Vec::new();
+    let parents: Vec<usize>=(0..fine.size()).map(|row| {
+        let coarse=fine.coordinates(row).map(|coordinate| coordinate/2);
+        let index=&mut coarse_index[(coarse[0]*coarse_cells[1]+coarse[1])*coarse_cells[2]+coarse[2]];
+        if index.is_none(){
+            *index=Some(coarse_coordinates.len());
+            coarse_coordinates.push(coarse);
+        }
+        index.unwrap()
+    }).collect();
+    let mut coarse=StencilMatrix::new(coarse_cells, coarse_coordinates);
+    for row in 0..fine.size(){
+        let parent=parents[row];
+        coarse.add_to_diagonal(parent, fine.diagonal(row));
+        for (column, weight) in fine.neighbors(row){
+            let other=parents[column];
+            if other==parent{//A face inside the block, it does not couple the block to anything else
+                coarse.add_to_diagonal(parent, -weight);
+            }else{
+                coarse.add_coupling(parent, other, weight);
+            }
+        }
+    }
+    return (coarse, parents);
+}
+
+fn smooth(matrix: &StencilMatrix, x: &mut [f32], b: &[f32]){
+    matrix.gauss_seidel_sweep(x, b, 1.0);
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::solver::tests::assert_solves;
+
+    #[test]
+    fn cycles_do_not_grow_with_the_number_of_cells(){
+        for cycle in [MultigridCycle::V, MultigridCycle::W]{
+            for periodic in [false, true]{
+                //Doubling the cells in every dimension adds a level, the number of cycles stays the same up to one because the largest residual is taken over eight times more cells
+                let coarse=assert_solves(&mut MultigridSolver::new(cycle, 2), 16, periodic);
+                let fine=assert_solves(&mut MultigridSolver::new(cycle, 2), 32, periodic);
+                assert!(fine<=coarse+1, "{:?} cycle, periodic {}: {} cycles on 16^3 cells, {} on 32^3", cycle, periodic, coarse, fine);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//The classic stationary iterations. They are simple and need no extra memory besides the solution, but the number of iterations grows quickly with the size of the grid.
+use rayon::prelude::*;
+
+use super::{LinearSolver, ResidualNorms, SolveStatistics, StencilMatrix};
+
+//The fraction of the step to the value that satisfies its own equation that an unknown takes in one Jacobi iteration.
+//With the full step an error that alternates from cell to cell (like a checkerboard) only changes sign and never becomes smaller, which stops the convergence on closed and periodic grids.
+const JACOBI_WEIGHT: f32 = 6.0/7.0;
+
+//Every unknown is moved towards the value that satisfies its equation with the values of its neighbours in the previous iteration (weighted Jacobi)
+#[derive(Clone, Debug, Default)]
+pub struct JacobiSolver{
+    last: Vec<f32>,
+}
+
+impl JacobiSolver{
+    pub fn new() -> Self{
+        return Self::default();
+    }
+}
+
+impl LinearSolver for JacobiSolver{
+    fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics{
+        return self.solve_with_history(matrix, x, b, tolerance, max_iterations, &mut Vec::new());
+    }
+    fn solve_with_history(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize, history: &mut Vec<ResidualNorms>) -> SolveStatistics{
+        self.last.resize(matrix.size(), 0.0);
+        for iteration in 0..max_iterations{
+            let norms=matrix.residual_norms(x, b);
+            history.push(norms);
+            if norms.max<=tolerance{
+                return SolveStatistics::measure(matrix, x, b, iteration, tolerance);
+            }
+            self.last.copy_from_slice(x);
+            let last=&self.last;
+            x.par_iter_mut().enumerate().for_each(|(row, value)| {
+                *value=last[row]+JACOBI_WEIGHT*(matrix.solve_row(row, last, b)-last[row]);
+            });
+        }
+        let statistics=SolveStatistics::measure(matrix, x, b, max_iterations, tolerance);
+        history.push(statistics.norms());
+        return statistics;
+    }
+}
+
+//Red-black Gauss-Seidel with over-relaxation: first all red unknowns are updated, then all black unknowns with the new red values.
+//An omega of 1 gives plain Gauss-Seidel. Because no two unknowns of the same colour are neighbours, the order within a colour does not matter.
+#[derive(Clone, Debug)]
+pub struct SorSolver{
+    omega: f32,
+}
+
+impl SorSolver{
+    //omega has to be strictly between 0 and 2, the fastest convergence is usually somewhere between 1.5 and 1.9
+    pub fn new(omega: f32) -> Self{
+        return Self{omega};
+    }
+}
+
+impl LinearSolver for SorSolver{
+    fn solve(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize) -> SolveStatistics{
+        return self.solve_with_history(matrix, x, b, tolerance, max_iterations, &mut Vec::new());
+    }
+    fn solve_with_history(&mut self, matrix: &StencilMatrix, x: &mut [f32], b: &[f32], tolerance: f32, max_iterations: usize, history: &mut Vec<ResidualNorms>) -> SolveStatistics{
+        for iteration in 0..max_iterations{
+            let norms=matrix.residual_norms(x, b);
+            history.push(norms);
+            if norms.max<=tolerance{
+                return SolveStatistics::measure(matrix, x, b, iteration, tolerance);
+            }
+            matrix.gauss_seidel_sweep(x, b, self.omega);
+        }
+        let statistics=SolveStatistics::measure(matrix, x, b, max_iterations, tolerance);
+        history.push(statistics.norms());
+        return statistics;
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{PressureSolver, SimulationConfig, solver::tests::assert_solves};
+
+    #[test]
+    fn jacobi_solves_the_manufactured_problem(){
+        for periodic in [false, true]{
+            assert_solves(&mut JacobiSolver::new(), 8, periodic);
+        }
+    }
+
+    #[test]
+    fn red_black_gauss_seidel_solves_the_manufactured_problem(){
+        for periodic in [false, true]{
+            assert_solves(&mut SorSolver::new(1.0), 8, periodic);
+        }
+    }
+
+    #[test]
+    fn sor_with_the_default_omega_solves_the_manufactured_problem_in_fewer_iterations(){
+        let PressureSolver::Sor{omega}=SimulationConfig::default().pressure_solver else {panic!("the default solver is not SOR")};
+        for periodic in [false, true]{
+            let gauss_seidel=assert_solves(&mut SorSolver::new(1.0), 8, periodic);
+            let sor=assert_solves(&mut SorSolver::new(omega), 8, periodic);
+            assert!(sor<gauss_seidel, "{} iterations with omega {} against {} with Gauss-Seidel", sor, omega, gauss_seidel);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//The time integration of the momentum predictor. A forward Euler step u + dt*F(u) is one call of predict_velocity, where the tendency F holds the convection,
+//the pressure gradient of the last time step, the explicit diffusion and the external force. The higher order methods combine several of these steps.
+//Adams-Bashforth only extrapolates the tendency without the pressure gradient: the gradient of the last pressure is taken once, like in forward Euler,
+//and the projection corrects it. Extrapolating it as well would mix in the pressure of two time steps ago, which the projection would then correct a second time.
+//The boundary conditions are set on every intermediate stage, so the next stage sees the walls, the obstacles and the periodic seams.
+use rayon::prelude::*;
+
+use crate::{ColorGrid, Field3D, PredictionFields, PressureGrid, SimulationConfig, SolidMask, Step, VelocityGrid, GHOST_LAYERS, boundary, obstacle, predict_velocity, update_velocity_field};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeIntegration{
+    ForwardEuler,//First order, one stage
+    Heun,//The second order Runge-Kutta method of Heun, two stages
+    SspRk3,//The strong stability preserving third order Runge-Kutta method, three stages
+    AdamsBashforth,//Second order Adams-Bashforth: one stage, together with the tendency of the last time step
+}
+
+//The tendency F without the pressure gradient of a time step for Adams-Bashforth, and the size of that time step
+pub(crate) type PreviousTendency = Option<([Field3D; 3], f32)>;
+
+//The provisional velocities of the three grids, and for Adams-Bashforth the tendency of this time step. The state only keeps the tendency once the whole time step has succeeded.
+//The velocities of fields are the ones the prediction starts from and last_time_step the velocities at the start of the time step, they differ when the velocities have already been carried by a semi-Lagrangian step.
+pub(crate) fn predict_velocities(fields: PredictionFields, last_time_step: [&VelocityGrid; 3], previous_tendency: Option<&([Field3D; 3], f32)>, step: Step, color_grid: &mut ColorGrid, config: &SimulationConfig) -> ([VelocityGrid; 3], PreviousTendency){
+    let PredictionFields{velocities: start, pressure_grid, solid_mask}=fields;
+    let dt=step.dt;
+    let [start_x, start_y, start_z]=start;
+    match config.time_integration{
+        TimeIntegration::ForwardEuler => {
+            return (forward_euler(start, pressure_grid, solid_mask, dt, config), None);
+        }
+        TimeIntegration::Heun => {
+            let mut first=forward_euler(start, pressure_grid, solid_mask, dt, config);
+            set_stage_boundary_conditions(&mut first, last_time_step, Step{time: step.time+dt, ..step}, solid_mask, color_grid, config);
+            let [mut x, mut y, mut z]=forward_euler([&first[0], &first[1], &first[2]], pressure_grid, solid_mask, dt, config);
+            combine(0.5, start_x, 0.5, &mut x);
+            combine(0.5, start_y, 0.5, &mut y);
+            combine(0.5, start_z, 0.5, &mut z);
+            return ([x, y, z], None);
+        }
+        TimeIntegration::SspRk3 => {
+            let mut first=forward_euler(start, pressure_grid, solid_mask, dt, config);
+            set_stage_boundary_conditions(&mut first, last_time_step, Step{time: step.time+dt, ..step}, solid_mask, color_grid, config);
+            let mut second=forward_euler([&first[0], &first[1], &first[2]], pressure_grid, solid_mask, dt, config);
+            for (start, second) in start.iter().zip(second.iter_mut()){
+                combine(0.75, start, 0.25, second);
+            }
+            set_stage_boundary_conditions(&mut second, last_time_step, Step{time: step.time+0.5*dt, ..step}, solid_mask, color_grid, config);
+            let [mut x, mut y, mut z]=forward_euler([&second[0], &second[1], &second[2]], pressure_grid, solid_mask, dt, config);
+            combine(1.0/3.0, start_x, 2.0/3.0, &mut x);
+            combine(1.0/3.0, start_y, 2.0/3.0, &mut y);
+            combine(1.0/3.0, start_z, 2.0/3.0, &mut z);
+            return ([x, y, z], None);
+        }
+        TimeIntegration::AdamsBashforth => {
+            let no_pressure: PressureGrid=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, 0.0);
+            let mut predicted=forward_euler(start, &no_pressure, solid_mask, dt, config);
+            let tendency: [Field3D; 3]=[0, 1, 2].map(|dimension| {
+                let mut tendency=predicted[dimension].grid.clone();
+                tendency.as_mut_slice().par_iter_mut().zip(start[dimension].grid.as_slice().par_iter()).for_each(|(tendency, start)| *tendency=(*tendency-start)/dt);
+                tendency
+            });
+            //The first step has no earlier tendency and stays forward Euler
+            if let Some((previous, previous_dt))=previous_tendency{
+                //For a time step of another size than the last one the weights change, with the same size they are 3/2 and -1/2
+                let ratio=dt/previous_dt;
+                for dimension in 0..3{
+                    let new=predicted[dimension].grid.as_mut_slice();
+                    new.par_iter_mut().zip(start[dimension].grid.as_slice().par_iter()).zip(tendency[dimension].as_slice().par_iter().zip(previous[dimension].as_slice().par_iter())).for_each(|((new, start), (tendency, previous))| {
+                        *new=start+dt*((1.0+0.5*ratio)*tendency-0.5*ratio*previous);
+                    });
+                }
+            }
+            //The pressure gradient of the last time step, only once
+            for velocity_grid in predicted.iter_mut(){
+                update_velocity_field(velocity_grid, pressure_grid, solid_mask, dt, config);
+            }
+            return (predicted, Some((tendency, dt)));
+        }
+    }
+}
+
+//u + dt*F(u) for the three grids
+fn forward_euler(velocities: [&VelocityGrid; 3], pressure_grid: &PressureGrid, solid_mask: &SolidMask, dt: f32, config: &SimulationConfig) -> [VelocityGrid; 3]{
+    let mut provisional_velocity_x = VelocityGrid::new(config.pressure_grid_size, 0);
+    let mut provisional_velocity_y = VelocityGrid::new(config.pressure_grid_size, 1);
+    let mut provisional_velocity_z = VelocityGrid::new(config.pressure_grid_size, 2);
+    let fields=PredictionFields{velocities, pressure_grid, solid_mask};
+    predict_velocity(&mut provisional_velocity_x, fields, dt, config);
+    predict_velocity(&mut provisional_velocity_y, fields, dt, config);
+    predict_velocity(&mut provisional_velocity_z, fields, dt, config);
+    return [provisional_velocity_x, provisional_velocity_y, provisional_velocity_z];
+}
+
+fn set_stage_boundary_conditions(stage: &mut [VelocityGrid; 3], last_time_step: [&VelocityGrid; 3], step: Step, solid_mask: &SolidMask, color_grid: &mut ColorGrid, config: &SimulationConfig){
+    let [velocity_x, velocity_y, velocity_z]=stage;
+    boundary::set_wall_boundary_conditions(velocity_x, velocity_y, velocity_z, last_time_step, step, color_grid, config);
+    obstacle::set_obstacle_boundary_conditions(velocity_x, velocity_y, velocity_z, solid_mask, config);
+}
+
+//result = a*velocity + b*result inside the domain. The ghost layers keep the values of the forward Euler step, the boundary conditions set them like after forward Euler.
+fn combine(a: f32, velocity: &VelocityGrid, b: f32, result: &mut VelocityGrid){
+    let grid=&velocity.grid;
+    let (range_y, range_z)=(grid.domain_range(1), grid.domain_range(2));
+    let row_length=grid.shape()[2];
+    result.grid.par_layers_mut(grid.domain_range(0)).for_each(|(x, layer)| {
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                let result=&mut layer[y*row_length+z];
+                *result=a*grid[[x,y,z]]+b*(*result);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::{obstacle::build_solid_mask, solved_range};
+
+    //Predict from still fluid with a constant external force, so the tendency of this step is the force in every point that is predicted.
+    //The tendency of the last step is `previous` everywhere, for a step of previous_dt seconds.
+    fn predict_with_force(time_integration: TimeIntegration, previous: f32, previous_dt: f32) -> ([VelocityGrid; 3], PreviousTendency, SimulationConfig){
+        let config=SimulationConfig{pressure_grid_size: [6, 5, 4], external_force: [1.0, -2.0, 4.0], boundary_patches: Vec::new(), time_integration, ..SimulationConfig::default()};
+        let still=[0, 1, 2].map(|dimension| VelocityGrid::new(config.pressure_grid_size, dimension));
+        let pressure_grid: PressureGrid=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, 0.0);
+        let solid_mask=build_solid_mask(&config);
+        let mut color_grid: ColorGrid=Field3D::cell_centered(config.pressure_grid_size, GHOST_LAYERS, [0.0; 3]);
+        let previous_tendency=([0, 1, 2].map(|dimension| Field3D::staggered(config.pressure_grid_size, GHOST_LAYERS, dimension, previous)), previous_dt);
+        let fields=PredictionFields{velocities: [&still[0], &still[1], &still[2]], pressure_grid: &pressure_grid, solid_mask: &solid_mask};
+        let step=Step{index: 1, time: 0.05, dt: 0.05};
+        let (predicted, tendency)=predict_velocities(fields, [&still[0], &still[1], &still[2]], Some(&previous_tendency), step, &mut color_grid, &config);
+        return (predicted, tendency, config);
+    }
+
+    //Every predicted velocity of the grids is expected[dimension]
+    fn assert_predicted(predicted: &[VelocityGrid; 3], expected: [f32; 3], config: &SimulationConfig){
+        for (velocity_grid, expected) in predicted.iter().zip(expected){
+            for x in solved_range(velocity_grid, 0, config){
+                for y in solved_range(velocity_grid, 1, config){
+                    for z in solved_range(velocity_grid, 2, config){
+                        let value=velocity_grid.grid[[x,y,z]];
+                        assert!((value-expected).abs()<=1e-6*expected.abs().max(1.0), "{} instead of {} at {:?} of the grid in dimension {}", value, expected, [x, y, z], velocity_grid.dimension);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn adams_bashforth_weights_the_last_tendency(){
+        let dt=0.05;
+        let (euler, _, config)=predict_with_force(TimeIntegration::ForwardEuler, 0.0, dt);
+        let force=[0, 1, 2].map(|dimension| euler[dimension].grid[[GHOST_LAYERS+1,GHOST_LAYERS+1,GHOST_LAYERS+1]]/dt);
+        assert!(force.iter().all(|&force| force!=0.0));
+        assert_predicted(&euler, force.map(|force| dt*force), &config);
+        //With the same step size the weights are 3/2 and -1/2
+        let (predicted, tendency, config)=predict_with_force(TimeIntegration::AdamsBashforth, 10.0, dt);
+        assert_predicted(&predicted, force.map(|force| dt*(1.5*force-0.5*10.0)), &config);
+        //The tendency that is kept for the next step is the one of this step, without the earlier one
+        let (tendency, tendency_dt)=tendency.unwrap();
+        assert_eq!(tendency_dt, dt);
+        for (dimension, field) in tendency.iter().enumerate(){
+            let value=field[[GHOST_LAYERS+1,GHOST_LAYERS+1,GHOST_LAYERS+1]];
+            assert!((value-force[dimension]).abs()<=1e-5*force[dimension].abs(), "tendency {} instead of {}", value, force[dimension]);
+        }
+        //When the last step was half as long the last tendency is further back in time: the weights are 1+ratio/2 and -ratio/2 with ratio 2
+        let (predicted, _, config)=predict_with_force(TimeIntegration::AdamsBashforth, 10.0, 0.5*dt);
+        assert_predicted(&predicted, force.map(|force| dt*(2.0*force-10.0)), &config);
+    }
+}
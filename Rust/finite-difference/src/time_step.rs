@@ -0,0 +1,136 @@
+//Choosing the size of every time step from the flow. The explicit scheme is only stable when the fluid moves less than a cell in a step (the CFL condition)
+//and when the momentum does not diffuse further than a cell in a step, so one fixed step size is either unstable for fast flows or wasteful for slow ones.
+use rayon::prelude::*;
+
+use crate::{ConvectionScheme, PatchKind, SimulationConfig, SimulationState, SolverError, VelocityGrid, WallCondition};
+
+//The limits of an adaptive time step. The step size is the largest one that satisfies both numbers, kept between the bounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveTimeStep{
+    pub cfl_number: f32,//The largest (|u|+|v|+|w|)*dt/dx, the fraction of a cell that the fluid may move in a step. It has to be below 1.
+    pub diffusion_number: f32,//The largest viscosity/density*dt/dx^2, the explicit diffusion is only stable up to 1/6
+    pub min_step_size: f32,//in s, used when the limits ask for an even smaller step
+    pub max_step_size: f32,//in s, used when the flow allows larger steps, for example when the fluid is at rest
+}
+
+//What decided the size of a time step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeStepLimit{
+    Fixed,//There is no adaptive time step, the step size of the config is used
+    Convection,//The CFL number
+    Diffusion,//The diffusion number
+    Minimum,//The limits ask for a step below the smallest allowed step size
+    Maximum,//The limits allow a step above the largest allowed step size
+}
+
+impl TimeStepLimit{
+    pub fn name(&self) -> &'static str{
+        return match self{
+            TimeStepLimit::Fixed => "fixed",
+            TimeStepLimit::Convection => "CFL number",
+            TimeStepLimit::Diffusion => "diffusion number",
+            TimeStepLimit::Minimum => "minimum step size",
+            TimeStepLimit::Maximum => "maximum step size",
+        };
+    }
+}
+
+//The time step that is being calculated. The functions of a time step take its size from here and not from the config, an adaptive time step has another size in every step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Step{
+    pub(crate) index: i32,//The number of the time step
+    pub(crate) time: f32,//The time in s at the start of the step, or at the stage of the step that is being calculated
+    pub(crate) dt: f32,//The size of the step in s
+}
+
+//The size of the next time step of the simulation and what limited it
+pub(crate) fn choose_time_step(state: &SimulationState, config: &SimulationConfig) -> (f32, TimeStepLimit){
+    let adaptive=match config.adaptive_time_step{
+        Some(adaptive) => adaptive,
+        None => {return (config.time_step_size, TimeStepLimit::Fixed);}
+    };
+    let mut step=(adaptive.max_step_size, TimeStepLimit::Maximum);
+    let speed: f32=[&state.velocity_x, &state.velocity_y, &state.velocity_z].iter().map(|velocity_grid| largest_speed(velocity_grid, state.time, config)).sum();
+    //The semi-Lagrangian step is stable for any CFL number
+    if speed>0.0 && !matches!(config.convection_scheme, ConvectionScheme::SemiLagrangian{..}){
+        step=smallest(step, (adaptive.cfl_number*config.grid_element_scale/speed, TimeStepLimit::Convection));
+    }
+    //Diffusion that is at least half implicit is stable for any diffusion number
+    if config.viscosity>0.0 && config.diffusion_scheme.implicit_fraction()<0.5{
+        step=smallest(step, (adaptive.diffusion_number*config.density*config.grid_element_scale*config.grid_element_scale/config.viscosity, TimeStepLimit::Diffusion));
+    }
+    if step.0<adaptive.min_step_size{
+        log::warn!(target: "solver", "The {} allows a time step of {} s, the minimum step size {} s is used", step.1.name(), step.0, adaptive.min_step_size);
+        return (adaptive.min_step_size, TimeStepLimit::Minimum);
+    }
+    return step;
+}
+
+//Stop before a time step in which the explicit convection would move the fluid more than a cell, that step would blow up the simulation.
+//The CFL number of a cell is (|u|+|v|+|w|)*dt/dx with the largest velocity on the two faces in every dimension.
+pub(crate) fn check_cfl_number(state: &SimulationState, time_step_size: f32, config: &SimulationConfig) -> Result<(), SolverError>{
+    if matches!(config.convection_scheme, ConvectionScheme::SemiLagrangian{..}){
+        return Ok(());
+    }
+    let velocities=[&state.velocity_x.grid, &state.velocity_y.grid, &state.velocity_z.grid];
+    let (pressure_grid, solid_mask)=(&state.pressure_grid, &state.solid_mask);
+    let ghost=pressure_grid.ghost();
+    let (range_y, range_z)=(pressure_grid.domain_range(1), pressure_grid.domain_range(2));
+    //The layers are compared in order, so the first of equal values wins on any number of threads
+    let layers: Vec<(f32, [usize; 3])>=pressure_grid.domain_range(0).into_par_iter().map(|x| {
+        let mut largest=(0.0f32, [x-ghost, 0, 0]);
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                if solid_mask[[x,y,z]]{
+                    continue;
+                }
+                let speed: f32=[[1, 0, 0], [0, 1, 0], [0, 0, 1]].iter().zip(velocities).map(|(dim, velocity)| velocity[[x,y,z]].abs().max(velocity[[x+dim[0],y+dim[1],z+dim[2]]].abs())).sum();
+                if speed>largest.0{
+                    largest=(speed, [x-ghost, y-ghost, z-ghost]);
+                }
+            }
+        }
+        largest
+    }).collect();
+    let (speed, cell)=layers.into_iter().fold((0.0, [0, 0, 0]), |largest, layer| if layer.0>largest.0 {layer} else {largest});
+    let cfl_number=speed*time_step_size/config.grid_element_scale;
+    if cfl_number>1.0{
+        return Err(SolverError::CflViolation{time_step: state.time_step, cfl_number, cell});
+    }
+    return Ok(());
+}
+
+fn smallest(a: (f32, TimeStepLimit), b: (f32, TimeStepLimit)) -> (f32, TimeStepLimit){
+    return if b.0<a.0 {b} else {a};
+}
+
+//The largest absolute velocity in the dimension of the grid, in the domain and on the walls.
+//The velocities that the boundaries will impose are included too, otherwise an inflow would only be noticed after the first step.
+fn largest_speed(velocity_grid: &VelocityGrid, time: f32, config: &SimulationConfig) -> f32{
+    let grid=&velocity_grid.grid;
+    let dimension=velocity_grid.dimension;
+    let (range_y, range_z)=(grid.domain_range(1), grid.domain_range(2));
+    let mut speed=grid.domain_range(0).into_par_iter().map(|x| {
+        let mut speed=0.0f32;
+        for y in range_y.clone(){
+            for z in range_z.clone(){
+                speed=speed.max(grid[[x,y,z]].abs());
+            }
+        }
+        speed
+    }).reduce(|| 0.0, f32::max);
+    for wall in config.walls.iter(){
+        if let WallCondition::Moving{velocity}=wall{
+            speed=speed.max(velocity[dimension].abs());
+        }
+    }
+    for patch in config.boundary_patches.iter().filter(|patch| patch.face.dimension==dimension && matches!(patch.kind, PatchKind::Inflow | PatchKind::Outflow)){
+        let cells=patch.cells();
+        for a in 0..cells[0]{
+            for b in 0..cells[1]{
+                speed=speed.max(patch.orthogonal_velocity([a, b], time).abs());
+            }
+        }
+    }
+    return speed;
+}
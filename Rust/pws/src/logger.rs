@@ -0,0 +1,101 @@
+//Writes the log of the simulation and the renderer to stderr, so stdout only has the summary of a batch run.
+//The report of every time step is logged with target report, "info,report=off" leaves it out.
+//The filter is a default level followed by levels for targets, e.g. "info,solver=debug,allocator=off".
+//A target level also applies to the targets below it, "solver" covers "solver::pressure".
+use log::{LevelFilter, Log, Metadata, Record};
+
+pub struct Logger{
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl Logger{
+    pub fn parse(filter: &str) -> Result<Self, String>{
+        let mut logger = Logger{default: LevelFilter::Info, targets: vec![]};
+        for part in filter.split(',').map(str::trim).filter(|part| !part.is_empty()){
+            match part.split_once('='){
+                Some((target, level)) => {
+                    logger.targets.push((target.trim().to_string(), parse_level(level.trim())?));
+                }
+                None => {logger.default = parse_level(part)?;}
+            }
+        }
+        //The longest matching target wins
+        logger.targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        return Ok(logger);
+    }
+
+    pub fn install(self){
+        let max_level = self.targets.iter().map(|(_, level)| *level).fold(self.default, Ord::max);
+        log::set_max_level(max_level);
+        log::set_boxed_logger(Box::new(self)).expect("The logger was already set");
+    }
+
+    fn level(&self, target: &str) -> LevelFilter{
+        for (prefix, level) in self.targets.iter(){
+            if target == prefix || target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.starts_with("::")){
+                return *level;
+            }
+        }
+        return self.default;
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String>{
+    return level.parse().map_err(|_| format!("Unknown log level {}, use off, error, warn, info, debug or trace", level));
+}
+
+impl Log for Logger{
+    fn enabled(&self, metadata: &Metadata) -> bool{
+        return metadata.level() <= self.level(metadata.target());
+    }
+
+    fn log(&self, record: &Record){
+        if self.enabled(record.metadata()){
+            eprintln!("[{:<5} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self){}
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn default_level_and_target_levels(){
+        let logger = Logger::parse("info,solver=debug").unwrap();
+        assert_eq!(logger.level("simulation"), LevelFilter::Info);
+        assert_eq!(logger.level("solver"), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn target_level_covers_the_targets_below_it(){
+        let logger = Logger::parse("warn,solver=trace").unwrap();
+        assert_eq!(logger.level("solver::pressure"), LevelFilter::Trace);
+        assert_eq!(logger.level("solvers"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn longest_matching_target_wins(){
+        let logger = Logger::parse("solver=debug,solver::pressure=error").unwrap();
+        assert_eq!(logger.level("solver::pressure"), LevelFilter::Error);
+        assert_eq!(logger.level("solver::diffusion"), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn unknown_level_is_rejected(){
+        assert!(Logger::parse("loud").is_err());
+        assert!(Logger::parse("info,solver=loud").err().is_some_and(|error| error.contains("loud")));
+    }
+
+    #[test]
+    fn report_off_leaves_out_the_reports(){
+        let logger = Logger::parse("info,report=off").unwrap();
+        let report = Metadata::builder().level(log::Level::Info).target("report").build();
+        let simulation = Metadata::builder().level(log::Level::Info).target("simulation").build();
+        assert!(!logger.enabled(&report));
+        assert!(logger.enabled(&simulation));
+    }
+}
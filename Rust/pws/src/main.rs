@@ -1,6 +1,199 @@
+mod logger;
+
+use std::{cell::Cell, path::{Path, PathBuf}};
+
+use finite_difference::{SimulationConfig, SimulationState, SolverError, StepReport, TimeStepLimit, checkpoint, scenario};
+use logger::Logger;
+
+const USAGE: &str = "Usage: rust [--scenario <file>] [--batch <time steps>] [--threads <count>] [--log <filter>]
+             [--checkpoint <file>] [--checkpoint-every <time steps>] [--restart <file>]
+    --scenario <file>       Read the simulation setup from a TOML scenario file
+    --batch <time steps>    Run the given number of time steps without a window
+    --threads <count>       The number of threads of the simulation, 0 for one per core
+    --log <filter>          The log levels on stderr, a default level and levels per target, e.g. info,solver=debug
+                            The targets are report, solver, boundary, renderer and allocator, the levels off, error, warn, info, debug and trace
+    --checkpoint <file>     Write the state of the simulation to a checkpoint file, periodically and after the last batch time step
+    --checkpoint-every <time steps>
+                            How often the checkpoint is written, 100 time steps by default
+    --restart <file>        Continue from a checkpoint, the scenario has to be the one the checkpoint was written with";
+
 fn main() {
-    finite_difference::initialize_simulation();
+    let mut config = SimulationConfig::default();
+    let mut batch_steps: Option<usize> = None;
+    let mut threads: Option<usize> = None;
+    let mut log_filter = String::from("info");
+    let mut checkpoint: Option<Checkpoint> = None;
+    let mut checkpoint_every: usize = 100;
+    let mut restart: Option<PathBuf> = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "--scenario" => {
+                let path: PathBuf = parse_value(&arg, args.next());
+                config = match scenario::load_scenario(&path){
+                    Ok(config) => config,
+                    Err(error) => {
+                        eprintln!("Invalid scenario {}: {}", path.display(), error);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--batch" => {
+                batch_steps = Some(parse_value(&arg, args.next()));
+            }
+            "--threads" => {
+                threads = Some(parse_value(&arg, args.next()));
+            }
+            "--log" => {
+                log_filter = parse_value(&arg, args.next());
+            }
+            "--checkpoint" => {
+                checkpoint = Some(Checkpoint{path: parse_value(&arg, args.next()), every: 0, last_written: Cell::new(None)});
+            }
+            "--checkpoint-every" => {
+                checkpoint_every = parse_value(&arg, args.next());
+                if checkpoint_every == 0{
+                    exit_with_usage("--checkpoint-every needs at least 1 time step");
+                }
+            }
+            "--restart" => {
+                restart = Some(parse_value(&arg, args.next()));
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => {exit_with_usage(&format!("Unknown argument {}", arg));}
+        }
+    }
+    match Logger::parse(&log_filter){
+        Ok(logger) => {logger.install();}
+        Err(message) => {exit_with_usage(&message);}
+    }
+    //The command line overrides the scenario, also when --threads comes before --scenario
+    if let Some(threads) = threads{
+        config.threads = threads;
+    }
+    if let Some(checkpoint) = checkpoint.as_mut(){
+        checkpoint.every = checkpoint_every;
+    }
+    let state = match &restart{
+        Some(path) => match checkpoint::read_checkpoint(path, &config){
+            Ok(state) => {
+                log::info!("Restarting from {} at time step {}, {} s", path.display(), state.time_step, state.time);
+                state
+            }
+            Err(error) => {
+                eprintln!("Invalid checkpoint {}: {}", path.display(), error);
+                std::process::exit(1);
+            }
+        },
+        None => match SimulationState::new(&config){
+            Ok(state) => state,
+            Err(error) => {exit_with_error(&error)}
+        },
+    };
+    match batch_steps{
+        Some(steps) => {run_batch(state, &config, steps, checkpoint.as_ref());}
+        None => {run_window(state, &config, checkpoint.as_ref());}
+    }
+}
+
+//Where and how often the checkpoints are written
+struct Checkpoint{
+    path: PathBuf,
+    every: usize,
+    last_written: Cell<Option<i32>>,//The time step of the checkpoint in the file
+}
+
+impl Checkpoint{
+    fn write_periodically(&self, state: &SimulationState, config: &SimulationConfig){
+        if (state.time_step as usize).is_multiple_of(self.every){
+            self.write(state, config);
+        }
+    }
+
+    //Write the checkpoint unless the file already has this time step
+    fn write(&self, state: &SimulationState, config: &SimulationConfig){
+        if self.last_written.get() == Some(state.time_step){
+            return;
+        }
+        match checkpoint::write_checkpoint(state, config, &self.path){
+            Ok(()) => {
+                self.last_written.set(Some(state.time_step));
+                log::info!("Wrote checkpoint {} at time step {}", self.path.display(), state.time_step);
+            }
+            Err(error) => {exit_with_checkpoint_error(&self.path, &error);}
+        }
+    }
+}
+
+fn run_batch(state: SimulationState, config: &SimulationConfig, steps: usize, checkpoint: Option<&Checkpoint>){
+    let state = match finite_difference::resume_headless(state, config, steps, |state, report| {
+        log_report(report);
+        if let Some(checkpoint) = checkpoint{
+            checkpoint.write_periodically(state, config);
+        }
+        false
+    }){
+        Ok(state) => state,
+        Err(error) => {exit_with_error(&error)}
+    };
+    //When the last time step was a periodic one the checkpoint is already written
+    if let Some(checkpoint) = checkpoint{
+        checkpoint.write(&state, config);
+    }
+    println!("Simulated {} time steps, {} s", state.time_step, state.time);
 }
 
+fn log_report(report: &StepReport){
+    let limit = match report.time_step_limit{
+        TimeStepLimit::Fixed => String::new(),
+        limit => format!(" limited by the {}", limit.name()),
+    };
+    log::info!(target: "report", "Time step {}: {} s{}, {} iterations, divergence {} 1/s (L2 norm {}, root mean square {}), kinetic energy {} J, {:.1} ms",
+        report.time_step, report.time_step_size, limit, report.iterations, report.divergence.max, report.divergence.l2, report.divergence.root_mean_square, report.kinetic_energy, report.phase_times.total.as_secs_f64()*1000.0);
+}
+
+#[cfg(feature = "renderer")]
+fn run_window(state: SimulationState, config: &SimulationConfig, checkpoint: Option<&Checkpoint>){
+    let result = finite_difference::resume_simulation(state, config, |state, _| {
+        if let Some(checkpoint) = checkpoint{
+            checkpoint.write_periodically(state, config);
+        }
+    });
+    if let Err(error) = result{
+        exit_with_error(&error);
+    }
+}
 
+#[cfg(not(feature = "renderer"))]
+fn run_window(_state: SimulationState, _config: &SimulationConfig, _checkpoint: Option<&Checkpoint>){
+    exit_with_usage("This build has no renderer, use --batch");
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> T{
+    let value = match value{
+        Some(value) => value,
+        None => {exit_with_usage(&format!("{} needs a value", arg))}
+    };
+    return match value.parse(){
+        Ok(value) => value,
+        Err(_) => {exit_with_usage(&format!("Invalid value {} for {}", value, arg))}
+    };
+}
 
+fn exit_with_error(error: &SolverError) -> !{
+    eprintln!("Simulation failed: {}", error);
+    std::process::exit(1);
+}
+
+fn exit_with_checkpoint_error(path: &Path, error: &checkpoint::CheckpointError) -> !{
+    eprintln!("Failed to write checkpoint {}: {}", path.display(), error);
+    std::process::exit(1);
+}
+
+fn exit_with_usage(message: &str) -> !{
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(1);
+}
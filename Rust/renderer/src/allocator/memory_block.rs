@@ -35,7 +35,7 @@ impl MemoryBlock{
                 return i as u32;
             }
         }
-        println!("{:?},\n\r{:?},\n\r{}",physical_device_memory_properties,memory_property_flags,memory_type_filter);
+        log::error!(target: "allocator", "No memory type with {:?} in filter {} among {:?}",memory_property_flags,memory_type_filter,physical_device_memory_properties);
         panic!("Requested unsupported memory type");
     }
     pub fn is_block_compatible(&self, physical_device_memory_properties : PhysicalDeviceMemoryProperties, memory_type_filter : u32, memory_property_flags : MemoryPropertyFlags) -> bool{
@@ -64,18 +64,18 @@ impl Allocator{
     pub fn dump_contents(&self){
         for (i, block) in self.blocks.iter().enumerate(){
             if block.is_some(){
-                println!("[{}]Block:",i);
+                log::debug!(target: "allocator", "[{}]Block:",i);
                 for (i,region) in block.as_ref().unwrap().regions.iter().enumerate(){
                     if region.is_some(){
-                        println!("  [{i}]Region, Offset: {}, Size: {}",region.as_ref().unwrap().offset, region.as_ref().unwrap().size);
+                        log::debug!(target: "allocator", "  [{i}]Region, Offset: {}, Size: {}",region.as_ref().unwrap().offset, region.as_ref().unwrap().size);
                     }
                     else{
-                        println!("  [{}]Region, Non-existant",i);
+                        log::debug!(target: "allocator", "  [{}]Region, Non-existant",i);
                     }
                 }
             }
             else{
-                println!("[{}]Block, Non-existant",i);
+                log::debug!(target: "allocator", "[{}]Block, Non-existant",i);
             }
         }
     }
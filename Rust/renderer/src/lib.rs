@@ -33,7 +33,7 @@ impl Renderer{
         let (shutdown_sender, receiver_shutdown) = std::sync::mpsc::channel();
         //Start the renderer on another thread
         thread_pool.spawn(move ||{
-            println!("Created render thread");
+            log::info!(target: "renderer", "Created render thread");
             let mut event_loop : EventLoop<()> = EventLoop::new_any_thread();
             let window = Window::new(&event_loop).expect("Failed to create render window");
             let mut renderer = RenderOnThread::new(&window, debug);
@@ -143,7 +143,7 @@ impl Renderer{
                 }
             });
             drop(renderer);
-            println!("Destroying render thread");
+            log::info!(target: "renderer", "Destroying render thread");
             shutdown_sender.send(RenderResult::Shutdown).unwrap();
         });
         return Self{
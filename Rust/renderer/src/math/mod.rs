@@ -77,7 +77,7 @@ impl InstanceVertex{
     }
     pub fn get_initial_vertex_data() -> Vec<(Vec<Self>,Vec<u32>)>{
         let arrow_file = format!("{}/arrow.obj",std::env::current_exe().unwrap().parent().unwrap().to_string_lossy());
-        println!("{}",arrow_file);
+        log::debug!(target: "renderer", "Loading the arrow model from {}",arrow_file);
         let obj = tobj::load_obj(arrow_file, &LoadOptions{triangulate:true,..Default::default()}).unwrap().0;
         let mut data = vec!();
         for model in obj{